@@ -2,7 +2,7 @@
 use crate::{
     logging::{LogAndHandleErrorMiddleware, LoggingCacheManager},
     paths::config_file,
-    responses::AccountAndAuth,
+    responses::{AccountAndAuth, AppAndAuth},
     settings::Settings,
     ucan::{encode_ucan_header, find_delegation_chain},
 };
@@ -18,9 +18,12 @@ use fission_core::{
         did::Did,
         fission::{FissionAbility, FissionPlugin},
         indexing::IndexingAbility,
+        volume::{VolumeAbility, VolumeResource},
     },
     common::{
-        Account, AccountCreationRequest, AccountLinkRequest, EmailVerifyRequest, UcansResponse,
+        Account, AccountCreationRequest, AccountLinkRequest, App, AppsResponse, Device,
+        DevicesResponse, EmailVerifyRequest, UcansResponse, UsernameAvailableResponse,
+        VerificationMode,
     },
     dns,
     ed_did_key::EdDidKey,
@@ -40,7 +43,7 @@ use rs_ucan::{
     builder::UcanBuilder,
     capability::Capability,
     plugins::Plugin,
-    semantics::{ability::Ability, caveat::EmptyCaveat},
+    semantics::{ability::Ability, caveat::EmptyCaveat, resource::Resource},
     ucan::Ucan,
 };
 use std::{
@@ -83,6 +86,8 @@ pub enum Commands {
     Account(AccountCmds),
     /// Commands related to managing the account-associated storage
     Volume(VolumeCmds),
+    /// Create, publish and delete apps
+    App(AppCmds),
     /// Print file paths used by the application (e.g. the path to config)
     Paths,
 }
@@ -107,6 +112,8 @@ pub enum AccountCommands {
     Delete(DeleteCommand),
     /// Issue UCANs for given ability
     Issue(IssueAbility),
+    /// List or revoke the devices that have access to one of your accounts
+    Devices(DevicesCmds),
 }
 
 #[derive(Debug, Parser)]
@@ -133,6 +140,38 @@ pub struct IssueAbility {
     lifetime: Option<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct DevicesCmds {
+    #[command(subcommand)]
+    command: DevicesCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DevicesCommands {
+    /// List the devices that have access to one of your accounts
+    List(DevicesListCommand),
+    /// Revoke a device's access to one of your accounts and its apps
+    Revoke(DevicesRevokeCommand),
+}
+
+#[derive(Debug, Parser)]
+pub struct DevicesListCommand {
+    /// Username of the account to list devices for.
+    /// If not provided, it's assumed you only have access to one account.
+    #[arg(long, short = 'u')]
+    username: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevicesRevokeCommand {
+    /// The DID of the device to revoke, as shown by "fission-cli account devices list"
+    did: String,
+    /// Username of the account to revoke the device from.
+    /// If not provided, it's assumed you only have access to one account.
+    #[arg(long, short = 'u')]
+    username: Option<String>,
+}
+
 #[derive(Debug, Parser)]
 pub struct VolumeCmds {
     #[command(subcommand)]
@@ -166,6 +205,62 @@ pub struct DownloadCommand {
     output: PathBuf,
 }
 
+#[derive(Debug, Parser)]
+pub struct AppCmds {
+    #[command(subcommand)]
+    command: AppCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AppCommands {
+    /// Create a new app with an automatically generated subdomain
+    Create(AppCreateCommand),
+    /// List the apps of one of your accounts
+    List(AppListCommand),
+    /// Publish a directory or file to an app
+    Publish(AppPublishCommand),
+    /// Delete one of your apps
+    Delete(AppDeleteCommand),
+}
+
+#[derive(Debug, Parser)]
+pub struct AppCreateCommand {
+    /// Username of the account that should own the app.
+    /// If not provided, it's assumed you only have access to one account.
+    #[arg(long, short = 'u')]
+    username: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct AppListCommand {
+    /// Username of the account to list apps for.
+    /// If not provided, it's assumed you only have access to one account.
+    #[arg(long, short = 'u')]
+    username: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct AppPublishCommand {
+    /// Name of the app to publish to, e.g. "friendly-pink-dragon"
+    name: String,
+    /// Path to the directory to publish
+    path: PathBuf,
+    /// Username of the account that owns the app.
+    /// If not provided, it's assumed you only have access to one account.
+    #[arg(long, short = 'u')]
+    username: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct AppDeleteCommand {
+    /// Name of the app to delete, e.g. "friendly-pink-dragon"
+    name: String,
+    /// Username of the account that owns the app.
+    /// If not provided, it's assumed you only have access to one account.
+    #[arg(long, short = 'u')]
+    username: Option<String>,
+}
+
 impl Cli {
     pub async fn run(&self, mut settings: Settings) -> Result<()> {
         let ansi = !self.no_colors;
@@ -267,7 +362,7 @@ impl Cli {
                             .delete_account(Did(auth.account.did.to_string()), &auth.ucans)
                             .await?;
 
-                        println!("Successfully deleted your account.");
+                        println!("Successfully deleted your account. It can be restored via email verification within the grace period.");
                     }
                     AccountCommands::Issue(ability) => {
                         let lifetime = match ability.lifetime.as_deref() {
@@ -310,6 +405,61 @@ impl Cli {
                                 .join(",")
                         );
                     }
+                    AccountCommands::Devices(devices) => match &devices.command {
+                        DevicesCommands::List(list) => {
+                            let accounts = state.find_accounts(state.find_capabilities()?).await;
+
+                            let auth = state.pick_account(
+                                accounts,
+                                &list.username,
+                                "Which account do you want to list devices for?",
+                            )?;
+
+                            let devices = state
+                                .list_devices(Did(auth.account.did.to_string()), &auth.ucans)
+                                .await?;
+
+                            for device in devices {
+                                if device.did == state.key.did_as_str() {
+                                    println!("{} (this device)", device.did);
+                                } else {
+                                    println!("{}", device.did);
+                                }
+                            }
+                        }
+                        DevicesCommands::Revoke(revoke) => {
+                            let accounts = state.find_accounts(state.find_capabilities()?).await;
+
+                            let auth = state.pick_account(
+                                accounts,
+                                &revoke.username,
+                                "Which account do you want to revoke the device from?",
+                            )?;
+
+                            if revoke.did == state.key.did_as_str() {
+                                let confirmed = inquire::Confirm::new(
+                                    "This is the DID of this device. Are you sure you want to revoke your own access?",
+                                )
+                                .with_default(false)
+                                .with_render_config(state.render_config)
+                                .prompt()?;
+
+                                if !confirmed {
+                                    return Ok(());
+                                }
+                            }
+
+                            state
+                                .revoke_device(
+                                    Did(auth.account.did.to_string()),
+                                    &auth.ucans,
+                                    &revoke.did,
+                                )
+                                .await?;
+
+                            println!("Successfully revoked device {}", revoke.did);
+                        }
+                    },
                 }
             }
             Commands::Volume(volume) => {
@@ -330,29 +480,7 @@ impl Cli {
                         let store = &MemoryBlockStore::new();
                         let cache = &InMemoryCache::new(100_000);
 
-                        let source = &publish.path;
-                        if !source.exists() {
-                            bail!("Can't publish path, it doesn't exist: {}", source.display());
-                        }
-
-                        let cid = if source.is_file() {
-                            let filename = source
-                                .file_name()
-                                .expect("If the source is a file, it should have a filename")
-                                .to_string_lossy()
-                                .to_string();
-                            let mut directory = PublicDirectory::new_rc(Utc::now());
-                            directory
-                                .write(&[filename], std::fs::read(source)?, Utc::now(), store)
-                                .await?;
-                            directory.store(store).await?
-                        } else if source.is_dir() {
-                            let mut directory = PublicDirectory::new_rc(Utc::now());
-                            import_dir_from(source, &mut directory, store).await?;
-                            directory.store(store).await?
-                        } else {
-                            bail!("Unsupported file type (not a file or directory)");
-                        };
+                        let cid = import_volume(&publish.path, store).await?;
 
                         state
                             .volume_put(did, &auth.ucans, cid, store, cache)
@@ -400,6 +528,110 @@ impl Cli {
                     }
                 }
             }
+            Commands::App(app) => {
+                let mut state = CliState::load(&settings, self.key_seed.clone(), ansi).await?;
+                state.fetch_ucans().await?;
+
+                match &app.command {
+                    AppCommands::Create(create) => {
+                        let accounts = state.find_accounts(state.find_capabilities()?).await;
+
+                        let auth = state.pick_account(
+                            accounts,
+                            &create.username,
+                            "Which account should own the app?",
+                        )?;
+
+                        let app = state
+                            .create_app(Did(auth.account.did.to_string()), &auth.ucans)
+                            .await?;
+
+                        println!("Successfully created app {}", app.name);
+                    }
+                    AppCommands::List(list) => {
+                        let accounts = state.find_accounts(state.find_capabilities()?).await;
+
+                        let auth = state.pick_account(
+                            accounts,
+                            &list.username,
+                            "Which account do you want to list apps for?",
+                        )?;
+
+                        let apps = state
+                            .list_apps(Did(auth.account.did.to_string()), &auth.ucans)
+                            .await?;
+
+                        if apps.is_empty() {
+                            println!("This account doesn't have any apps yet. Use \"fission-cli app create\" to create a new one.");
+                        } else {
+                            for app in apps {
+                                match app.cid {
+                                    Some(cid) => println!("{} (published {cid})", app.name),
+                                    None => println!("{} (unpublished)", app.name),
+                                }
+                            }
+                        }
+                    }
+                    AppCommands::Publish(publish) => {
+                        let accounts = state.find_accounts(state.find_capabilities()?).await;
+
+                        let auth = state.pick_account(
+                            accounts,
+                            &publish.username,
+                            "Which account owns the app?",
+                        )?;
+
+                        let app = state.find_app(&auth, &publish.name).await?;
+                        let app_did = Did(app.did);
+
+                        let Some(chain) = find_delegation_chain(
+                            &app_did,
+                            &VolumeAbility::Update,
+                            state.key.did_as_str(),
+                            &state.ucans,
+                        ) else {
+                            bail!(
+                                "This device doesn't have access to publish app {}",
+                                app.name
+                            );
+                        };
+
+                        let store = &MemoryBlockStore::new();
+                        let cache = &InMemoryCache::new(100_000);
+
+                        let cid = import_volume(&publish.path, store).await?;
+
+                        state
+                            .car_mirror_put(
+                                "/api/v0/apps/volume/push",
+                                app_did,
+                                &chain,
+                                cid,
+                                store,
+                                cache,
+                            )
+                            .await?;
+                        println!("Successfully published {}", app.name);
+                    }
+                    AppCommands::Delete(delete) => {
+                        let accounts = state.find_accounts(state.find_capabilities()?).await;
+
+                        let auth = state.pick_account(
+                            accounts,
+                            &delete.username,
+                            "Which account owns the app?",
+                        )?;
+
+                        let app = state.find_app(&auth, &delete.name).await?;
+
+                        state
+                            .delete_app(Did(auth.account.did.to_string()), &auth.ucans, &app.did)
+                            .await?;
+
+                        println!("Successfully deleted app {}", app.name);
+                    }
+                }
+            }
             Commands::Paths => {
                 println!(
                     "{}",
@@ -505,6 +737,11 @@ impl<'s> CliState<'s> {
         self.server_request(Method::POST, "/api/v0/auth/email/verify")?
             .json(&EmailVerifyRequest {
                 email: email.clone(),
+                mode: VerificationMode::Code,
+                relay_topic: None,
+                purpose: None,
+                did: None,
+                locale: None,
             })
             .send()
             .await?;
@@ -524,11 +761,28 @@ impl<'s> CliState<'s> {
 
         let (ucan, proofs) = self.issue_ucan(self.device_did(), FissionAbility::AccountCreate)?;
 
-        // TODO Check for availablility. Allow retries.
-        let username = inquire::Text::new("Choose a username:")
-            .with_render_config(self.render_config)
-            .prompt()?;
-        let username = Username::from_unicode(&username)?;
+        let username = loop {
+            let username = inquire::Text::new("Choose a username:")
+                .with_render_config(self.render_config)
+                .prompt()?;
+
+            let username = match Username::from_unicode(&username) {
+                Ok(username) => username,
+                Err(e) => {
+                    println!("That's not a valid username: {e}");
+                    continue;
+                }
+            };
+
+            if self.is_username_available(&username).await? {
+                break username;
+            }
+
+            println!(
+                "The username {} is not available. Please choose another one.",
+                username.to_unicode()
+            );
+        };
 
         let response: AccountAndAuth = self
             .server_request(Method::POST, "/api/v0/account")?
@@ -539,6 +793,7 @@ impl<'s> CliState<'s> {
                 email,
                 username,
                 code: code.trim().to_string(),
+                credential_id: None,
             })
             .send()
             .await?
@@ -550,6 +805,20 @@ impl<'s> CliState<'s> {
         Ok(response.account)
     }
 
+    async fn is_username_available(&self, username: &Username) -> Result<bool> {
+        let response: UsernameAvailableResponse = self
+            .server_request(
+                Method::GET,
+                &format!("/api/v0/account/username/{username}/available"),
+            )?
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(response.available)
+    }
+
     async fn link_account(&mut self) -> Result<Account> {
         let username = inquire::Text::new("What's your username?")
             .with_render_config(self.render_config)
@@ -753,6 +1022,38 @@ impl<'s> CliState<'s> {
         Ok(())
     }
 
+    async fn list_devices(&self, did: Did, chain: &[Ucan]) -> Result<Vec<Device>> {
+        let ucan = self.issue_ucan_with(did, FissionAbility::AccountInfo, None, chain)?;
+
+        let response: DevicesResponse = self
+            .server_request(Method::GET, "/api/v0/account/devices")?
+            .bearer_auth(ucan.encode()?)
+            .header("ucans", encode_ucan_header(chain)?)
+            .header(CACHE_CONTROL, "no-cache")
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(response.devices)
+    }
+
+    async fn revoke_device(&self, did: Did, chain: &[Ucan], device_did: &str) -> Result<()> {
+        let ucan = self.issue_ucan_with(did, FissionAbility::AccountManage, None, chain)?;
+
+        self.server_request(
+            Method::DELETE,
+            &format!("/api/v0/account/devices/{device_did}"),
+        )?
+        .bearer_auth(ucan.encode()?)
+        .header("ucans", encode_ucan_header(chain)?)
+        .send()
+        .await?
+        .error_for_status()?;
+
+        Ok(())
+    }
+
     async fn volume_put(
         &self,
         did: Did,
@@ -760,6 +1061,19 @@ impl<'s> CliState<'s> {
         cid: Cid,
         store: &MemoryBlockStore,
         cache: &InMemoryCache,
+    ) -> Result<()> {
+        self.car_mirror_put("/api/v0/volume/push", did, chain, cid, store, cache)
+            .await
+    }
+
+    async fn car_mirror_put(
+        &self,
+        path_prefix: &str,
+        did: Did,
+        chain: &[Ucan],
+        cid: Cid,
+        store: &MemoryBlockStore,
+        cache: &InMemoryCache,
     ) -> Result<()> {
         // We use a custom client, because we need to skip caching requests,
         // as our caching middleware from http-cache will fail on reqwest body streams.
@@ -768,13 +1082,17 @@ impl<'s> CliState<'s> {
             .build();
 
         let mut upload_url = self.settings.api_endpoint.clone();
-        upload_url.set_path(&format!("/api/v0/volume/push/{cid}"));
+        upload_url.set_path(&format!("{path_prefix}/{cid}"));
 
         // we use `push_with`, instead of `send_car_mirror_push`, because this way we can
         // re-issue a fresh UCAN token for each request and avoid having auth tokens expire.
         car_mirror_reqwest::push_with(cid, store, cache, |body| async {
-            let ucan =
-                self.issue_ucan_with(did.clone(), FissionAbility::AccountManage, None, chain)?;
+            let ucan = self.issue_ucan_with(
+                VolumeResource::new(did.as_ref()),
+                VolumeAbility::Update,
+                None,
+                chain,
+            )?;
 
             Ok::<_, anyhow::Error>(
                 client
@@ -791,6 +1109,57 @@ impl<'s> CliState<'s> {
         Ok(())
     }
 
+    async fn create_app(&self, did: Did, chain: &[Ucan]) -> Result<App> {
+        let ucan = self.issue_ucan_with(did, FissionAbility::AccountManage, None, chain)?;
+
+        let response: AppAndAuth = self
+            .server_request(Method::POST, "/api/v0/apps")?
+            .bearer_auth(ucan.encode()?)
+            .header("ucans", encode_ucan_header(chain)?)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(response.app)
+    }
+
+    async fn list_apps(&self, did: Did, chain: &[Ucan]) -> Result<Vec<App>> {
+        let ucan = self.issue_ucan_with(did, FissionAbility::AccountInfo, None, chain)?;
+
+        let response: AppsResponse = self
+            .server_request(Method::GET, "/api/v0/apps")?
+            .bearer_auth(ucan.encode()?)
+            .header("ucans", encode_ucan_header(chain)?)
+            .header(CACHE_CONTROL, "no-cache")
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(response.apps)
+    }
+
+    async fn find_app(&self, auth: &AccountAndAuth, name: &str) -> Result<App> {
+        self.list_apps(Did(auth.account.did.to_string()), &auth.ucans)
+            .await?
+            .into_iter()
+            .find(|app| app.name == name)
+            .ok_or_else(|| anyhow!("Couldn't find an app named {name} on this account."))
+    }
+
+    async fn delete_app(&self, did: Did, chain: &[Ucan], app_did: &str) -> Result<()> {
+        let ucan = self.issue_ucan_with(did, FissionAbility::AccountManage, None, chain)?;
+
+        self.server_request(Method::DELETE, &format!("/api/v0/apps/{app_did}"))?
+            .bearer_auth(ucan.encode()?)
+            .header("ucans", encode_ucan_header(chain)?)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
     async fn volume_get(
         &self,
         cid: Cid,
@@ -838,14 +1207,14 @@ impl<'s> CliState<'s> {
 
     fn issue_ucan_with(
         &self,
-        subject_did: Did,
+        subject: impl Resource,
         ability: impl Ability,
         lifetime: Option<Option<u64>>,
         chain: &[Ucan],
     ) -> Result<Ucan> {
         let mut builder = UcanBuilder::default()
             .for_audience(&self.server_did)
-            .claiming_capability(Capability::new(subject_did, ability, EmptyCaveat));
+            .claiming_capability(Capability::new(subject, ability, EmptyCaveat));
 
         if let Some(lifetime) = lifetime.unwrap_or(Some(360)) {
             builder = builder.with_lifetime(lifetime);
@@ -934,6 +1303,31 @@ fn setup_tracing(ansi: bool) {
         .init();
 }
 
+async fn import_volume(source: &Path, store: &impl BlockStore) -> Result<Cid> {
+    if !source.exists() {
+        bail!("Can't publish path, it doesn't exist: {}", source.display());
+    }
+
+    let mut directory = PublicDirectory::new_rc(Utc::now());
+
+    if source.is_file() {
+        let filename = source
+            .file_name()
+            .expect("If the source is a file, it should have a filename")
+            .to_string_lossy()
+            .to_string();
+        directory
+            .write(&[filename], std::fs::read(source)?, Utc::now(), store)
+            .await?;
+    } else if source.is_dir() {
+        import_dir_from(source, &mut directory, store).await?;
+    } else {
+        bail!("Unsupported file type (not a file or directory)");
+    };
+
+    Ok(directory.store(store).await?)
+}
+
 #[async_recursion::async_recursion]
 async fn export_dir_to(
     path: PathBuf,
@@ -970,7 +1364,7 @@ async fn export_dir_to(
 }
 
 async fn import_dir_from(
-    path: &PathBuf,
+    path: &Path,
     directory: &mut Arc<PublicDirectory>,
     store: &impl BlockStore,
 ) -> Result<()> {
@@ -1,4 +1,4 @@
-use fission_core::common::Account;
+use fission_core::common::{Account, App};
 use rs_ucan::ucan::Ucan;
 use serde::{Deserialize, Serialize};
 
@@ -7,3 +7,9 @@ pub struct AccountAndAuth {
     pub account: Account,
     pub ucans: Vec<Ucan>,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AppAndAuth {
+    pub app: App,
+    pub ucans: Vec<Ucan>,
+}
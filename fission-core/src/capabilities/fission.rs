@@ -4,9 +4,13 @@ use super::did::Did;
 use anyhow::Result;
 use rs_ucan::{
     plugins::Plugin,
-    semantics::{ability::Ability, caveat::EmptyCaveat},
+    semantics::{
+        ability::Ability,
+        caveat::{Caveat, EmptyCaveat},
+    },
 };
-use std::fmt::Display;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fmt::Display};
 
 /// An rs-ucan plugin for handling fission server capabilities
 #[derive(Debug)]
@@ -31,6 +35,38 @@ pub enum FissionAbility {
     AccountDelete,
 }
 
+/// Caveats for fission account capabilities.
+///
+/// The empty caveat `{}` doesn't restrict the capability at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FissionCaveat {
+    /// Only allow using the capability for these operations.
+    /// If unset, all operations are allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operations: Option<BTreeSet<AccountOperation>>,
+}
+
+/// The kinds of changes `account/manage` capabilities can be restricted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccountOperation {
+    /// `username`, changing the account's username
+    Username,
+    /// `handle`, setting or removing the account's custom handle
+    Handle,
+    /// `email`, adding, changing or removing email addresses
+    Email,
+    /// `devices`, revoking devices' access to the account
+    Devices,
+    /// `recovery-code`, creating recovery codes
+    RecoveryCode,
+    /// `apps`, creating and deleting apps
+    Apps,
+    /// `volume-settings`, changing volume settings like whether a volume is private
+    VolumeSettings,
+}
+
 const ACCOUNT_READ: &str = "account/info";
 const ACCOUNT_CREATE: &str = "account/create";
 const ACCOUNT_LINK: &str = "account/link";
@@ -41,7 +77,7 @@ const ACCOUNT_DELETE: &str = "account/delete";
 impl Plugin for FissionPlugin {
     type Resource = Did;
     type Ability = FissionAbility;
-    type Caveat = EmptyCaveat;
+    type Caveat = FissionCaveat;
 
     type Error = anyhow::Error;
 
@@ -105,6 +141,59 @@ impl Ability for FissionAbility {
     }
 }
 
+impl FissionCaveat {
+    /// A caveat that only allows given operations
+    pub fn only(operations: impl IntoIterator<Item = AccountOperation>) -> Self {
+        Self {
+            operations: Some(operations.into_iter().collect()),
+        }
+    }
+
+    /// Whether this caveat doesn't restrict anything
+    pub fn is_unrestricted(&self) -> bool {
+        self.operations.is_none()
+    }
+
+    /// Whether this caveat allows using the capability for given operation
+    pub fn allows(&self, operation: AccountOperation) -> bool {
+        self.operations
+            .as_ref()
+            .map_or(true, |operations| operations.contains(&operation))
+    }
+}
+
+impl Caveat for FissionCaveat {
+    fn is_valid_attenuation(&self, other: &dyn Caveat) -> bool {
+        if other.downcast_ref::<EmptyCaveat>().is_some() {
+            return true;
+        }
+
+        let Some(other) = other.downcast_ref::<Self>() else {
+            return false;
+        };
+
+        match (&self.operations, &other.operations) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(ours), Some(theirs)) => ours.is_subset(theirs),
+        }
+    }
+}
+
+impl Display for AccountOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Username => "username",
+            Self::Handle => "handle",
+            Self::Email => "email",
+            Self::Devices => "devices",
+            Self::RecoveryCode => "recovery-code",
+            Self::Apps => "apps",
+            Self::VolumeSettings => "volume-settings",
+        })
+    }
+}
+
 impl Display for FissionAbility {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
@@ -186,6 +275,40 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_caveat_attenuation() {
+        let unrestricted = FissionCaveat::default();
+        let rename = FissionCaveat::only([AccountOperation::Username, AccountOperation::Handle]);
+        let username = FissionCaveat::only([AccountOperation::Username]);
+
+        assert!(username.is_valid_attenuation(&rename));
+        assert!(username.is_valid_attenuation(&unrestricted));
+        assert!(rename.is_valid_attenuation(&EmptyCaveat));
+        assert!(!rename.is_valid_attenuation(&username));
+        assert!(!unrestricted.is_valid_attenuation(&rename));
+
+        assert!(username.allows(AccountOperation::Username));
+        assert!(!username.allows(AccountOperation::Email));
+        assert!(unrestricted.allows(AccountOperation::Email));
+    }
+
+    #[test]
+    fn test_caveat_serialization() -> TestResult {
+        let unrestricted: FissionCaveat = serde_json::from_str("{}")?;
+        assert!(unrestricted.is_unrestricted());
+
+        let caveat: FissionCaveat =
+            serde_json::from_str(r#"{ "operations": ["username", "recovery-code"] }"#)?;
+        assert_eq!(
+            caveat,
+            FissionCaveat::only([AccountOperation::Username, AccountOperation::RecoveryCode])
+        );
+
+        assert!(serde_json::from_str::<FissionCaveat>(r#"{ "maxSize": 10 }"#).is_err());
+
+        Ok(())
+    }
+
     #[ignore = "waiting for rs_ucan updates to support ucan:<did> scheme"]
     #[test]
     fn test_broken_ucan() -> Result<()> {
@@ -2,10 +2,16 @@
 
 use std::fmt::Display;
 
+use super::did::Did;
 use rs_ucan::{
     plugins::{ucan::UcanResource, Plugin},
-    semantics::{ability::Ability, caveat::EmptyCaveat, resource::Resource},
+    semantics::{
+        ability::{Ability, TopAbility},
+        caveat::{Caveat, EmptyCaveat},
+        resource::Resource,
+    },
 };
+use serde::{Deserialize, Serialize};
 
 /// An rs-ucan plugin for volume capabilities
 #[derive(Debug)]
@@ -22,6 +28,20 @@ pub struct VolumeResource {
 pub enum VolumeAbility {
     /// The ability to update the volume CID
     Update,
+    /// The ability to pull a private volume
+    Read,
+}
+
+/// Caveats for volume capabilities.
+///
+/// The empty caveat `{}` doesn't restrict the capability at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VolumeCaveat {
+    /// The maximum number of bytes a single push may upload,
+    /// counted across all requests of the push.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
 }
 
 rs_ucan::register_plugin!(VOLUME, &VolumePlugin);
@@ -29,7 +49,7 @@ rs_ucan::register_plugin!(VOLUME, &VolumePlugin);
 impl Plugin for VolumePlugin {
     type Resource = VolumeResource;
     type Ability = VolumeAbility;
-    type Caveat = EmptyCaveat;
+    type Caveat = VolumeCaveat;
     type Error = anyhow::Error;
 
     fn scheme(&self) -> &'static str {
@@ -58,6 +78,7 @@ impl Plugin for VolumePlugin {
     ) -> Result<Option<Self::Ability>, Self::Error> {
         Ok(match ability {
             "volume/update" => Some(VolumeAbility::Update),
+            "volume/read" => Some(VolumeAbility::Read),
             _ => None,
         })
     }
@@ -74,12 +95,29 @@ impl Plugin for VolumePlugin {
     }
 }
 
+impl VolumeResource {
+    /// The volume of given account or app DID
+    pub fn new(did: impl Into<String>) -> Self {
+        Self { did: did.into() }
+    }
+
+    /// The DID of the account or app owning this volume
+    pub fn did(&self) -> &str {
+        &self.did
+    }
+}
+
 impl Resource for VolumeResource {
     fn is_valid_attenuation(&self, other: &dyn Resource) -> bool {
         if let Some(UcanResource::AllProvable) = other.downcast_ref() {
             return true;
         }
 
+        // Whoever has access to an account has access to its volume
+        if let Some(Did(did)) = other.downcast_ref() {
+            return &self.did == did;
+        }
+
         let Some(VolumeResource { did }) = other.downcast_ref() else {
             return false;
         };
@@ -97,6 +135,10 @@ impl Display for VolumeResource {
 
 impl Ability for VolumeAbility {
     fn is_valid_attenuation(&self, other: &dyn Ability) -> bool {
+        if other.downcast_ref::<TopAbility>().is_some() {
+            return true;
+        }
+
         let Some(other) = other.downcast_ref::<VolumeAbility>() else {
             return false;
         };
@@ -105,10 +147,65 @@ impl Ability for VolumeAbility {
     }
 }
 
+impl VolumeCaveat {
+    /// Whether this caveat doesn't restrict anything
+    pub fn is_unrestricted(&self) -> bool {
+        self.max_size.is_none()
+    }
+}
+
+impl Caveat for VolumeCaveat {
+    fn is_valid_attenuation(&self, other: &dyn Caveat) -> bool {
+        if other.downcast_ref::<EmptyCaveat>().is_some() {
+            return true;
+        }
+
+        let Some(other) = other.downcast_ref::<Self>() else {
+            return false;
+        };
+
+        match (self.max_size, other.max_size) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(ours), Some(theirs)) => ours <= theirs,
+        }
+    }
+}
+
 impl Display for VolumeAbility {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Update => f.write_str("volume/update"),
+            Self::Read => f.write_str("volume/read"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caveat_attenuation() {
+        let large = VolumeCaveat {
+            max_size: Some(1000),
+        };
+        let small = VolumeCaveat {
+            max_size: Some(100),
+        };
+
+        assert!(small.is_valid_attenuation(&large));
+        assert!(large.is_valid_attenuation(&large));
+        assert!(large.is_valid_attenuation(&VolumeCaveat::default()));
+        assert!(large.is_valid_attenuation(&EmptyCaveat));
+        assert!(!large.is_valid_attenuation(&small));
+        assert!(!VolumeCaveat::default().is_valid_attenuation(&large));
+    }
+
+    #[test]
+    fn test_caveat_rejects_path_prefix() {
+        // Pushes always replace the whole volume, so path restrictions can't be enforced
+        assert!(serde_json::from_str::<VolumeCaveat>(r#"{ "pathPrefix": "public" }"#).is_err());
+        assert!(serde_json::from_str::<VolumeCaveat>(r#"{ "maxSize": 10 }"#).is_ok());
+    }
+}
@@ -1,6 +1,9 @@
 //! Request and response data types that are common and useful between clients of and the fission server
 
-use crate::username::{Handle, Username};
+use crate::{
+    revocation::Revocation,
+    username::{Handle, Username},
+};
 use rs_ucan::ucan::Ucan;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
@@ -9,11 +12,80 @@ use validator::Validate;
 
 /// Email verification request struct
 #[derive(Deserialize, Serialize, Validate, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct EmailVerifyRequest {
     /// The email address of the user signing up
     #[validate(email)]
     #[schema(example = "max.mustermann@example.com")]
     pub email: String,
+    /// Whether to send a code or a single-use link. Defaults to sending a code.
+    #[serde(default)]
+    pub mode: VerificationMode,
+    /// The relay topic that the code is sent on once the link is confirmed.
+    /// Required when `mode` is `link`. Should be random, since anyone listening
+    /// on the topic receives the code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "3f0c9a1e77b2d4c80e5a6f13c29d8b04")]
+    pub relay_topic: Option<String>,
+    /// What the code from the link is going to be used for.
+    /// Required when `mode` is `link`, the code can't be used for anything else.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<VerificationPurpose>,
+    /// The DID that's going to use the code from the link, see `VerificationPurpose`.
+    /// Required when `mode` is `link`, the code can't be used by any other DID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "did:key:z6MkkT5xHf6ecp4A2sNBVPGXTFHbvjDhmrHKdyYHrfMakxtD")]
+    pub did: Option<String>,
+    /// The language tag of the locale the email should be written in, e.g. `de` or `pt-BR`.
+    /// Falls back to a more general or the default locale, if there's no translation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(min = 2, max = 35))]
+    #[schema(example = "de")]
+    pub locale: Option<String>,
+}
+
+/// How an email address is verified
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationMode {
+    /// The email contains a 6-digit code that the user enters in the client
+    #[default]
+    Code,
+    /// The email contains a single-use link. Confirming it sends a code to the waiting client
+    /// over the websocket relay, see `EmailVerifyRequest::relay_topic`.
+    Link,
+}
+
+/// What a code from a verification link is requested for.
+///
+/// Account recovery isn't possible with codes from links, since whoever requests a link
+/// receives its code once the link is confirmed.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerificationPurpose {
+    /// Creating an account, used by the DID of the new account's agent
+    Signup,
+    /// Linking a device to an account, used by the device's DID
+    LinkDevice,
+    /// Changing an account's email address, used by the account DID
+    ChangeEmail,
+    /// Verifying an email address added to an account, used by the account DID
+    AddEmail,
+    /// Restoring an account that's pending deletion, used by the account DID
+    Restore,
+}
+
+impl VerificationPurpose {
+    /// The name this purpose is serialized as
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Signup => "signup",
+            Self::LinkDevice => "link-device",
+            Self::ChangeEmail => "change-email",
+            Self::AddEmail => "add-email",
+            Self::Restore => "restore",
+        }
+    }
 }
 
 /// Account Request Struct (for creating new accounts)
@@ -29,6 +101,26 @@ pub struct AccountCreationRequest {
     /// Email verification code
     #[schema(example = "123456")]
     pub code: String,
+    /// Credential ID of a passkey registered via `/api/v0/auth/webauthn/register/finish`.
+    /// If present, the passkey's DID gets root access to the new account, too.
+    #[serde(
+        rename = "credentialID",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub credential_id: Option<String>,
+}
+
+/// Request data for confirming an account's email address change
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema, Validate)]
+pub struct EmailChangeRequest {
+    /// The new email address
+    #[validate(email)]
+    #[schema(example = "max.mustermann@example.com")]
+    pub email: String,
+    /// Email verification code sent to the new email address
+    #[schema(example = "123456")]
+    pub code: String,
 }
 
 /// Request data for the account link route
@@ -39,6 +131,95 @@ pub struct AccountLinkRequest {
     pub code: String,
 }
 
+/// Request data for restoring an account that's pending deletion
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct AccountRestoreRequest {
+    /// The code from the verification email sent to the account's email address
+    #[schema(example = "123456")]
+    pub code: String,
+}
+
+/// Request data for verifying an additional email address of an account
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct AccountEmailVerifyRequest {
+    /// Email verification code sent to the address
+    #[schema(example = "123456")]
+    pub code: String,
+}
+
+/// Request data for recovering access to an account from a new device.
+/// Exactly one of `recoveryCode` or `code` needs to be provided.
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountRecoveryRequest {
+    /// A recovery code previously generated for the account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "3f0c9a1e-77b2d4c8-0e5a6f13-c29d8b04")]
+    pub recovery_code: Option<String>,
+    /// An email verification code sent to any of the account's verified email addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "123456")]
+    pub code: Option<String>,
+}
+
+/// A freshly generated account recovery code
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodeResponse {
+    /// The recovery code. It's only shown once, the server only stores a hash of it.
+    #[schema(example = "3f0c9a1e-77b2d4c8-0e5a6f13-c29d8b04")]
+    pub recovery_code: String,
+}
+
+/// Request data for starting a passkey registration or login
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct PasskeyStartRequest {
+    /// The username to register the passkey for, or the username of the account to log into
+    #[schema(value_type = String, example = "my_username")]
+    pub username: Username,
+}
+
+/// The WebAuthn options for a passkey ceremony the server started
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyChallengeResponse {
+    /// Refers to the started ceremony when finishing it
+    pub challenge_id: String,
+    /// Options to pass to `navigator.credentials.create()` when registering,
+    /// or `navigator.credentials.get()` when logging in
+    #[schema(value_type = Object)]
+    pub options: serde_json::Value,
+}
+
+/// Request data for finishing a passkey registration or login
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyFinishRequest {
+    /// The challenge ID returned when starting the ceremony
+    pub challenge_id: String,
+    /// The `PublicKeyCredential` returned by the browser, in JSON form
+    #[schema(value_type = Object)]
+    pub credential: serde_json::Value,
+}
+
+/// Response for a successful passkey registration
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct PasskeyRegistrationResponse {
+    /// The registered credential ID. Pass this on when creating an account.
+    #[serde(rename = "credentialID")]
+    pub credential_id: String,
+    /// The agent DID derived from the passkey's public key
+    #[schema(example = "did:key:zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169")]
+    pub did: String,
+}
+
+/// Request data for changing who can pull a volume
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct VolumeVisibilityRequest {
+    /// Whether pulling the volume requires a `volume/read` capability
+    pub private: bool,
+}
+
 /// Response type indiciating success
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct SuccessResponse {
@@ -46,6 +227,13 @@ pub struct SuccessResponse {
     pub success: bool,
 }
 
+/// Response for the username availability check
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UsernameAvailableResponse {
+    /// Whether the username can be registered
+    pub available: bool,
+}
+
 /// Response for the member number
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -94,3 +282,192 @@ pub struct Account {
     #[schema(example = "max.mustermann@example.com")]
     pub email: Option<String>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+/// An email address associated with an account
+pub struct AccountEmail {
+    /// The email address
+    #[schema(example = "max.mustermann@example.com")]
+    pub email: String,
+
+    /// Whether the address was verified via verification code.
+    /// Codes sent to any verified address can be used for logging in.
+    pub verified: bool,
+
+    /// Whether this is the account's primary address, as returned in account information
+    pub primary: bool,
+}
+
+/// Response type listing an account's email addresses
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AccountEmailsResponse {
+    /// The account's email addresses, primary address first
+    pub emails: Vec<AccountEmail>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+/// A device (agent DID) that the server delegated account access to
+pub struct Device {
+    /// The device's agent DID
+    #[schema(example = "did:key:z6MkemkqoaUBYisJbrE9onCPgJoZve1yji2azp3oJo9W6u2A")]
+    pub did: String,
+
+    /// Unix timestamp (seconds) of when the device's access expires, if ever
+    pub expires_at: Option<u64>,
+}
+
+/// Response type listing the devices with access to an account
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DevicesResponse {
+    /// The devices that currently hold account access
+    pub devices: Vec<Device>,
+}
+
+/// Diagnostics of checking whether a handle's domain points to an account DID
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HandleCheckResponse {
+    /// The handle that was checked
+    #[schema(example = "oedipa.example.com")]
+    pub handle: String,
+    /// The account DID the handle needs to point to
+    #[schema(example = "did:key:z6MkemkqoaUBYisJbrE9onCPgJoZve1yji2azp3oJo9W6u2A")]
+    pub did: String,
+    /// Whether either the DNS record or the well-known file point to the account DID
+    pub verified: bool,
+    /// Result of looking up the `_did.<handle>` DNS TXT record
+    pub dns: DnsTxtDiagnostic,
+    /// Result of fetching `https://<handle>/.well-known/did.txt`
+    pub well_known: WellKnownDiagnostic,
+}
+
+/// Result of looking up a `_did` DNS TXT record
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DnsTxtDiagnostic {
+    /// The queried DNS name
+    #[schema(example = "_did.oedipa.example.com")]
+    pub name: String,
+    /// Which resolver answered the query
+    #[schema(example = "upstream resolver (Cloudflare)")]
+    pub resolver: String,
+    /// How the lookup went
+    pub outcome: DnsLookupOutcome,
+    /// The TXT record values that were found
+    pub values: Vec<String>,
+    /// Whether one of the values is the account DID
+    pub matches: bool,
+    /// Details if the lookup failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of a DNS lookup
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DnsLookupOutcome {
+    /// The name has TXT records
+    Found,
+    /// The name exists, but has no TXT records
+    NoRecords,
+    /// The name doesn't exist
+    NxDomain,
+    /// The resolver failed to answer, e.g. because an upstream nameserver timed out
+    ServFail,
+    /// No answer in time
+    Timeout,
+    /// The lookup failed for another reason
+    Error,
+}
+
+/// Result of fetching a handle's well-known DID file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WellKnownDiagnostic {
+    /// The fetched URL
+    #[schema(example = "https://oedipa.example.com/.well-known/did.txt")]
+    pub url: String,
+    /// How fetching the file went
+    pub outcome: WellKnownOutcome,
+    /// Whether the file's content is the account DID.
+    /// The content itself isn't returned, so this can't be used to read arbitrary files.
+    pub matches: bool,
+    /// Details if the request failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of fetching a well-known file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WellKnownOutcome {
+    /// The file was served
+    Found,
+    /// The server responded with an error status
+    NotFound,
+    /// The server couldn't be reached or the response was invalid
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+/// Information about an app
+pub struct App {
+    /// App DID
+    #[schema(example = "did:key:z6MkemkqoaUBYisJbrE9onCPgJoZve1yji2azp3oJo9W6u2A")]
+    pub did: String,
+
+    /// The app's subdomain name
+    #[schema(example = "friendly-pink-dragon")]
+    pub name: String,
+
+    /// CID of the app's latest published volume
+    #[schema(example = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354")]
+    pub cid: Option<String>,
+}
+
+/// A passkey registered for an account
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountPasskey {
+    /// The WebAuthn credential ID, base64url-encoded
+    #[serde(rename = "credentialID")]
+    pub credential_id: String,
+
+    /// The agent DID derived from the passkey's public key
+    pub did: String,
+}
+
+/// All data the server stores about an account, as returned by the account export route
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExport {
+    /// Account information
+    pub account: Account,
+
+    /// All email addresses of the account, primary address first
+    pub emails: Vec<AccountEmail>,
+
+    /// The passkeys registered for the account
+    pub passkeys: Vec<AccountPasskey>,
+
+    /// CID of the account's current volume
+    #[schema(example = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354")]
+    pub volume_cid: Option<String>,
+
+    /// The apps owned by the account
+    pub apps: Vec<App>,
+
+    /// All indexed UCANs that have the account DID or its volume as resource, in encoded form
+    pub ucans: Vec<String>,
+
+    /// Revocations of UCANs for the account or issued by the account
+    pub revocations: Vec<Revocation>,
+}
+
+/// Response type listing apps
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AppsResponse {
+    /// The apps owned by the account
+    pub apps: Vec<App>,
+}
@@ -8,7 +8,7 @@ use ed25519::{
 use ed25519_dalek::SigningKey;
 use rand::thread_rng;
 use rs_ucan::crypto::SignerDid;
-use signature::Signer;
+use signature::{Signer, Verifier};
 use std::fmt::Display;
 use zeroize::ZeroizeOnDrop;
 
@@ -66,6 +66,12 @@ impl Signer<Signature> for EdDidKey {
     }
 }
 
+impl Verifier<Signature> for EdDidKey {
+    fn verify(&self, msg: &[u8], signature: &Signature) -> Result<(), signature::Error> {
+        self.signing_key.verify(msg, signature)
+    }
+}
+
 impl AsRef<str> for EdDidKey {
     fn as_ref(&self) -> &str {
         self.did_as_str()
@@ -6,6 +6,43 @@ use std::str::FromStr;
 use utoipa::ToSchema;
 use validator::{Validate, ValidationError, ValidationErrors};
 
+/// Usernames that can't be registered, because they are either
+/// used for infrastructure subdomains or likely to be used for impersonation.
+pub const RESERVED_USERNAMES: &[&str] = &[
+    "about",
+    "abuse",
+    "account",
+    "admin",
+    "administrator",
+    "api",
+    "app",
+    "apps",
+    "auth",
+    "blog",
+    "dns",
+    "docs",
+    "fission",
+    "ftp",
+    "help",
+    "hostmaster",
+    "imap",
+    "info",
+    "ipfs",
+    "mail",
+    "noreply",
+    "ns",
+    "postmaster",
+    "root",
+    "security",
+    "smtp",
+    "staff",
+    "status",
+    "support",
+    "system",
+    "webmaster",
+    "www",
+];
+
 /// A verified username.
 ///
 /// This doesn't include the domain portion.
@@ -73,6 +110,20 @@ impl Username {
     pub fn to_unicode(&self) -> String {
         idna::domain_to_unicode(self.as_str()).0
     }
+
+    /// Whether this username is reserved and thus can't be registered
+    pub fn is_reserved(&self) -> bool {
+        RESERVED_USERNAMES.contains(&self.as_str())
+    }
+
+    /// Compute the confusable skeleton of this username according to
+    /// [UTS #39](https://www.unicode.org/reports/tr39/#Confusable_Detection).
+    ///
+    /// Two usernames that are visually confusable, like `paypal` and `раураl`
+    /// (using cyrillic letters), map to the same skeleton.
+    pub fn skeleton(&self) -> String {
+        confusable_skeleton(&self.to_unicode())
+    }
 }
 
 /// A verified handle.
@@ -144,6 +195,16 @@ impl Handle {
     pub fn to_unicode(&self) -> String {
         idna::domain_to_unicode(self.as_str()).0
     }
+
+    /// Compute the confusable skeleton of this handle.
+    /// See `Username::skeleton` for more information.
+    pub fn skeleton(&self) -> String {
+        confusable_skeleton(&self.to_unicode())
+    }
+}
+
+fn confusable_skeleton(s: &str) -> String {
+    unicode_security::skeleton(s).collect()
 }
 
 fn valid_domain_encoding(s: &str) -> Result<(), ValidationError> {
@@ -215,6 +276,32 @@ mod tests {
         assert_matches!("bob22".parse::<Username>(), Ok(_));
     }
 
+    #[test]
+    fn test_reserved_usernames() -> Result<()> {
+        assert!("admin".parse::<Username>()?.is_reserved());
+        assert!("www".parse::<Username>()?.is_reserved());
+        assert!(!"alice".parse::<Username>()?.is_reserved());
+        Ok(())
+    }
+
+    #[test]
+    fn test_confusable_skeletons() -> Result<()> {
+        let latin = "paypal".parse::<Username>()?;
+        let cyrillic = Username::from_unicode("раураl")?;
+        assert_ne!(latin, cyrillic);
+        assert_eq!(latin.skeleton(), cyrillic.skeleton());
+
+        let modern = "modern".parse::<Username>()?;
+        let rnodern = "rnodern".parse::<Username>()?;
+        assert_eq!(modern.skeleton(), rnodern.skeleton());
+
+        let alice = "alice".parse::<Username>()?;
+        let bob = "bob".parse::<Username>()?;
+        assert_ne!(alice.skeleton(), bob.skeleton());
+
+        Ok(())
+    }
+
     #[test]
     fn test_punycode_valid() {
         assert_matches!(Username::from_unicode("bücher"), Ok(_));
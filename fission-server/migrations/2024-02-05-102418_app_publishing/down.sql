@@ -0,0 +1,10 @@
+DROP INDEX idx_apps_owner_id;
+
+ALTER TABLE apps
+    DROP CONSTRAINT IF EXISTS unique_app_did,
+    DROP CONSTRAINT IF EXISTS unique_app_name;
+
+ALTER TABLE apps
+    DROP COLUMN did,
+    DROP COLUMN name,
+    ADD COLUMN cid TEXT;
@@ -0,0 +1,10 @@
+ALTER TABLE apps
+    DROP COLUMN cid,
+    ADD COLUMN did TEXT NOT NULL,
+    ADD COLUMN name TEXT NOT NULL;
+
+ALTER TABLE apps
+    ADD CONSTRAINT unique_app_did UNIQUE (did),
+    ADD CONSTRAINT unique_app_name UNIQUE (name);
+
+CREATE INDEX idx_apps_owner_id ON apps (owner_id);
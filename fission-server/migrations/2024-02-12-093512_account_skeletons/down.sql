@@ -0,0 +1,6 @@
+DROP INDEX idx_accounts_handle_skeleton;
+DROP INDEX idx_accounts_username_skeleton;
+
+ALTER TABLE accounts
+  DROP COLUMN handle_skeleton,
+  DROP COLUMN username_skeleton;
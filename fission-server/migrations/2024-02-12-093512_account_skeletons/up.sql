@@ -0,0 +1,8 @@
+-- UTS #39 confusable skeletons of usernames and handles.
+-- Rows that existed before this migration are backfilled on server startup.
+ALTER TABLE accounts
+  ADD COLUMN username_skeleton TEXT,
+  ADD COLUMN handle_skeleton TEXT;
+
+CREATE INDEX idx_accounts_username_skeleton ON accounts (username_skeleton);
+CREATE INDEX idx_accounts_handle_skeleton ON accounts (handle_skeleton);
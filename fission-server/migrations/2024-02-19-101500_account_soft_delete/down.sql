@@ -0,0 +1,4 @@
+DROP INDEX idx_accounts_deleted_at;
+
+ALTER TABLE accounts
+  DROP COLUMN deleted_at;
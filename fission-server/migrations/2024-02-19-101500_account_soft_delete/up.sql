@@ -0,0 +1,6 @@
+-- Accounts with deleted_at set are pending deletion and can still be restored
+-- until they're purged after the configured grace period.
+ALTER TABLE accounts
+  ADD COLUMN deleted_at TIMESTAMP;
+
+CREATE INDEX idx_accounts_deleted_at ON accounts (deleted_at);
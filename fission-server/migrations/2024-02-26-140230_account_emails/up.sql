@@ -0,0 +1,25 @@
+-- All email addresses associated with an account.
+-- The primary address is mirrored in accounts.email.
+CREATE TABLE account_emails (
+    id SERIAL PRIMARY KEY,
+
+    inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    verified BOOLEAN NOT NULL DEFAULT FALSE,
+    is_primary BOOLEAN NOT NULL DEFAULT FALSE,
+
+    CONSTRAINT unique_account_email UNIQUE (account_id, email)
+);
+
+SELECT diesel_manage_updated_at('account_emails');
+
+-- A verified email address can only belong to a single account
+CREATE UNIQUE INDEX idx_account_emails_verified_email ON account_emails (email) WHERE verified;
+-- Accounts have at most one primary email address
+CREATE UNIQUE INDEX idx_account_emails_primary ON account_emails (account_id) WHERE is_primary;
+
+INSERT INTO account_emails (account_id, email, verified, is_primary)
+  SELECT id, email, TRUE, TRUE FROM accounts WHERE email IS NOT NULL;
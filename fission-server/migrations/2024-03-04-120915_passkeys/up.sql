@@ -0,0 +1,36 @@
+-- WebAuthn passkeys. Each passkey gets its own agent DID derived from its public key.
+-- Passkeys are registered before the account they belong to is created,
+-- so account_id is only set once an account claims the passkey.
+CREATE TABLE passkeys (
+    id SERIAL PRIMARY KEY,
+
+    inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    account_id INTEGER REFERENCES accounts(id) ON DELETE CASCADE,
+    credential_id TEXT NOT NULL,
+    did TEXT NOT NULL,
+    passkey JSONB NOT NULL,
+
+    CONSTRAINT unique_passkey_credential_id UNIQUE (credential_id)
+);
+
+SELECT diesel_manage_updated_at('passkeys');
+
+CREATE INDEX idx_passkeys_account_id ON passkeys (account_id);
+
+-- State of in-progress WebAuthn registration and authentication ceremonies
+CREATE TABLE webauthn_challenges (
+    id SERIAL PRIMARY KEY,
+
+    inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    challenge_id TEXT NOT NULL,
+    account_id INTEGER REFERENCES accounts(id) ON DELETE CASCADE,
+    state JSONB NOT NULL,
+
+    CONSTRAINT unique_webauthn_challenge_id UNIQUE (challenge_id)
+);
+
+SELECT diesel_manage_updated_at('webauthn_challenges');
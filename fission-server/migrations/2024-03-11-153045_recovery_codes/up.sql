@@ -0,0 +1,15 @@
+-- Single-use codes for recovering access to an account.
+-- Only a hash of the code is stored.
+CREATE TABLE recovery_codes (
+    id SERIAL PRIMARY KEY,
+
+    inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+    account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+
+    CONSTRAINT unique_recovery_code_account UNIQUE (account_id)
+);
+
+SELECT diesel_manage_updated_at('recovery_codes');
@@ -0,0 +1,8 @@
+-- all code hashes would become invalid
+TRUNCATE email_verifications;
+
+ALTER TABLE email_verifications
+  DROP COLUMN failed_attempts;
+
+ALTER TABLE email_verifications
+  RENAME COLUMN code_hash TO code;
@@ -0,0 +1,8 @@
+-- plaintext codes can't be converted into hashes without knowing them
+TRUNCATE email_verifications;
+
+ALTER TABLE email_verifications
+  RENAME COLUMN code TO code_hash;
+
+ALTER TABLE email_verifications
+  ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE email_verifications
+  DROP COLUMN link_used;
@@ -0,0 +1,2 @@
+ALTER TABLE email_verifications
+  ADD COLUMN link_used BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+DROP INDEX idx_accounts_handle_verified_at;
+
+ALTER TABLE accounts
+  DROP COLUMN handle_unverified_since,
+  DROP COLUMN handle_verified_at;
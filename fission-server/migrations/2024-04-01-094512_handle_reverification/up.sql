@@ -0,0 +1,13 @@
+-- Handles are periodically re-verified against their domain.
+-- handle_verified_at is the last time that succeeded, handle_unverified_since
+-- is set while it keeps failing, until the handle is cleared.
+ALTER TABLE accounts
+  ADD COLUMN handle_verified_at TIMESTAMP,
+  ADD COLUMN handle_unverified_since TIMESTAMP;
+
+-- Existing handles were verified when they were set
+UPDATE accounts
+  SET handle_verified_at = updated_at
+  WHERE handle IS NOT NULL;
+
+CREATE INDEX idx_accounts_handle_verified_at ON accounts (handle_verified_at);
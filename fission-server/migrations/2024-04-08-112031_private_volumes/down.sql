@@ -0,0 +1,8 @@
+DROP INDEX idx_volumes_cid;
+
+ALTER TABLE volumes
+  DROP COLUMN private,
+  DROP COLUMN owner_did;
+
+ALTER TABLE apps DROP COLUMN volume_private;
+ALTER TABLE accounts DROP COLUMN volume_private;
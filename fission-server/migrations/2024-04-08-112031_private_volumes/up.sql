@@ -0,0 +1,21 @@
+-- Volumes can opt into being private, so pulling them requires a volume/read capability.
+-- Every pushed version of a volume gets its own row, so each row remembers its owner
+-- and whether it was private, to keep protecting old versions.
+ALTER TABLE accounts ADD COLUMN volume_private BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE apps ADD COLUMN volume_private BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE volumes
+  ADD COLUMN owner_did TEXT,
+  ADD COLUMN private BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE volumes
+  SET owner_did = accounts.did
+  FROM accounts
+  WHERE accounts.volume_id = volumes.id;
+
+UPDATE volumes
+  SET owner_did = apps.did
+  FROM apps
+  WHERE apps.volume_id = volumes.id;
+
+CREATE INDEX idx_volumes_cid ON volumes (cid);
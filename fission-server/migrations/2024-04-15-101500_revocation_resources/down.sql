@@ -0,0 +1,3 @@
+DROP INDEX idx_revocations_resources;
+
+ALTER TABLE revocations DROP COLUMN resources;
@@ -0,0 +1,13 @@
+-- Revoking a UCAN deletes its indexed row, so revocations remember the resources
+-- of the revoked UCAN themselves, e.g. to find them when exporting an account.
+ALTER TABLE revocations ADD COLUMN resources TEXT[] NOT NULL DEFAULT '{}';
+
+UPDATE revocations
+  SET resources = ARRAY(
+    SELECT DISTINCT capabilities.resource
+    FROM capabilities
+    INNER JOIN ucans ON ucans.id = capabilities.ucan_id
+    WHERE ucans.cid = revocations.cid
+  );
+
+CREATE INDEX idx_revocations_resources ON revocations USING GIN (resources);
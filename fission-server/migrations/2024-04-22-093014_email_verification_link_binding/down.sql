@@ -0,0 +1,3 @@
+ALTER TABLE email_verifications
+  DROP COLUMN link_did,
+  DROP COLUMN link_purpose;
@@ -0,0 +1,3 @@
+ALTER TABLE email_verifications
+  ADD COLUMN link_purpose TEXT,
+  ADD COLUMN link_did TEXT;
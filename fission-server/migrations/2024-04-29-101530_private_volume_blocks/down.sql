@@ -0,0 +1 @@
+DROP TABLE private_volume_blocks;
@@ -0,0 +1,12 @@
+-- Private volumes remember all blocks in their DAG, so blocks within them can't be
+-- pulled by their own CID, or by pushing another DAG that links to them.
+-- The blockstore finds blocks by multihash alone, so blocks are indexed by their
+-- multihash encoded as a CIDv1 with the raw codec, no matter how they're linked.
+-- Volumes that were private before this migration are indexed when the server starts.
+CREATE TABLE private_volume_blocks (
+  volume_id INTEGER NOT NULL REFERENCES volumes(id) ON DELETE CASCADE,
+  raw_cid TEXT NOT NULL,
+  PRIMARY KEY (volume_id, raw_cid)
+);
+
+CREATE INDEX idx_private_volume_blocks_raw_cid ON private_volume_blocks (raw_cid);
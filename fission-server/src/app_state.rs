@@ -3,14 +3,18 @@
 use crate::{
     db::Pool,
     dns::server::DnsServer,
-    routes::ws::WsPeerMap,
+    passkeys::build_webauthn,
+    rate_limit::RateLimiter,
+    routes::{volume::PushSizes, ws::WsPeerMap},
     settings::{self},
     setups::{DbBlockStore, IpfsDatabase, ServerSetup},
+    username_denylist::UsernameDenylist,
 };
 use anyhow::{anyhow, Result};
 use car_mirror::cache::{CacheMissing, InMemoryCache};
 use fission_core::ed_did_key::EdDidKey;
 use std::sync::Arc;
+use webauthn_rs::Webauthn;
 
 #[derive(Clone)]
 /// Global application route state.
@@ -31,6 +35,18 @@ pub struct AppState<S: ServerSetup> {
     pub server_keypair: Arc<EdDidKey>,
     /// The DNS server state. Used for answering DoH queries
     pub dns_server: DnsServer,
+    /// Usernames that can't be registered
+    pub username_denylist: Arc<UsernameDenylist>,
+    /// The WebAuthn relying party for passkey registration & login
+    pub webauthn: Arc<Webauthn>,
+    /// Rate limits for unauthenticated requests
+    pub rate_limiter: RateLimiter<S::RateLimitStore>,
+    /// Settings for single-use email verification links
+    pub verification_links: Arc<settings::VerificationLinks>,
+    /// The client for outgoing HTTP requests, e.g. for verifying handles
+    pub http_client: S::HttpClient,
+    /// How many bytes unfinished volume pushes received so far
+    pub push_sizes: Arc<PushSizes>,
 }
 
 /// Anything related to block storage (connection to kubo/something mocking kubo, caches, metadata)
@@ -82,6 +98,12 @@ pub struct AppStateBuilder<S: ServerSetup> {
     server_keypair: Option<EdDidKey>,
     dns_server: Option<DnsServer>,
     ws_peer_map: Arc<WsPeerMap>,
+    username_denylist: UsernameDenylist,
+    webauthn: Option<Webauthn>,
+    rate_limits: settings::RateLimits,
+    rate_limit_store: S::RateLimitStore,
+    verification_links: settings::VerificationLinks,
+    http_client: S::HttpClient,
 }
 
 impl<S: ServerSetup> Default for AppStateBuilder<S> {
@@ -95,6 +117,12 @@ impl<S: ServerSetup> Default for AppStateBuilder<S> {
             server_keypair: None,
             dns_server: None,
             ws_peer_map: Default::default(),
+            username_denylist: Default::default(),
+            webauthn: None,
+            rate_limits: Default::default(),
+            rate_limit_store: Default::default(),
+            verification_links: Default::default(),
+            http_client: Default::default(),
         }
     }
 }
@@ -127,6 +155,11 @@ impl<S: ServerSetup> AppStateBuilder<S> {
 
         let ws_peer_map = self.ws_peer_map;
 
+        let webauthn = match self.webauthn {
+            Some(webauthn) => webauthn,
+            None => build_webauthn(&settings::Webauthn::default())?,
+        };
+
         Ok(AppState {
             dns_settings,
             db_pool,
@@ -135,6 +168,12 @@ impl<S: ServerSetup> AppStateBuilder<S> {
             ws_peer_map,
             server_keypair: Arc::new(did),
             dns_server,
+            username_denylist: Arc::new(self.username_denylist),
+            webauthn: Arc::new(webauthn),
+            rate_limiter: RateLimiter::new(self.rate_limit_store, self.rate_limits),
+            verification_links: Arc::new(self.verification_links),
+            http_client: self.http_client,
+            push_sizes: Default::default(),
             blocks: Blocks::new(
                 ipfs_db,
                 // TODO(matheus23): make these numbers configurable
@@ -191,6 +230,45 @@ impl<S: ServerSetup> AppStateBuilder<S> {
         self
     }
 
+    /// Set the usernames that can't be registered
+    pub fn with_username_denylist(mut self, username_denylist: UsernameDenylist) -> Self {
+        self.username_denylist = username_denylist;
+        self
+    }
+
+    /// Set the WebAuthn relying party used for passkeys
+    pub fn with_webauthn(mut self, webauthn: Webauthn) -> Self {
+        self.webauthn = Some(webauthn);
+        self
+    }
+
+    /// Set the rate limits for unauthenticated requests
+    pub fn with_rate_limits(mut self, rate_limits: settings::RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    /// Set the store that keeps track of rate limited requests
+    pub fn with_rate_limit_store(mut self, rate_limit_store: S::RateLimitStore) -> Self {
+        self.rate_limit_store = rate_limit_store;
+        self
+    }
+
+    /// Set the settings for single-use email verification links
+    pub fn with_verification_links(
+        mut self,
+        verification_links: settings::VerificationLinks,
+    ) -> Self {
+        self.verification_links = verification_links;
+        self
+    }
+
+    /// Set the client for outgoing HTTP requests
+    pub fn with_http_client(mut self, http_client: S::HttpClient) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
     /// Set the websocket peer map
     pub fn with_ws_peer_map(mut self, ws_peer_map: Arc<WsPeerMap>) -> Self {
         self.ws_peer_map = ws_peer_map;
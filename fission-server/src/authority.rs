@@ -5,24 +5,32 @@ use crate::{
     db,
     db::Conn,
     error::{AppError, AppResult},
-    models::revocation::find_revoked_subset,
+    models::{account::AccountRecord, revocation::find_revoked_subset},
     setups::ServerSetup,
 };
 use anyhow::{bail, Result};
 use fission_core::{
-    capabilities::did::Did,
+    capabilities::{
+        did::Did,
+        fission::{AccountOperation, FissionCaveat},
+        volume::{VolumeAbility, VolumeCaveat, VolumeResource},
+    },
     revocation::{canonical_cid, Revocation},
 };
 use http::StatusCode;
 use libipld::{raw::RawCodec, Ipld};
 use rs_ucan::{
     did_verifier::DidVerifierMap,
-    semantics::ability::Ability,
+    semantics::{
+        ability::{Ability, TopAbility},
+        caveat::{Caveat, EmptyCaveat},
+        resource::Resource,
+    },
     store::{InMemoryStore, Store},
     ucan::Ucan,
     DefaultFact,
 };
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::BTreeSet;
 
 //-------//
@@ -38,6 +46,18 @@ pub struct Authority<F = DefaultFact> {
     pub proofs: Vec<Ucan>,
 }
 
+/// What an invocation needs the caveats of its capabilities to allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaveatRequirement {
+    /// The capability must not be restricted by caveats
+    Unrestricted,
+    /// The capability must allow given `account/manage` operation
+    AccountOperation(AccountOperation),
+    /// The capability must allow pushing to the volume.
+    /// Upload size limits are enforced while receiving the push, see `volume_max_size`.
+    VolumeUpdate,
+}
+
 //-----------------//
 // IMPLEMENTATIONS //
 //-----------------//
@@ -95,11 +115,31 @@ impl<F: Clone + DeserializeOwned> Authority<F> {
     /// Validates whether or not the UCAN and proofs have the capability to
     /// perform the given action, with the given issuer as the root of that
     /// authority.
+    ///
+    /// Capabilities restricted by caveats are rejected,
+    /// use `get_capability_for` for routes that support them.
     pub async fn get_capability<S: ServerSetup>(
         &self,
         app_state: &AppState<S>,
         ability: impl Ability,
     ) -> AppResult<Did> {
+        self.get_capability_for(app_state, ability, CaveatRequirement::Unrestricted)
+            .await
+    }
+
+    /// Like `get_capability`, but allows capabilities with caveats,
+    /// as long as they allow given requirement.
+    ///
+    /// The caveats of the invocation and of all proofs in the delegation chain
+    /// need to allow it. Other proofs sent along are ignored.
+    pub async fn get_capability_for<S: ServerSetup>(
+        &self,
+        app_state: &AppState<S>,
+        ability: impl Ability,
+        requirement: impl Into<CaveatRequirement>,
+    ) -> AppResult<Did> {
+        let requirement = requirement.into();
+
         self.validate_audience(app_state.server_keypair.did_as_str())?;
 
         let revocations = self
@@ -115,79 +155,239 @@ impl<F: Clone + DeserializeOwned> Authority<F> {
 
         let current_time = rs_ucan::time::now();
 
-        let mut store = InMemoryStore::<RawCodec>::default();
-
-        for proof in &self.proofs {
-            // TODO(matheus23): rs-ucan should probably have support for revoked CIDs
-            if revocations.contains(&canonical_cid(proof)?) {
-                continue; // This CID was revoked.
-            }
-            // TODO(matheus23): we assume SHA2-256 atm. The spec says to hash with all CID formats used in proofs >.<
-            store.write(Ipld::Bytes(proof.encode()?.as_bytes().to_vec()), None)?;
-        }
-
         let caps = self.ucan.capabilities().collect::<Vec<_>>();
-        let [cap] = caps[..] else {
-            if caps.is_empty() {
-                tracing::error!("No capabilities provided.");
-                return Err(AppError::new(
-                    StatusCode::BAD_REQUEST,
-                    Some("Invocation UCAN without capabilities provided."),
-                ));
-            }
-            tracing::error!(caps = ?caps, "Invocation UCAN with multiple capabilities is ambiguous.");
+        if caps.is_empty() {
+            tracing::error!("No capabilities provided.");
             return Err(AppError::new(
                 StatusCode::BAD_REQUEST,
-                Some("Invocation UCAN with multiple capabilities is ambiguous."),
+                Some("Invocation UCAN without capabilities provided."),
             ));
-        };
+        }
 
-        if !cap.ability().is_valid_attenuation(&ability) {
+        let matching = caps
+            .iter()
+            .filter(|cap| cap.ability().is_valid_attenuation(&ability))
+            .collect::<Vec<_>>();
+
+        if matching.is_empty() {
+            let abilities = caps
+                .iter()
+                .map(|cap| cap.ability().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
             return Err(AppError::new(
                 StatusCode::FORBIDDEN,
                 Some(format!(
-                    "Invalid authorization. Expected ability {ability}, but got {}",
-                    cap.ability()
+                    "Invalid authorization. Expected ability {ability}, but got {abilities}"
                 )),
             ));
         }
 
-        let Some(Did(did)) = cap.resource().downcast_ref() else {
+        let dids = match matching
+            .iter()
+            .map(|cap| subject_did(cap.resource()).ok_or(cap.resource()))
+            .collect::<Result<BTreeSet<_>, _>>()
+        {
+            Ok(dids) => dids,
+            Err(resource) => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    Some(format!(
+                        "Invalid authorization. Expected a DID or volume, but got {resource}"
+                    )),
+                ));
+            }
+        };
+
+        // Several capabilities for the same resource are fine, e.g. differently attenuated ones,
+        // but it's unclear which resource to act on if they name different ones.
+        let (Some(did), 1) = (dids.first().copied(), dids.len()) else {
+            tracing::error!(?dids, %ability, "Invocation UCAN capabilities are ambiguous.");
             return Err(AppError::new(
                 StatusCode::BAD_REQUEST,
                 Some(format!(
-                    "Invalid authorization. Expected resource to be DID, but got {}",
-                    cap.resource()
+                    "Ambiguous {ability} capabilities for several resources in invocation UCAN."
                 )),
             ));
         };
 
         let ability_str = ability.to_string();
 
-        let caps = self
-            .ucan
-            .capabilities_for(
+        // Checked before `capabilities_for` takes ownership of the ability,
+        // but only reported once the UCAN chain itself is known to be valid.
+        let invocation_check = matching
+            .iter()
+            .map(|cap| requirement.check(cap.caveat()))
+            .reduce(|first, next| first.or(next))
+            .unwrap_or(Ok(()));
+
+        // Proofs whose caveats don't allow the requirement are left out of the store,
+        // so only chains of proofs that do allow it can be validated.
+        let mut store = InMemoryStore::<RawCodec>::default();
+        let mut proof_check = Ok(());
+
+        for proof in &self.proofs {
+            // TODO(matheus23): rs-ucan should probably have support for revoked CIDs
+            if revocations.contains(&canonical_cid(proof)?) {
+                continue; // This CID was revoked.
+            }
+
+            let restriction = proof
+                .capabilities()
+                .filter(|proof_cap| {
+                    let same_resource = subject_did(proof_cap.resource()) == Some(did);
+                    let delegates_ability =
+                        proof_cap.ability().downcast_ref::<TopAbility>().is_some()
+                            || ability.is_valid_attenuation(proof_cap.ability());
+                    same_resource && delegates_ability
+                })
+                .try_for_each(|proof_cap| requirement.check(proof_cap.caveat()));
+
+            if let Err(reason) = restriction {
+                proof_check = Err(reason);
+                continue;
+            }
+
+            // TODO(matheus23): we assume SHA2-256 atm. The spec says to hash with all CID formats used in proofs >.<
+            store.write(Ipld::Bytes(proof.encode()?.as_bytes().to_vec()), None)?;
+        }
+
+        let volume = matching
+            .iter()
+            .find_map(|cap| cap.resource().downcast_ref::<VolumeResource>().cloned());
+
+        let verifiers = &DidVerifierMap::default();
+        let caps = match volume {
+            Some(volume) => {
+                self.ucan
+                    .capabilities_for(did, volume, ability, current_time, verifiers, &store)
+            }
+            None => self.ucan.capabilities_for(
                 did,
-                Did(did.clone()),
+                Did(did.to_string()),
                 ability,
                 current_time,
-                &DidVerifierMap::default(),
+                verifiers,
                 &store,
+            ),
+        };
+
+        // Without a chain of proofs allowing the requirement,
+        // the restricted proofs left out above are the reason why.
+        let caps = match (caps, proof_check) {
+            (Ok(caps), _) if !caps.is_empty() => caps,
+            (_, Err(reason)) => {
+                return Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    Some(format!("Invalid authorization. {reason}")),
+                ));
+            }
+            (caps, Ok(())) => caps.map_err(|e| AppError::new(StatusCode::FORBIDDEN, Some(e)))?,
+        };
+
+        let conn = &mut db::connect(&app_state.db_pool).await?;
+        if AccountRecord::is_pending_deletion(conn, did).await? {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                Some("Account is pending deletion"),
+            ));
+        }
+
+        if let Err(reason) = invocation_check {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                Some(format!("Invalid authorization. {reason}")),
+            ));
+        }
+
+        let cap = caps.first().ok_or_else(|| {
+            AppError::new(
+                StatusCode::FORBIDDEN,
+                Some(format!(
+                "Invalid authorization. Couldn't find proof for {ability_str} as issued from {did}"
+            )),
             )
-            .map_err(|e| AppError::new(StatusCode::FORBIDDEN, Some(e)))?;
-
-        // TODO(matheus23): Not yet handling caveats.
-        caps.first()
-            .ok_or_else(|| {
-                AppError::new(StatusCode::FORBIDDEN, Some(format!(
-                    "Invalid authorization. Couldn't find proof for {ability_str} as issued from {did}"
-                )))
-            })?
-            .resource()
-            .downcast_ref()
-            .cloned()
+        })?;
+
+        subject_did(cap.resource())
+            .map(|did| Did(did.to_string()))
             .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, Some("Invalid authorization. Something went wrong. Capability resource is not a DID.")))
     }
+
+    /// The smallest upload size limit that the `maxSize` caveats of the invocation
+    /// and its proofs put on pushes to the volume of given DID, if any.
+    pub fn volume_max_size(&self, did: &str) -> Option<u64> {
+        let proof_caps = self.proofs.iter().flat_map(|proof| proof.capabilities());
+
+        self.ucan
+            .capabilities()
+            .chain(proof_caps)
+            .filter(|cap| {
+                subject_did(cap.resource()) == Some(did)
+                    && VolumeAbility::Update.is_valid_attenuation(cap.ability())
+            })
+            .filter_map(|cap| cap.caveat().downcast_ref::<VolumeCaveat>()?.max_size)
+            .min()
+    }
+}
+
+/// The DID that a supported resource refers to:
+/// Either the DID itself, or the DID owning a `volume:<did>` resource.
+fn subject_did(resource: &dyn Resource) -> Option<&str> {
+    if let Some(Did(did)) = resource.downcast_ref() {
+        return Some(did);
+    }
+
+    resource
+        .downcast_ref::<VolumeResource>()
+        .map(VolumeResource::did)
+}
+
+impl CaveatRequirement {
+    /// Check whether given caveat allows this requirement.
+    /// Returns the reason if it doesn't.
+    pub fn check(&self, caveat: &dyn Caveat) -> Result<(), String> {
+        if caveat.downcast_ref::<EmptyCaveat>().is_some() {
+            return Ok(());
+        }
+
+        if let Some(caveat) = caveat.downcast_ref::<FissionCaveat>() {
+            let operation = match self {
+                _ if caveat.is_unrestricted() => return Ok(()),
+                Self::AccountOperation(operation) => Some(*operation),
+                Self::Unrestricted | Self::VolumeUpdate => None,
+            };
+
+            return match operation {
+                Some(operation) if caveat.allows(operation) => Ok(()),
+                Some(operation) => Err(format!(
+                    "Capability doesn't allow the {operation} operation"
+                )),
+                None => Err("Capability is restricted to specific operations".to_string()),
+            };
+        }
+
+        if let Some(caveat) = caveat.downcast_ref::<VolumeCaveat>() {
+            return match self {
+                _ if caveat.is_unrestricted() => Ok(()),
+                Self::VolumeUpdate => Ok(()),
+                _ => Err("Capability is restricted by volume caveats".to_string()),
+            };
+        }
+
+        // Other plugins' caveats are fine as long as they don't restrict anything,
+        // but fail closed on any restrictions we don't understand.
+        match caveat.serialize(serde_json::value::Serializer) {
+            Ok(serde_json::Value::Object(fields)) if fields.is_empty() => Ok(()),
+            _ => Err("Capability has unsupported caveats".to_string()),
+        }
+    }
+}
+
+impl From<AccountOperation> for CaveatRequirement {
+    fn from(operation: AccountOperation) -> Self {
+        Self::AccountOperation(operation)
+    }
 }
 
 //-------//
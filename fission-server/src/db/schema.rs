@@ -1,5 +1,17 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    account_emails (id) {
+        id -> Int4,
+        inserted_at -> Timestamp,
+        updated_at -> Timestamp,
+        account_id -> Int4,
+        email -> Text,
+        verified -> Bool,
+        is_primary -> Bool,
+    }
+}
+
 diesel::table! {
     accounts (id) {
         id -> Int4,
@@ -10,17 +22,25 @@ diesel::table! {
         updated_at -> Timestamp,
         volume_id -> Nullable<Int4>,
         handle -> Nullable<Text>,
+        username_skeleton -> Nullable<Text>,
+        handle_skeleton -> Nullable<Text>,
+        deleted_at -> Nullable<Timestamp>,
+        handle_verified_at -> Nullable<Timestamp>,
+        handle_unverified_since -> Nullable<Timestamp>,
+        volume_private -> Bool,
     }
 }
 
 diesel::table! {
     apps (id) {
         id -> Int4,
-        cid -> Nullable<Text>,
         inserted_at -> Timestamp,
         updated_at -> Timestamp,
         owner_id -> Int4,
         volume_id -> Nullable<Int4>,
+        did -> Text,
+        name -> Text,
+        volume_private -> Bool,
     }
 }
 
@@ -40,7 +60,40 @@ diesel::table! {
         inserted_at -> Timestamp,
         updated_at -> Timestamp,
         email -> Text,
-        code -> Text,
+        code_hash -> Text,
+        failed_attempts -> Int4,
+        link_used -> Bool,
+        link_purpose -> Nullable<Text>,
+        link_did -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    passkeys (id) {
+        id -> Int4,
+        inserted_at -> Timestamp,
+        updated_at -> Timestamp,
+        account_id -> Nullable<Int4>,
+        credential_id -> Text,
+        did -> Text,
+        passkey -> Jsonb,
+    }
+}
+
+diesel::table! {
+    private_volume_blocks (volume_id, raw_cid) {
+        volume_id -> Int4,
+        raw_cid -> Text,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Int4,
+        inserted_at -> Timestamp,
+        updated_at -> Timestamp,
+        account_id -> Int4,
+        code_hash -> Text,
     }
 }
 
@@ -50,6 +103,7 @@ diesel::table! {
         cid -> Text,
         iss -> Text,
         challenge -> Text,
+        resources -> Array<Text>,
     }
 }
 
@@ -65,26 +119,49 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webauthn_challenges (id) {
+        id -> Int4,
+        inserted_at -> Timestamp,
+        updated_at -> Timestamp,
+        challenge_id -> Text,
+        account_id -> Nullable<Int4>,
+        state -> Jsonb,
+    }
+}
+
 diesel::table! {
     volumes (id) {
         id -> Int4,
         inserted_at -> Timestamp,
         updated_at -> Timestamp,
         cid -> Text,
+        owner_did -> Nullable<Text>,
+        private -> Bool,
     }
 }
 
+diesel::joinable!(account_emails -> accounts (account_id));
 diesel::joinable!(accounts -> volumes (volume_id));
 diesel::joinable!(apps -> accounts (owner_id));
 diesel::joinable!(apps -> volumes (volume_id));
 diesel::joinable!(capabilities -> ucans (ucan_id));
+diesel::joinable!(passkeys -> accounts (account_id));
+diesel::joinable!(private_volume_blocks -> volumes (volume_id));
+diesel::joinable!(recovery_codes -> accounts (account_id));
+diesel::joinable!(webauthn_challenges -> accounts (account_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    account_emails,
     accounts,
     apps,
     capabilities,
     email_verifications,
+    passkeys,
+    private_volume_blocks,
+    recovery_codes,
     revocations,
     ucans,
     volumes,
+    webauthn_challenges,
 );
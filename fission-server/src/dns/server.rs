@@ -13,7 +13,7 @@ use bytes::Bytes;
 use hickory_server::{
     authority::{Authority, Catalog, ZoneType},
     proto::{
-        rr::{rdata, RData, Record, RecordSet, RecordType, RrKey},
+        rr::{rdata, LowerName, RData, Record, RecordSet, RecordType, RrKey},
         serialize::txt::RDataParser,
     },
     resolver::{config::NameServerConfigGroup, Name},
@@ -31,7 +31,7 @@ use tokio::sync::broadcast;
 pub struct DnsServer {
     /// The authority that handles the server's main `_did` DNS TXT record lookups
     pub server_did_authority: Arc<InMemoryAuthority>,
-    /// The authority that handles all user `_did` and `_dnslink` DNS TXT record lookups
+    /// The authority that handles all user and app `_did` and `_dnslink` DNS TXT record lookups
     pub user_did_authority: Arc<UserRecordsAuthority>,
     /// The catch-all authority that forwards requests to secondary nameservers
     pub forwarder: Arc<ForwardAuthority>,
@@ -96,6 +96,25 @@ impl DnsServer {
         Ok(rx.recv().await?)
     }
 
+    /// Describe which of the server's authorities answers queries for given name.
+    /// Names outside of the server's zones are forwarded to an upstream resolver.
+    pub fn resolver_for(&self, name: &Name) -> String {
+        let name = LowerName::from(name.clone());
+        let authorities = [
+            (self.server_did_authority.origin(), "server DID records"),
+            (self.user_did_authority.origin(), "user records"),
+            (self.user_did_authority.apps_origin(), "app records"),
+            (self.test_authority.origin(), "test records"),
+        ];
+
+        authorities
+            .into_iter()
+            .filter(|(origin, _)| origin.zone_of(&name))
+            .max_by_key(|(origin, _)| origin.num_labels())
+            .map(|(origin, records)| format!("fission-server ({records} for {origin})"))
+            .unwrap_or_else(|| "upstream resolver (Cloudflare)".to_string())
+    }
+
     fn setup_server_did_authority(
         settings: &Dns,
         server_did: String,
@@ -140,9 +159,11 @@ impl DnsServer {
         default_soa: rdata::SOA,
     ) -> Result<UserRecordsAuthority> {
         let origin_name = Name::parse(&settings.origin, Some(&Name::root()))?;
+        let apps_origin_name = Name::parse(&settings.apps_origin, Some(&Name::root()))?;
         Ok(UserRecordsAuthority::new(
             db_pool,
             origin_name.into(),
+            apps_origin_name.into(),
             default_soa,
             settings.default_ttl,
             settings.dnslink_ttl,
@@ -205,6 +226,10 @@ impl RequestHandler for DnsServer {
         // A catalog is very light-weight. Just a hashmap.
         // Shouldn't be a big cost to initialize for requests.
         let mut catalog = Catalog::new();
+        catalog.upsert(
+            self.user_did_authority.apps_origin().clone(),
+            Box::new(Arc::clone(&self.user_did_authority)),
+        );
         catalog.upsert(
             self.user_did_authority.origin().clone(),
             Box::new(Arc::clone(&self.user_did_authority)),
@@ -2,10 +2,11 @@
 
 use crate::{
     db::{self, Pool},
-    models::account::AccountRecord,
+    models::{account::AccountRecord, app::AppRecord},
 };
 use anyhow::Result;
 use async_trait::async_trait;
+use diesel::OptionalExtension;
 use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
 use hickory_server::{
     authority::{
@@ -25,10 +26,12 @@ use hickory_server::{
 use std::{borrow::Borrow, sync::Arc};
 
 /// DNS Request Handler for user DIDs of the form `{_did,_dnslink}.<username>.<server origin>`
+/// and app records of the form `{_did,_dnslink}.<app name>.<apps origin>`
 #[derive(Debug)]
 pub struct UserRecordsAuthority {
     db_pool: Pool,
     origin: LowerName,
+    apps_origin: LowerName,
     default_soa: SOA,
     default_ttl: u32,
     dnslink_ttl: u32,
@@ -39,6 +42,7 @@ impl UserRecordsAuthority {
     pub fn new(
         db_pool: Pool,
         origin: LowerName,
+        apps_origin: LowerName,
         default_soa: SOA,
         default_ttl: u32,
         dnslink_ttl: u32,
@@ -46,26 +50,77 @@ impl UserRecordsAuthority {
         UserRecordsAuthority {
             db_pool,
             origin,
+            apps_origin,
             default_soa,
             default_ttl,
             dnslink_ttl,
         }
     }
 
-    async fn db_lookup_user_did(&self, username: String) -> Result<String> {
+    /// The origin under which app records are served
+    pub fn apps_origin(&self) -> &LowerName {
+        &self.apps_origin
+    }
+
+    /// Look up the DID of the user or app with given subdomain.
+    ///
+    /// If the users and apps origin are the same, usernames take precedence.
+    async fn db_lookup_did(&self, subdomain: String, base: &Name) -> Result<Option<String>> {
         let conn = &mut db::connect(&self.db_pool).await?;
-        let account = AccountRecord::find_by_username(conn, username).await?;
-        Ok(account.did)
+
+        if *base == self.origin.clone().into() {
+            if let Some(account) = AccountRecord::find_by_username(conn, &subdomain)
+                .await
+                .optional()?
+            {
+                return Ok(Some(account.did));
+            }
+        }
+
+        if *base == self.apps_origin.clone().into() {
+            if let Some(app) = AppRecord::find_by_name(conn, &subdomain).await.optional()? {
+                return Ok(Some(app.did));
+            }
+        }
+
+        Ok(None)
     }
 
-    async fn db_lookup_user_dnslink(&self, username: String) -> Result<Option<String>> {
+    /// Look up the DNSLink of the user or app volume with given subdomain.
+    ///
+    /// If the users and apps origin are the same, usernames take precedence.
+    /// Private volumes don't have a DNSLink.
+    async fn db_lookup_dnslink(&self, subdomain: String, base: &Name) -> Result<Option<String>> {
+        let is_users_base = *base == self.origin.clone().into();
+        let is_apps_base = *base == self.apps_origin.clone().into();
+
         let conn = &mut db::connect(&self.db_pool).await?;
         conn.transaction(|conn| {
             async move {
-                let account = AccountRecord::find_by_username(conn, username).await?;
-                Ok(account
-                    .get_volume(conn)
-                    .await?
+                let account = if is_users_base {
+                    AccountRecord::find_by_username(conn, &subdomain)
+                        .await
+                        .optional()?
+                } else {
+                    None
+                };
+
+                // An account without a volume still shadows an app of the same name,
+                // just like for _did records
+                let volume = match account {
+                    Some(account) => account.get_volume(conn).await?,
+                    None if is_apps_base => {
+                        match AppRecord::find_by_name(conn, &subdomain).await.optional()? {
+                            Some(app) => app.get_volume(conn).await?,
+                            None => None,
+                        }
+                    }
+                    None => None,
+                };
+
+                // Publishing private volumes' CIDs would let anyone pull them
+                Ok(volume
+                    .filter(|volume_record| !volume_record.private)
                     .map(|volume_record| format!("dnslink=/ipfs/{}", volume_record.cid)))
             }
             .scope_boxed()
@@ -122,11 +177,12 @@ impl Authority for UserRecordsAuthority {
             .map_err(|e| LookupError::ResolveError(ResolveError::from(e)))?;
 
         // base needs to be computer.name, if the request was _did.alice.computer.name
-        if base != self.origin().clone().into() {
+        // or the apps origin, if the request was e.g. _dnslink.friendly-pink-dragon.fission.app
+        if base != self.origin().clone().into() && base != self.apps_origin().clone().into() {
             return Ok(AuthLookup::Empty);
         }
 
-        let username = String::from_utf8(user_bytes.to_vec()).map_err(|e| {
+        let subdomain = String::from_utf8(user_bytes.to_vec()).map_err(|e| {
             LookupError::ResolveError(
                 format!("Failed decoding non-utf8 subdomain segment: {e}").into(),
             )
@@ -135,10 +191,14 @@ impl Authority for UserRecordsAuthority {
         match first {
             // Serve requests for e.g. _did.alice.computer.name
             Some(b"_did") => {
-                tracing::info!(%name, %username, "Looking up DID record");
+                tracing::info!(%name, %subdomain, "Looking up DID record");
 
-                let account_did = match self.db_lookup_user_did(username).await {
-                    Ok(account_did) => account_did,
+                let did = match self.db_lookup_did(subdomain, &base).await {
+                    Ok(Some(did)) => did,
+                    Ok(None) => {
+                        tracing::debug!("No account or app found during _did DNS entry lookup");
+                        return Ok(AuthLookup::Empty);
+                    }
                     Err(err) => {
                         tracing::debug!(?err, "Account lookup failed during _did DNS entry lookup");
                         return Ok(AuthLookup::Empty);
@@ -150,7 +210,7 @@ impl Authority for UserRecordsAuthority {
                         lookup_options,
                         Arc::new(did_record_set(
                             name,
-                            account_did,
+                            did,
                             self.default_ttl,
                             self.default_soa.serial(),
                         )),
@@ -159,11 +219,12 @@ impl Authority for UserRecordsAuthority {
                 ))
             }
             // Serve requests for volumes e.g. _dnslink.alice.computer.name
+            // or _dnslink.friendly-pink-dragon.fission.app
             Some(b"_dnslink") => {
-                let account_dnslink = match self.db_lookup_user_dnslink(username).await {
-                    Ok(Some(account_dnslink)) => account_dnslink,
+                let dnslink = match self.db_lookup_dnslink(subdomain, &base).await {
+                    Ok(Some(dnslink)) => dnslink,
                     Ok(None) => {
-                        // This is perfectly normal. The user just doesn't have an associated volume.
+                        // This is perfectly normal. The user or app just doesn't have an associated volume.
                         return Ok(AuthLookup::Empty);
                     }
                     Err(err) => {
@@ -180,7 +241,7 @@ impl Authority for UserRecordsAuthority {
                         lookup_options,
                         Arc::new(did_record_set(
                             name,
-                            account_dnslink,
+                            dnslink,
                             self.dnslink_ttl,
                             self.default_soa.serial(),
                         )),
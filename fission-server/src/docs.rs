@@ -3,13 +3,21 @@
 use crate::{
     error::AppError,
     extract::authority_addon::UcanAddon,
-    models::account::AccountAndAuth,
-    routes::{account, auth, capability_indexing, health, ping, revocations},
+    models::{account::AccountAndAuth, app::AppAndAuth},
+    routes::{
+        account, app, auth, capability_indexing, health, ping, revocations, volume, webauthn,
+    },
 };
 use fission_core::{
     common::{
-        Account, AccountCreationRequest, AccountLinkRequest, EmailVerifyRequest,
-        MemberNumberResponse, SuccessResponse, UcansResponse,
+        Account, AccountCreationRequest, AccountEmail, AccountEmailVerifyRequest,
+        AccountEmailsResponse, AccountExport, AccountLinkRequest, AccountPasskey,
+        AccountRecoveryRequest, AccountRestoreRequest, App, AppsResponse, Device, DevicesResponse,
+        DnsLookupOutcome, DnsTxtDiagnostic, EmailChangeRequest, EmailVerifyRequest,
+        HandleCheckResponse, MemberNumberResponse, PasskeyChallengeResponse, PasskeyFinishRequest,
+        PasskeyRegistrationResponse, PasskeyStartRequest, RecoveryCodeResponse, SuccessResponse,
+        UcansResponse, UsernameAvailableResponse, VerificationMode, VerificationPurpose,
+        VolumeVisibilityRequest, WellKnownDiagnostic, WellKnownOutcome,
     },
     revocation::Revocation,
 };
@@ -22,13 +30,39 @@ use utoipa::OpenApi;
         health::healthcheck,
         ping::get,
         auth::request_token,
+        auth::open_link,
+        auth::confirm_link,
+        webauthn::start_registration,
+        webauthn::finish_registration,
+        webauthn::start_login,
+        webauthn::finish_login,
         account::create_account,
         account::link_account,
         account::get_account,
         account::get_member_number,
+        account::export_account,
         account::patch_username,
+        account::get_username_available,
         account::patch_handle,
+        account::check_handle_setup,
+        account::request_email_change,
+        account::patch_email,
+        account::list_emails,
+        account::add_email,
+        account::verify_email,
+        account::set_primary_email,
+        account::remove_email,
+        account::list_devices,
+        account::revoke_device,
         account::delete_account,
+        account::restore_account,
+        account::create_recovery_code,
+        account::recover_account,
+        app::create_app,
+        app::list_apps,
+        app::push_app_volume_cid,
+        app::delete_app,
+        volume::patch_volume_visibility,
         revocations::post_revocation,
         capability_indexing::get_capabilities,
     ),
@@ -36,13 +70,40 @@ use utoipa::OpenApi;
         schemas(
             AppError,
             EmailVerifyRequest,
+            VerificationMode,
+            VerificationPurpose,
             SuccessResponse,
             MemberNumberResponse,
+            UsernameAvailableResponse,
             Account,
+            AccountExport,
+            AccountPasskey,
             AccountCreationRequest,
             AccountLinkRequest,
+            AccountEmail,
+            AccountEmailsResponse,
+            AccountEmailVerifyRequest,
+            Device,
+            DevicesResponse,
+            HandleCheckResponse,
+            DnsTxtDiagnostic,
+            DnsLookupOutcome,
+            WellKnownDiagnostic,
+            WellKnownOutcome,
+            AccountRestoreRequest,
+            AccountRecoveryRequest,
+            RecoveryCodeResponse,
+            EmailChangeRequest,
+            PasskeyStartRequest,
+            PasskeyChallengeResponse,
+            PasskeyFinishRequest,
+            PasskeyRegistrationResponse,
             UcansResponse,
             AccountAndAuth,
+            App,
+            AppsResponse,
+            AppAndAuth,
+            VolumeVisibilityRequest,
             Revocation,
             health::HealthcheckResponse
         )
@@ -0,0 +1,255 @@
+//! Plain-text & HTML bodies for verification emails
+
+use crate::{settings, setups::Verification};
+use anyhow::{Context, Result};
+use lettre::{
+    message::{header::ContentType, Mailbox, MessageBuilder, MultiPart},
+    Message,
+};
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+/// Subject of the notice sent to an account's previous email address
+pub const EMAIL_CHANGED_SUBJECT: &str = "Your Fission email address was changed";
+
+/// The plain-text body of the notice sent to an account's previous email address
+pub fn email_changed_text(new_email: &str) -> String {
+    format!(
+        "The email address of your Fission account was changed to {new_email}.\n\n\
+        If you didn't make this change, please contact support@fission.codes."
+    )
+}
+
+/// The rendered bodies of an email
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailBody {
+    /// The plain-text alternative
+    pub text: String,
+    /// The HTML alternative
+    pub html: String,
+}
+
+/// Templates for verification emails.
+///
+/// Code templates can refer to the code via `{{code}}`, link templates to the link via `{{link}}`.
+#[derive(Debug, Clone)]
+pub struct EmailTemplates {
+    code_text: String,
+    code_html: String,
+    link_text: String,
+    link_html: String,
+}
+
+impl Default for EmailTemplates {
+    fn default() -> Self {
+        Self {
+            code_text: include_str!("../templates/email/code.txt").to_string(),
+            code_html: include_str!("../templates/email/code.html").to_string(),
+            link_text: include_str!("../templates/email/link.txt").to_string(),
+            link_html: include_str!("../templates/email/link.html").to_string(),
+        }
+    }
+}
+
+impl EmailTemplates {
+    /// Load templates from a directory with `code.txt`, `code.html`, `link.txt` & `link.html`.
+    /// Files that don't exist fall back to the built-in templates.
+    pub fn load(dir: &Path) -> Result<Self> {
+        Self::default().overridden_from(dir)
+    }
+
+    /// Replace templates with the ones from given directory, where they exist
+    pub fn overridden_from(self, dir: &Path) -> Result<Self> {
+        Ok(Self {
+            code_text: load_or(dir, "code.txt", self.code_text)?,
+            code_html: load_or(dir, "code.html", self.code_html)?,
+            link_text: load_or(dir, "link.txt", self.link_text)?,
+            link_html: load_or(dir, "link.html", self.link_html)?,
+        })
+    }
+
+    /// Render the email bodies for given verification
+    pub fn render(&self, verification: &Verification) -> EmailBody {
+        match verification {
+            Verification::Code(code) => EmailBody {
+                text: fill(&self.code_text, "code", code),
+                html: fill(&self.code_html, "code", &escape_html(code)),
+            },
+            Verification::Link(url) => EmailBody {
+                text: fill(&self.link_text, "link", url),
+                html: fill(&self.link_html, "link", &escape_html(url)),
+            },
+        }
+    }
+}
+
+/// Builds complete verification emails, for providers that don't template emails themselves
+#[derive(Debug, Clone)]
+pub struct EmailComposer {
+    from: Mailbox,
+    default: Arc<LocalizedEmail>,
+    locales: Arc<BTreeMap<String, LocalizedEmail>>,
+}
+
+#[derive(Debug, Clone)]
+struct LocalizedEmail {
+    subject: String,
+    templates: EmailTemplates,
+}
+
+impl EmailComposer {
+    /// Create a composer with the sender & subject from the `[email]` settings.
+    /// Translations are added via `with_locale`.
+    pub fn new(settings: &settings::Email, templates: EmailTemplates) -> Result<Self> {
+        Ok(Self {
+            from: Mailbox::new(
+                Some(settings.from_name.clone()),
+                settings.from_address.parse()?,
+            ),
+            default: Arc::new(LocalizedEmail {
+                subject: settings.subject.clone(),
+                templates,
+            }),
+            locales: Default::default(),
+        })
+    }
+
+    /// Use given subject & templates for verification emails requested in `locale`
+    pub fn with_locale(mut self, locale: &str, subject: String, templates: EmailTemplates) -> Self {
+        Arc::make_mut(&mut self.locales).insert(
+            normalize_locale(locale),
+            LocalizedEmail { subject, templates },
+        );
+        self
+    }
+
+    /// Compose the email with the code or link that verifies `email`
+    pub fn verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<Message> {
+        let localized = find_locale(&self.locales, locale).unwrap_or(self.default.as_ref());
+        let body = localized.templates.render(verification);
+
+        Ok(self
+            .builder(email, &localized.subject)?
+            .multipart(MultiPart::alternative_plain_html(body.text, body.html))?)
+    }
+
+    /// Compose the notice to an account's previous email address that it was changed
+    pub fn email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<Message> {
+        Ok(self
+            .builder(old_email, EMAIL_CHANGED_SUBJECT)?
+            .header(ContentType::TEXT_PLAIN)
+            .body(email_changed_text(new_email))?)
+    }
+
+    fn builder(&self, to: &str, subject: &str) -> Result<MessageBuilder> {
+        Ok(Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject))
+    }
+}
+
+fn load_or(dir: &Path, file_name: &str, default: String) -> Result<String> {
+    let path = dir.join(file_name);
+    if !path.exists() {
+        return Ok(default);
+    }
+    std::fs::read_to_string(&path)
+        .with_context(|| format!("Reading email template {}", path.display()))
+}
+
+/// Find the closest translation for a requested language tag:
+/// `pt-BR` matches a `pt-br` translation, or else a `pt` translation.
+/// Keys need to be normalized via `normalize_locale`.
+pub fn find_locale<'a, T>(locales: &'a BTreeMap<String, T>, locale: Option<&str>) -> Option<&'a T> {
+    let mut locale = normalize_locale(locale?);
+    loop {
+        if let Some(found) = locales.get(&locale) {
+            return Some(found);
+        }
+        let (more_general, _) = locale.rsplit_once('-')?;
+        locale = more_general.to_string();
+    }
+}
+
+/// Language tags are case-insensitive, and `_` is commonly used instead of `-`
+pub fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_lowercase().replace('_', "-")
+}
+
+fn fill(template: &str, variable: &str, value: &str) -> String {
+    template.replace(&format!("{{{{{variable}}}}}"), value)
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lettre::message::header::Subject;
+
+    #[test]
+    fn test_render_default_templates() {
+        let templates = EmailTemplates::default();
+
+        let body = templates.render(&Verification::Code("012345".to_string()));
+        assert!(body.text.contains("012345"));
+        assert!(body.html.contains("012345"));
+        assert!(!body.text.contains("{{"));
+
+        let body = templates.render(&Verification::Link(
+            "https://example.com/link?a=1&b=2".to_string(),
+        ));
+        assert!(body.text.contains("https://example.com/link?a=1&b=2"));
+        assert!(body
+            .html
+            .contains(r#"href="https://example.com/link?a=1&amp;b=2""#));
+    }
+
+    #[test]
+    fn test_find_locale_falls_back() {
+        let locales = BTreeMap::from([("de".to_string(), "de"), ("pt-br".to_string(), "pt-br")]);
+
+        assert_eq!(find_locale(&locales, Some("de")), Some(&"de"));
+        assert_eq!(find_locale(&locales, Some("de-AT")), Some(&"de"));
+        assert_eq!(find_locale(&locales, Some("pt_BR")), Some(&"pt-br"));
+        assert_eq!(find_locale(&locales, Some("pt-PT")), None);
+        assert_eq!(find_locale(&locales, Some("fr")), None);
+        assert_eq!(find_locale(&locales, None), None);
+    }
+
+    #[test]
+    fn test_compose_localized_subject() -> anyhow::Result<()> {
+        let composer = EmailComposer::new(&Default::default(), EmailTemplates::default())?
+            .with_locale(
+                "de",
+                "Dein Fission-Bestätigungscode".to_string(),
+                EmailTemplates::default(),
+            );
+        let code = Verification::Code("012345".to_string());
+
+        let message = composer.verification("oedipa@trystero.com", &code, Some("de-DE"))?;
+        assert_eq!(
+            message.headers().get::<Subject>(),
+            Some(Subject::from("Dein Fission-Bestätigungscode".to_string()))
+        );
+
+        let message = composer.verification("oedipa@trystero.com", &code, Some("fr"))?;
+        assert_eq!(
+            message.headers().get::<Subject>(),
+            Some(Subject::from("Your Fission Verification Code".to_string()))
+        );
+
+        Ok(())
+    }
+}
@@ -7,8 +7,12 @@ use axum::{
     Json,
 };
 
-use http::header::ToStrError;
+use http::{
+    header::{ToStrError, RETRY_AFTER},
+    HeaderValue,
+};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use ulid::Ulid;
 use utoipa::ToSchema;
 use validator::ValidationErrors;
@@ -26,6 +30,9 @@ pub type AppResult<T> = std::result::Result<T, AppError>;
 /// 2. Set the title to the `canonical_reason` of the status code.
 ///    According to spec, this should NOT change over time.
 /// 3. For unrecoverable errors, encode the detail as the to_string of the error
+/// 4. For errors that clients may want to handle specifically, set an
+///    application-specific `code` that doesn't change over time.
+/// 5. Put machine-readable details, e.g. diagnostics, into `meta`.
 ///
 /// Other fields not currently captured (but can be added)
 ///
@@ -33,7 +40,6 @@ pub type AppResult<T> = std::result::Result<T, AppError>;
 /// - links - a link object with further information about the problem
 /// - source - a JSON pointer indicating a problem in the request json OR
 ///   a parameter specifying a problematic query parameter
-/// - meta - a meta object containing arbitrary information about the error
 #[derive(ToSchema, thiserror::Error, Eq, PartialEq, Debug, Deserialize, Serialize)]
 pub struct AppError {
     #[schema(value_type = u16, example = 404)]
@@ -43,6 +49,15 @@ pub struct AppError {
     pub(crate) detail: Option<String>,
     #[schema(example = "Entity with id 123 not found")]
     pub(crate) title: Option<String>,
+    #[schema(example = "username-denied")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) code: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) meta: Option<serde_json::Value>,
+    /// Seconds until the request may be retried, sent as a `Retry-After` header
+    #[serde(skip)]
+    pub(crate) retry_after: Option<u64>,
 }
 
 impl AppError {
@@ -52,9 +67,32 @@ impl AppError {
             status: status_code,
             title: Self::canonical_reason_to_string(&status_code),
             detail: message.map(|m| m.to_string()),
+            code: None,
+            meta: None,
+            retry_after: None,
         }
     }
 
+    /// Attach an application-specific error code
+    pub fn with_code(mut self, code: impl ToString) -> AppError {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    /// Attach machine-readable details about the error
+    pub fn with_meta(mut self, meta: impl Serialize) -> AppError {
+        self.meta = serde_json::to_value(meta).ok();
+        self
+    }
+
+    /// Tell clients when to retry the request via a `Retry-After` header.
+    /// Rounds up to whole seconds.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> AppError {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        self.retry_after = Some(secs.max(1));
+        self
+    }
+
     /// [AppError] for [StatusCode::NOT_FOUND].
     pub fn not_found(id: Ulid) -> AppError {
         Self::new(
@@ -88,8 +126,15 @@ impl From<AppError> for (StatusCode, Json<ErrorResponse>) {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let retry_after = self.retry_after;
         let error_response: (StatusCode, Json<ErrorResponse>) = self.into();
-        error_response.into_response()
+        let mut response = error_response.into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
 
@@ -335,6 +380,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_retry_after_header() {
+        let response = AppError::new(StatusCode::TOO_MANY_REQUESTS, None::<String>)
+            .with_retry_after(Duration::from_millis(1500))
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_json_api_error_response() -> TestResult {
         // verify that our json api response complies with the standard
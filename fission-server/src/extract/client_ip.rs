@@ -0,0 +1,32 @@
+//! Client IP extractor
+
+use crate::{app_state::AppState, error::AppError, setups::ServerSetup};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use std::net::{IpAddr, SocketAddr};
+
+/// The IP address of the client that made the request.
+///
+/// This is the peer address, unless the peer is one of the configured trusted proxies,
+/// in which case it's taken from the `X-Forwarded-For` header.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: ServerSetup> FromRequestParts<AppState<S>> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<S>,
+    ) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await?;
+
+        Ok(ClientIp(
+            state.rate_limiter.client_ip(peer.ip(), &parts.headers),
+        ))
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod authority;
 pub mod authority_addon;
+pub mod client_ip;
 pub mod dag_cbor;
 pub mod doh;
 pub mod json;
@@ -0,0 +1,275 @@
+//! Verifying that an account controls the domain of its custom handle
+
+use crate::{
+    app_state::AppState,
+    db,
+    dns::server::DnsServer,
+    extract::doh::{encode_query_as_request, DnsQuery},
+    models::account::AccountRecord,
+    settings,
+    setups::{HttpClient, ServerSetup},
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use fission_core::{
+    common::{
+        DnsLookupOutcome, DnsTxtDiagnostic, HandleCheckResponse, WellKnownDiagnostic,
+        WellKnownOutcome,
+    },
+    username::Handle,
+};
+use hickory_server::proto::{
+    op::ResponseCode,
+    rr::{Name, RecordType},
+    serialize::binary::BinDecodable,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    str::FromStr,
+    time::Duration,
+};
+
+/// DNS lookups that take longer than this are reported as timed out
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How an account proved that it controls a handle's domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleProof {
+    /// A `_did.<handle>` TXT record set to the account DID
+    DnsTxt,
+    /// A file at `https://<handle>/.well-known/did.txt` containing the account DID
+    WellKnown,
+}
+
+/// The URL of the file that can prove control over a handle's domain
+/// for users that can't edit their DNS records.
+pub fn well_known_url(handle: &Handle) -> String {
+    format!("https://{handle}/.well-known/did.txt")
+}
+
+/// Check whether the handle's domain points to given DID.
+///
+/// DNS is checked first, then the well-known file. Returns `None` if neither matches.
+/// Fails if the DNS lookup failed, as opposed to not finding a matching record.
+pub async fn verify_handle<S: ServerSetup>(
+    state: &AppState<S>,
+    handle: &Handle,
+    did: &str,
+) -> Result<Option<HandleProof>> {
+    let dns = lookup_did_txt(&state.dns_server, handle, did).await;
+    if dns.matches {
+        return Ok(Some(HandleProof::DnsTxt));
+    }
+
+    if fetch_well_known(&state.http_client, handle, did)
+        .await
+        .matches
+    {
+        return Ok(Some(HandleProof::WellKnown));
+    }
+
+    match dns.outcome {
+        DnsLookupOutcome::ServFail | DnsLookupOutcome::Timeout | DnsLookupOutcome::Error => {
+            bail!(
+                "Looking up {} failed with {:?}: {}",
+                dns.name,
+                dns.outcome,
+                dns.error.unwrap_or_default()
+            )
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Run all checks for whether the handle's domain points to given DID and report their details,
+/// so users can debug their setup.
+pub async fn check_handle<S: ServerSetup>(
+    state: &AppState<S>,
+    handle: &Handle,
+    did: &str,
+) -> HandleCheckResponse {
+    let (dns, well_known) = tokio::join!(
+        lookup_did_txt(&state.dns_server, handle, did),
+        fetch_well_known(&state.http_client, handle, did),
+    );
+
+    HandleCheckResponse {
+        handle: handle.to_string(),
+        did: did.to_string(),
+        verified: dns.matches || well_known.matches,
+        dns,
+        well_known,
+    }
+}
+
+/// What a pass of `reverify_handles` did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReverificationSummary {
+    /// Handles that still point to their account
+    pub verified: usize,
+    /// Handles that were newly marked as unverified
+    pub unverified: usize,
+    /// Handles that were unverified for longer than the grace period and got cleared
+    pub cleared: usize,
+}
+
+/// Re-verify all handles whose last verification is older than the configured TTL.
+///
+/// Handles that don't point to their account anymore are marked as unverified,
+/// and cleared once they stayed unverified for the grace period.
+/// Handles that couldn't be checked, e.g. due to DNS errors, are left alone until the next pass.
+/// Doesn't hold on to a database connection while checking handles.
+pub async fn reverify_handles<S: ServerSetup>(
+    state: &AppState<S>,
+    settings: &settings::Handles,
+) -> Result<ReverificationSummary> {
+    let now = Utc::now().naive_utc();
+    let cutoff = now - chrono::Duration::from_std(settings.verification_ttl())?;
+    let grace_period = chrono::Duration::from_std(settings.unverified_grace_period())?;
+
+    let accounts = {
+        let conn = &mut db::connect(&state.db_pool).await?;
+        AccountRecord::find_handles_to_reverify(conn, cutoff).await?
+    };
+
+    let mut summary = ReverificationSummary::default();
+
+    for account in accounts {
+        let Some(handle_str) = account.handle.as_deref() else {
+            continue;
+        };
+
+        let verified = match Handle::from_str(handle_str) {
+            Ok(handle) => match verify_handle(state, &handle, &account.did).await {
+                Ok(proof) => proof.is_some(),
+                Err(e) => {
+                    tracing::warn!(handle = handle_str, ?e, "Couldn't re-verify handle");
+                    continue;
+                }
+            },
+            // Stored handles are validated, but if one slips through it can't be verified
+            Err(_) => false,
+        };
+
+        // Checks can take seconds, so a connection is only taken from the pool for the update.
+        // Updates are skipped if the handle changed since it was loaded.
+        let conn = &mut db::connect(&state.db_pool).await?;
+
+        if verified {
+            account.mark_handle_verified(conn).await?;
+            summary.verified += 1;
+            continue;
+        }
+
+        match account.handle_unverified_since {
+            None => {
+                tracing::info!(handle = handle_str, "Handle failed re-verification");
+                account.mark_handle_unverified(conn).await?;
+                summary.unverified += 1;
+            }
+            Some(since) if since + grace_period <= now => {
+                tracing::info!(handle = handle_str, %since, "Clearing unverified handle");
+                account.clear_handle(conn).await?;
+                summary.cleared += 1;
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(summary)
+}
+
+async fn lookup_did_txt(dns_server: &DnsServer, handle: &Handle, did: &str) -> DnsTxtDiagnostic {
+    let name = format!("_did.{handle}");
+    let resolver = match Name::from_ascii(&name) {
+        Ok(parsed) => dns_server.resolver_for(&parsed),
+        Err(_) => "none".to_string(),
+    };
+
+    let lookup = tokio::time::timeout(DNS_TIMEOUT, query_txt(dns_server, &name)).await;
+
+    let mut diagnostic = DnsTxtDiagnostic {
+        name,
+        resolver,
+        outcome: DnsLookupOutcome::Error,
+        values: Vec::new(),
+        matches: false,
+        error: None,
+    };
+
+    let response = match lookup {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            diagnostic.error = Some(format!("{e:#}"));
+            return diagnostic;
+        }
+        Err(_) => {
+            diagnostic.outcome = DnsLookupOutcome::Timeout;
+            return diagnostic;
+        }
+    };
+
+    diagnostic.values = response
+        .answer
+        .into_iter()
+        .filter(|answer| answer.record_type == u16::from(RecordType::TXT))
+        .map(|answer| answer.data)
+        .collect();
+    diagnostic.matches = diagnostic.values.iter().any(|value| value == did);
+    diagnostic.outcome = match ResponseCode::from(response.status as u16) {
+        ResponseCode::NoError if diagnostic.values.is_empty() => DnsLookupOutcome::NoRecords,
+        ResponseCode::NoError => DnsLookupOutcome::Found,
+        ResponseCode::NXDomain => DnsLookupOutcome::NxDomain,
+        ResponseCode::ServFail => DnsLookupOutcome::ServFail,
+        code => {
+            diagnostic.error = Some(format!("Unexpected response code {code:?}"));
+            DnsLookupOutcome::Error
+        }
+    };
+
+    tracing::debug!(?diagnostic, "Looked up _did TXT record");
+
+    diagnostic
+}
+
+async fn query_txt(dns_server: &DnsServer, name: &str) -> Result<fission_core::dns::Response> {
+    // TODO Better APIs. It should be easier to ask our own DNS server some Qs
+    let localhost_dns_v4 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 53));
+
+    let message_bytes = dns_server
+        .answer_request(encode_query_as_request(
+            DnsQuery::new(name.to_string(), RecordType::TXT),
+            localhost_dns_v4,
+        )?)
+        .await?;
+
+    let message = hickory_server::proto::op::Message::from_bytes(message_bytes.as_ref())
+        .map_err(|e| anyhow!(e))?;
+
+    fission_core::dns::Response::from_message(message)
+}
+
+async fn fetch_well_known(
+    http_client: &impl HttpClient,
+    handle: &Handle,
+    did: &str,
+) -> WellKnownDiagnostic {
+    let url = well_known_url(handle);
+
+    let (outcome, matches, error) = match http_client.get_text(&url).await {
+        Ok(Some(body)) => (WellKnownOutcome::Found, body.trim() == did, None),
+        Ok(None) => (WellKnownOutcome::NotFound, false, None),
+        Err(e) => {
+            // The domain is out of our control, so failing to reach it isn't a server error
+            tracing::debug!(%url, error = %e, "Couldn't fetch well-known DID file");
+            (WellKnownOutcome::Error, false, Some(format!("{e:#}")))
+        }
+    };
+
+    WellKnownDiagnostic {
+        url,
+        outcome,
+        matches,
+        error,
+    }
+}
@@ -12,17 +12,22 @@ pub mod authority;
 pub mod db;
 pub mod dns;
 pub mod docs;
+pub mod email_templates;
 pub mod error;
 pub mod extract;
+pub mod handle_verification;
 pub mod headers;
 pub mod metrics;
 pub mod middleware;
 pub mod models;
+pub mod passkeys;
+pub mod rate_limit;
 pub mod router;
 pub mod routes;
 pub mod settings;
 pub mod setups;
 pub mod tracer;
 pub mod tracing_layers;
+pub mod username_denylist;
 
 pub mod test_utils;
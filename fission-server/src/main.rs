@@ -13,14 +13,20 @@ use fission_server::{
     db::{self, Pool},
     dns::server::DnsServer,
     docs::ApiDoc,
+    handle_verification,
     metrics::{process, prom::setup_metrics_recorder},
     middleware::{self, request_ulid::MakeRequestUlid, runtime},
+    models::{
+        account::AccountRecord, passkey::PasskeyRecord, volume::Volume,
+        webauthn_challenge::WebauthnChallengeRecord,
+    },
+    passkeys::build_webauthn,
     router,
     routes::{fallback::notfound_404, ws::WsPeerMap},
     settings::{AppEnvironment, Otel, Settings},
     setups::{
         local::{LocalSetup, WebsocketCodeSender},
-        prod::{EmailVerificationCodeSender, IpfsHttpApiDatabase, ProdSetup},
+        prod::{IpfsHttpApiDatabase, ProdSetup, ProdVerificationCodeSender},
         ServerSetup,
     },
     test_utils::ephermeral_db::{create_ephermeral_db, destroy_ephermeral_db},
@@ -30,6 +36,7 @@ use fission_server::{
         metrics_layer::{MetricsLayer, METRIC_META_PREFIX},
         storage_layer::StorageLayer,
     },
+    username_denylist::UsernameDenylist,
 };
 use http::header;
 use metrics_exporter_prometheus::PrometheusHandle;
@@ -151,6 +158,16 @@ async fn main() -> Result<()> {
         )
     };
 
+    let backfilled = AccountRecord::backfill_skeletons(&mut db::connect(&db_pool).await?).await?;
+    if backfilled > 0 {
+        info!(
+            subject = "accounts",
+            category = "init",
+            backfilled,
+            "backfilled username and handle skeletons",
+        );
+    }
+
     let server_keypair = load_keypair(&settings, cli.gen_key_if_needed).await?;
 
     match settings.server.environment {
@@ -181,6 +198,27 @@ async fn run_with_app_state<S: ServerSetup>(
         cancellation_token.clone(),
     ));
 
+    let purge_task = tokio::spawn(purge_deleted_accounts(
+        app_state.clone(),
+        settings.clone(),
+        cancellation_token.clone(),
+    ));
+
+    let reverify_task = tokio::spawn(reverify_handles(
+        app_state.clone(),
+        settings.clone(),
+        cancellation_token.clone(),
+    ));
+
+    let webauthn_task = tokio::spawn(purge_webauthn_leftovers(
+        app_state.clone(),
+        settings.clone(),
+        cancellation_token.clone(),
+    ));
+
+    // Volumes that were made private before their blocks were indexed
+    tokio::spawn(index_private_volumes(app_state.clone()));
+
     let app_server = tokio::spawn(serve_app(
         app_state,
         settings.clone(),
@@ -232,7 +270,14 @@ async fn run_with_app_state<S: ServerSetup>(
         exit(130)
     });
 
-    let (metrics, app, dns) = tokio::try_join!(metrics_server, app_server, dns_server)?;
+    let (metrics, app, dns, purge, reverify, webauthn) = tokio::try_join!(
+        metrics_server,
+        app_server,
+        dns_server,
+        purge_task,
+        reverify_task,
+        webauthn_task
+    )?;
 
     if let Err(e) = metrics {
         log::error!("metrics server crashed: {}", e);
@@ -246,6 +291,18 @@ async fn run_with_app_state<S: ServerSetup>(
         log::error!("dns server crashed: {}", e);
     }
 
+    if let Err(e) = purge {
+        log::error!("account purge task crashed: {}", e);
+    }
+
+    if let Err(e) = reverify {
+        log::error!("handle re-verification task crashed: {}", e);
+    }
+
+    if let Err(e) = webauthn {
+        log::error!("WebAuthn cleanup task crashed: {}", e);
+    }
+
     if let Some(db_name) = ephemeral_db {
         let mut base_url = Url::from_str(&settings.database.url)?;
         base_url.set_path("");
@@ -279,6 +336,113 @@ async fn serve_metrics(
     Ok(())
 }
 
+async fn purge_deleted_accounts<S: ServerSetup>(
+    app_state: AppState<S>,
+    settings: Settings,
+    token: CancellationToken,
+) -> Result<()> {
+    let grace_period = settings.accounts.deletion_grace_period();
+    let mut interval = tokio::time::interval(settings.accounts.purge_interval());
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        let conn = &mut match db::connect(&app_state.db_pool).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::error!(?e, "Couldn't connect to the database for purging accounts");
+                continue;
+            }
+        };
+
+        match AccountRecord::purge_deleted(grace_period, &app_state.server_keypair, conn).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!(purged, "Purged deleted accounts"),
+            Err(e) => tracing::error!(?e, "Failed purging deleted accounts"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Passkey registrations are unauthenticated, so leftovers need to be cleaned up
+async fn purge_webauthn_leftovers<S: ServerSetup>(
+    app_state: AppState<S>,
+    settings: Settings,
+    token: CancellationToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(settings.webauthn.cleanup_interval());
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        let conn = &mut match db::connect(&app_state.db_pool).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::error!(?e, "Couldn't connect to the database for purging passkeys");
+                continue;
+            }
+        };
+
+        match WebauthnChallengeRecord::delete_expired(conn).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!(purged, "Purged expired WebAuthn challenges"),
+            Err(e) => tracing::error!(?e, "Failed purging expired WebAuthn challenges"),
+        }
+
+        match PasskeyRecord::delete_unclaimed(conn).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!(purged, "Purged unclaimed passkeys"),
+            Err(e) => tracing::error!(?e, "Failed purging unclaimed passkeys"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn reverify_handles<S: ServerSetup>(
+    app_state: AppState<S>,
+    settings: Settings,
+    token: CancellationToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(settings.handles.reverify_interval());
+
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        match handle_verification::reverify_handles(&app_state, &settings.handles).await {
+            Ok(summary) if summary == Default::default() => {}
+            Ok(summary) => tracing::info!(?summary, "Re-verified handles"),
+            Err(e) => tracing::error!(?e, "Failed re-verifying handles"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn index_private_volumes<S: ServerSetup>(app_state: AppState<S>) {
+    let indexed = async {
+        let conn = &mut db::connect(&app_state.db_pool).await?;
+        Volume::index_unindexed_private(conn, app_state.blocks.ipfs_db()).await
+    }
+    .await;
+
+    match indexed {
+        Ok(0) => {}
+        Ok(indexed) => tracing::info!(indexed, "Indexed blocks of private volumes"),
+        Err(e) => tracing::error!(?e, "Failed indexing blocks of private volumes"),
+    }
+}
+
 async fn setup_prod_app_state(
     settings: &Settings,
     db_pool: Pool,
@@ -290,10 +454,14 @@ async fn setup_prod_app_state(
         .with_dns_settings(settings.dns.clone())
         .with_db_pool(db_pool)
         .with_ipfs_peers(settings.ipfs.peers.clone())
-        .with_verification_code_sender(EmailVerificationCodeSender::new(settings.mailgun.clone()))
+        .with_verification_code_sender(ProdVerificationCodeSender::from_settings(settings)?)
         .with_ipfs_db(IpfsHttpApiDatabase::new().await?)
         .with_server_keypair(server_keypair)
         .with_dns_server(dns_server)
+        .with_username_denylist(UsernameDenylist::new(&settings.usernames)?)
+        .with_webauthn(build_webauthn(&settings.webauthn)?)
+        .with_rate_limits(settings.rate_limits.clone())
+        .with_verification_links(settings.verification_links.clone())
         .finalize()?;
 
     Ok(app_state)
@@ -317,6 +485,10 @@ async fn setup_local_app_state(
         .with_ipfs_db(IpfsHttpApiDatabase::new().await?)
         .with_server_keypair(server_keypair)
         .with_dns_server(dns_server)
+        .with_username_denylist(UsernameDenylist::new(&settings.usernames)?)
+        .with_webauthn(build_webauthn(&settings.webauthn)?)
+        .with_rate_limits(settings.rate_limits.clone())
+        .with_verification_links(settings.verification_links.clone())
         .finalize()?;
 
     Ok(app_state)
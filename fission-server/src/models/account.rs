@@ -3,21 +3,31 @@
 use super::capability_indexing::index_ucan;
 use crate::{
     db::{
-        schema::{accounts, ucans},
+        schema::{accounts, apps, capabilities, revocations, ucans},
         Conn,
     },
-    models::volume::{NewVolumeRecord, Volume},
+    models::{
+        account_email::AccountEmailRecord,
+        app::AppRecord,
+        passkey::PasskeyRecord,
+        revocation::{
+            find_revoked_subset, revoke_agent_ucans, revoke_device_ucans, revoke_resource_ucans,
+            RevocationRecord,
+        },
+        volume::{NewVolumeRecord, Volume},
+    },
     settings,
     setups::IpfsDatabase,
 };
 use anyhow::{bail, Result};
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
 use fission_core::{
-    capabilities::did::Did,
-    common::Account,
+    capabilities::{did::Did, volume::VolumeResource},
+    common::{Account, AccountExport, AccountPasskey, Device},
     ed_did_key::EdDidKey,
+    revocation::Revocation,
     username::{Handle, Username},
 };
 use rs_ucan::{
@@ -27,15 +37,20 @@ use rs_ucan::{
     ucan::Ucan,
 };
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
 use utoipa::ToSchema;
 
+/// Skeleton stored for legacy usernames and handles that don't validate anymore.
+/// Skeletons of valid names are never empty, so these never match as confusable.
+pub const INVALID_NAME_SKELETON: &str = "";
+
 /// New Account Struct (for creating new accounts)
 #[derive(Insertable)]
 #[diesel(table_name = accounts)]
 struct NewAccountRecord {
     did: String,
     username: String,
+    username_skeleton: String,
     email: String,
 }
 
@@ -80,27 +95,54 @@ pub struct AccountRecord {
 
     /// Custom domain handle associated with the account
     pub handle: Option<String>,
+
+    /// Confusable skeleton of the username, see `Username::skeleton`
+    pub username_skeleton: Option<String>,
+
+    /// Confusable skeleton of the handle, see `Handle::skeleton`
+    pub handle_skeleton: Option<String>,
+
+    /// When the account was deleted. Accounts with this set are pending deletion
+    /// and can be restored until they're purged.
+    #[schema(value_type = Option<String>)]
+    pub deleted_at: Option<NaiveDateTime>,
+
+    /// When the handle was last verified to point to this account
+    #[schema(value_type = Option<String>)]
+    pub handle_verified_at: Option<NaiveDateTime>,
+
+    /// Since when re-verifying the handle fails. Unverified handles aren't used
+    /// as the account's username and are cleared after a grace period.
+    #[schema(value_type = Option<String>)]
+    pub handle_unverified_since: Option<NaiveDateTime>,
+
+    /// Whether pulling the account's volume requires a `volume/read` capability
+    pub volume_private: bool,
 }
 
 impl AccountRecord {
     /// Create a new Account. Inserts the account into the database.
     pub async fn new(
         conn: &mut Conn<'_>,
-        username: impl AsRef<str>,
+        username: &Username,
         email: String,
         did: String,
     ) -> Result<Self, diesel::result::Error> {
-        let username = username.as_ref().to_string();
         let new_account = NewAccountRecord {
             did,
-            username,
+            username: username.to_string(),
+            username_skeleton: username.skeleton(),
             email,
         };
 
-        diesel::insert_into(accounts::table)
+        let account: Self = diesel::insert_into(accounts::table)
             .values(&new_account)
             .get_result(conn)
-            .await
+            .await?;
+
+        AccountEmailRecord::replace_primary(conn, account.id, &new_account.email).await?;
+
+        Ok(account)
     }
 
     /// Find a Fission Account by username, validate that the UCAN has permission to access it
@@ -127,6 +169,311 @@ impl AccountRecord {
             .await
     }
 
+    /// Find an account whose username or handle is visually confusable with
+    /// the given skeletons, ignoring the account with DID `except_did`.
+    ///
+    /// `handle_skeleton` is compared to other accounts' handles,
+    /// `username_skeleton` to other accounts' usernames.
+    pub async fn find_confusable(
+        conn: &mut Conn<'_>,
+        username_skeleton: Option<&str>,
+        handle_skeleton: &str,
+        except_did: Option<&str>,
+    ) -> Result<Option<Self>, diesel::result::Error> {
+        let mut query = accounts::table
+            .filter(
+                accounts::handle_skeleton
+                    .eq(handle_skeleton)
+                    .or(accounts::username_skeleton.eq(username_skeleton)),
+            )
+            .into_boxed();
+
+        if let Some(did) = except_did {
+            query = query.filter(accounts::did.ne(did));
+        }
+
+        query.first::<AccountRecord>(conn).await.optional()
+    }
+
+    /// Compute the skeletons of accounts that were created before skeletons
+    /// were tracked. Returns the number of accounts that got a skeleton.
+    ///
+    /// Legacy names that don't validate anymore have no skeleton. Their skeleton is set to
+    /// [`INVALID_NAME_SKELETON`], so they aren't picked up again on every startup.
+    pub async fn backfill_skeletons(conn: &mut Conn<'_>) -> Result<usize> {
+        let records = accounts::table
+            .filter(
+                accounts::username
+                    .is_not_null()
+                    .and(accounts::username_skeleton.is_null())
+                    .or(accounts::handle
+                        .is_not_null()
+                        .and(accounts::handle_skeleton.is_null())),
+            )
+            .get_results::<AccountRecord>(conn)
+            .await?;
+
+        let mut backfilled = 0;
+
+        for record in records.iter() {
+            let username_skeleton = record.username.as_deref().map(|username| {
+                Username::from_str(username)
+                    .map(|username| username.skeleton())
+                    .ok()
+            });
+
+            let handle_skeleton = record.handle.as_deref().map(|handle| {
+                Handle::from_str(handle)
+                    .map(|handle| handle.skeleton())
+                    .ok()
+            });
+
+            let skipped =
+                matches!(username_skeleton, Some(None)) || matches!(handle_skeleton, Some(None));
+
+            if skipped {
+                tracing::warn!(account = ?record, "Skipping skeletons of invalid legacy names");
+            } else {
+                backfilled += 1;
+            }
+
+            let username_skeleton = username_skeleton
+                .map(|skeleton| skeleton.unwrap_or_else(|| INVALID_NAME_SKELETON.to_string()));
+            let handle_skeleton = handle_skeleton
+                .map(|skeleton| skeleton.unwrap_or_else(|| INVALID_NAME_SKELETON.to_string()));
+
+            diesel::update(accounts::table)
+                .filter(accounts::id.eq(record.id))
+                .set((
+                    accounts::username_skeleton.eq(username_skeleton),
+                    accounts::handle_skeleton.eq(handle_skeleton),
+                ))
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(backfilled)
+    }
+
+    /// Whether given DID is the DID of an account that's pending deletion
+    /// or of an app owned by such an account.
+    pub async fn is_pending_deletion(
+        conn: &mut Conn<'_>,
+        did: impl AsRef<str>,
+    ) -> Result<bool, diesel::result::Error> {
+        let did = did.as_ref();
+
+        let accounts_pending: i64 = accounts::table
+            .filter(accounts::deleted_at.is_not_null())
+            .filter(accounts::did.eq(did).or(
+                accounts::id.eq_any(apps::table.filter(apps::did.eq(did)).select(apps::owner_id)),
+            ))
+            .count()
+            .get_result(conn)
+            .await?;
+
+        Ok(accounts_pending > 0)
+    }
+
+    /// Mark this account as pending deletion.
+    /// It can be restored via `restore` until it's purged via `purge_deleted`.
+    pub async fn mark_deleted(self, conn: &mut Conn<'_>) -> Result<Self, diesel::result::Error> {
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .set(accounts::deleted_at.eq(Some(Utc::now().naive_utc())))
+            .get_result(conn)
+            .await
+    }
+
+    /// Undo `mark_deleted`
+    pub async fn restore(self, conn: &mut Conn<'_>) -> Result<Self, diesel::result::Error> {
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .set(accounts::deleted_at.eq(None::<NaiveDateTime>))
+            .get_result(conn)
+            .await
+    }
+
+    /// Find accounts with a handle that wasn't verified since `cutoff`,
+    /// least recently verified first. Accounts pending deletion are skipped.
+    pub async fn find_handles_to_reverify(
+        conn: &mut Conn<'_>,
+        cutoff: NaiveDateTime,
+    ) -> Result<Vec<Self>, diesel::result::Error> {
+        accounts::table
+            .filter(accounts::handle.is_not_null())
+            .filter(accounts::deleted_at.is_null())
+            .filter(
+                accounts::handle_verified_at
+                    .is_null()
+                    .or(accounts::handle_verified_at.lt(cutoff)),
+            )
+            .order(accounts::handle_verified_at.asc().nulls_first())
+            .get_results(conn)
+            .await
+    }
+
+    /// Record that the account's handle was verified just now.
+    ///
+    /// Doesn't do anything if the handle changed in the meantime.
+    pub async fn mark_handle_verified(
+        &self,
+        conn: &mut Conn<'_>,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .filter(accounts::handle.eq(&self.handle))
+            .set((
+                accounts::handle_verified_at.eq(Some(Utc::now().naive_utc())),
+                accounts::handle_unverified_since.eq(None::<NaiveDateTime>),
+            ))
+            .execute(conn)
+            .await
+    }
+
+    /// Record that the account's handle failed re-verification,
+    /// unless it was already marked as unverified earlier.
+    pub async fn mark_handle_unverified(
+        &self,
+        conn: &mut Conn<'_>,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .filter(accounts::handle.eq(&self.handle))
+            .filter(accounts::handle_unverified_since.is_null())
+            .set(accounts::handle_unverified_since.eq(Some(Utc::now().naive_utc())))
+            .execute(conn)
+            .await
+    }
+
+    /// Disassociate the account's handle, e.g. after it failed re-verification for too long.
+    ///
+    /// Doesn't do anything if the handle changed in the meantime.
+    pub async fn clear_handle(&self, conn: &mut Conn<'_>) -> Result<usize, diesel::result::Error> {
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .filter(accounts::handle.eq(&self.handle))
+            .set((
+                accounts::handle.eq(None::<String>),
+                accounts::handle_skeleton.eq(None::<String>),
+                accounts::handle_verified_at.eq(None::<NaiveDateTime>),
+                accounts::handle_unverified_since.eq(None::<NaiveDateTime>),
+            ))
+            .execute(conn)
+            .await
+    }
+
+    /// Delete this account and its apps for good and revoke all UCANs the server issued for them.
+    pub async fn delete(self, server: &EdDidKey, conn: &mut Conn<'_>) -> Result<Self> {
+        // Apps reference their owner, so they need to go first
+        for app in AppRecord::find_by_owner(conn, self.id).await? {
+            app.delete(server, conn).await?;
+        }
+
+        diesel::delete(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .execute(conn)
+            .await?;
+
+        // Revoke the server to user UCANs
+        let revoked = revoke_resource_ucans(&self.did, server, conn).await?;
+
+        if revoked == 0 {
+            tracing::warn!(account = ?self, "Trouble revoking UCANs associated with this account: Couldn't find UCANs to revoke");
+        }
+
+        Ok(self)
+    }
+
+    /// List the devices that the server delegated access to this account to.
+    ///
+    /// Revoked and expired delegations are skipped.
+    /// If a device was linked multiple times, the longest-lived delegation determines its expiry.
+    pub async fn list_devices(
+        &self,
+        server: &EdDidKey,
+        conn: &mut Conn<'_>,
+    ) -> Result<Vec<Device>> {
+        let device_ucans: Vec<(String, String, Option<NaiveDateTime>)> = capabilities::table
+            .inner_join(ucans::table)
+            .filter(capabilities::resource.eq(&self.did))
+            .filter(ucans::issuer.eq(server.did_as_str()))
+            .select((ucans::cid, ucans::audience, ucans::expires_at))
+            .distinct()
+            .get_results(conn)
+            .await?;
+
+        let revoked = find_revoked_subset(
+            device_ucans.iter().map(|(cid, _, _)| cid.clone()).collect(),
+            conn,
+        )
+        .await?;
+
+        let now = Utc::now().naive_utc();
+        let mut devices = BTreeMap::<String, Option<NaiveDateTime>>::new();
+        for (cid, did, expires_at) in device_ucans {
+            if revoked.contains(&cid) || expires_at.is_some_and(|exp| exp <= now) {
+                continue;
+            }
+
+            devices
+                .entry(did)
+                .and_modify(|latest| {
+                    *latest = latest.zip(expires_at).map(|(a, b)| a.max(b));
+                })
+                .or_insert(expires_at);
+        }
+
+        Ok(devices
+            .into_iter()
+            .map(|(did, expires_at)| Device {
+                did,
+                expires_at: expires_at.map(|exp| exp.and_utc().timestamp() as u64),
+            })
+            .collect())
+    }
+
+    /// Revoke a device's access to this account and its apps.
+    /// Returns the number of revoked UCANs, zero if the device didn't have access.
+    pub async fn revoke_device(
+        &self,
+        agent_did: &str,
+        server: &EdDidKey,
+        conn: &mut Conn<'_>,
+    ) -> Result<usize> {
+        let mut revoked = revoke_device_ucans(&self.did, agent_did, server, conn).await?;
+
+        for app in AppRecord::find_by_owner(conn, self.id).await? {
+            revoked += revoke_device_ucans(&app.did, agent_did, server, conn).await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Delete all accounts that have been pending deletion for longer than the grace period.
+    /// Returns the number of deleted accounts.
+    pub async fn purge_deleted(
+        grace_period: Duration,
+        server: &EdDidKey,
+        conn: &mut Conn<'_>,
+    ) -> Result<usize> {
+        let cutoff = Utc::now().naive_utc() - chrono::Duration::from_std(grace_period)?;
+
+        let expired = accounts::table
+            .filter(accounts::deleted_at.lt(cutoff))
+            .get_results::<AccountRecord>(conn)
+            .await?;
+
+        let purged = expired.len();
+
+        for account in expired {
+            conn.transaction(|conn| account.delete(server, conn).scope_boxed())
+                .await?;
+        }
+
+        Ok(purged)
+    }
+
     /// Get the volume associated with the user's account.
     //
     // Note: this doesn't use a join, but rather a separate query to the volumes table.
@@ -149,7 +496,7 @@ impl AccountRecord {
     ) -> Result<NewVolumeRecord> {
         ipfs_db.pin_add(cid, true).await?;
 
-        let volume = Volume::new(conn, cid).await?;
+        let volume = Volume::new(conn, cid, &self.did, self.volume_private, ipfs_db).await?;
 
         diesel::update(accounts::dsl::accounts)
             .filter(accounts::id.eq(self.id))
@@ -160,6 +507,27 @@ impl AccountRecord {
         Ok(volume.into())
     }
 
+    /// Make the account's volume private or public, including its current version
+    /// and all versions pushed from now on.
+    pub async fn set_volume_private(
+        &self,
+        conn: &mut Conn<'_>,
+        private: bool,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<()> {
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.id))
+            .set(accounts::volume_private.eq(private))
+            .execute(conn)
+            .await?;
+
+        if let Some(volume_id) = self.volume_id {
+            Volume::set_private(conn, volume_id, private, ipfs_db).await?;
+        }
+
+        Ok(())
+    }
+
     /// Update the CID of the user's volume.
     //
     // Note: I'm not extremely stoked about having two SQL queries when we could
@@ -188,7 +556,13 @@ impl AccountRecord {
 
     /// Turn this database record into an account struct used in APIs
     pub fn to_account(self, dns_settings: &settings::Dns) -> Result<Account> {
-        let username = match (self.handle.as_ref(), self.username.as_ref()) {
+        // Handles that failed re-verification shouldn't be shown anymore
+        let handle = self
+            .handle
+            .as_ref()
+            .filter(|_| self.handle_unverified_since.is_none());
+
+        let username = match (handle, self.username.as_ref()) {
             // Prefer using the user's handle
             (Some(handle), _) => Some(Handle::from_str(handle)?),
             // Otherwise use their username
@@ -203,6 +577,73 @@ impl AccountRecord {
             email: self.email,
         })
     }
+
+    /// Collect all data stored about this account, e.g. for a GDPR data export.
+    pub async fn export(
+        self,
+        dns_settings: &settings::Dns,
+        conn: &mut Conn<'_>,
+    ) -> Result<AccountExport> {
+        let volume_cid = self.get_volume(conn).await?.map(|volume| volume.cid);
+
+        let emails = AccountEmailRecord::list(conn, self.id)
+            .await?
+            .iter()
+            .map(AccountEmailRecord::to_account_email)
+            .collect();
+
+        let passkeys = PasskeyRecord::find_by_account(conn, self.id)
+            .await?
+            .into_iter()
+            .map(|record| AccountPasskey {
+                credential_id: record.credential_id,
+                did: record.did,
+            })
+            .collect();
+
+        let mut apps = Vec::new();
+        for app in AppRecord::find_by_owner(conn, self.id).await? {
+            apps.push(app.to_app(conn).await?);
+        }
+
+        let resources = vec![self.did.clone(), VolumeResource::new(&self.did).to_string()];
+
+        let encoded_ucans = capabilities::table
+            .inner_join(ucans::table)
+            .filter(capabilities::resource.eq_any(&resources))
+            .select(ucans::encoded)
+            .distinct()
+            .get_results::<String>(conn)
+            .await?;
+
+        // The revoked UCANs themselves are usually deleted from the index
+        let revocations = revocations::table
+            .filter(
+                revocations::iss
+                    .eq(&self.did)
+                    .or(revocations::resources.overlaps_with(&resources)),
+            )
+            .order(revocations::id)
+            .get_results::<RevocationRecord>(conn)
+            .await?
+            .into_iter()
+            .map(|record| Revocation {
+                iss: record.iss,
+                revoke: record.cid,
+                challenge: record.challenge,
+            })
+            .collect();
+
+        Ok(AccountExport {
+            account: self.to_account(dns_settings)?,
+            emails,
+            passkeys,
+            volume_cid,
+            apps,
+            ucans: encoded_ucans,
+            revocations,
+        })
+    }
 }
 
 /// Account with UCANs that give root auth to a specific DID
@@ -233,8 +674,8 @@ impl AccountAndAuth {
         dns_settings: &settings::Dns,
         conn: &mut Conn<'_>,
     ) -> Result<Self> {
-        let (ucans, account_did) = Self::issue_root_ucans(server, agent_did, conn).await?;
-        let record = AccountRecord::new(conn, username, email, account_did).await?;
+        let (ucans, account_did) = issue_root_ucans(server, agent_did, conn).await?;
+        let record = AccountRecord::new(conn, &username, email, account_did).await?;
 
         Ok(Self {
             ucans,
@@ -254,19 +695,12 @@ impl AccountAndAuth {
         dns_settings: &settings::Dns,
         conn: &mut Conn<'_>,
     ) -> Result<Self> {
-        let server_ucan: String = ucans::table
-            .filter(ucans::issuer.eq(&account.did))
-            .filter(ucans::audience.eq(server.did_as_str()))
-            .select(ucans::encoded)
-            .get_result(conn)
-            .await?;
-
-        let server_ucan: Ucan = Ucan::from_str(&server_ucan)?;
+        let server_ucan = find_server_ucan(&account.did, server, conn).await?;
 
         let account_did = account.did.clone();
 
         let agent_ucan =
-            Self::issue_agent_ucan(server, account_did, agent_did, &server_ucan, conn).await?;
+            issue_agent_ucan(server, account_did, agent_did, &server_ucan, conn).await?;
 
         Ok(Self {
             ucans: vec![server_ucan, agent_ucan],
@@ -274,47 +708,97 @@ impl AccountAndAuth {
         })
     }
 
-    async fn issue_root_ucans(
-        server: &EdDidKey,
+    /// Recover access to an account for a new agent.
+    ///
+    /// Revokes all UCANs the server issued to previous agents of the account and its apps,
+    /// then delegates access to both to given agent DID.
+    pub async fn recover(
+        account: AccountRecord,
         agent_did: &str,
+        server: &EdDidKey,
+        dns_settings: &settings::Dns,
         conn: &mut Conn<'_>,
-    ) -> Result<(Vec<Ucan>, String)> {
-        let account = EdDidKey::generate(); // Zeroized on drop
-
-        // Delegate all access to the fission server
-        let capability = Capability::new(Did(account.did()), TopAbility, EmptyCaveat);
-        let server_ucan: Ucan = UcanBuilder::default()
-            .for_audience(server)
-            .claiming_capability(capability)
-            .sign(&account)?;
+    ) -> Result<Self> {
+        let revoked = revoke_agent_ucans(&account.did, server, conn).await?;
+        tracing::info!(
+            did = account.did,
+            revoked,
+            "Revoked agent UCANs for account recovery"
+        );
+
+        for app in AppRecord::find_by_owner(conn, account.id).await? {
+            revoke_agent_ucans(&app.did, server, conn).await?;
+            let server_ucan = find_server_ucan(&app.did, server, conn).await?;
+            issue_agent_ucan(server, app.did, agent_did, &server_ucan, conn).await?;
+        }
 
-        // Persist UCAN in the DB
-        index_ucan(&server_ucan, conn).await?;
+        Self::link_agent(account, agent_did, server, dns_settings, conn).await
+    }
+}
 
-        // Delegate the account to the agent
-        let agent_ucan =
-            Self::issue_agent_ucan(server, account.did(), agent_did, &server_ucan, conn).await?;
+/// Find the UCAN that delegates all rights of given resource to the server
+async fn find_server_ucan(
+    resource_did: &str,
+    server: &EdDidKey,
+    conn: &mut Conn<'_>,
+) -> Result<Ucan> {
+    let server_ucan: String = ucans::table
+        .filter(ucans::issuer.eq(resource_did))
+        .filter(ucans::audience.eq(server.did_as_str()))
+        .select(ucans::encoded)
+        .get_result(conn)
+        .await?;
+
+    Ok(Ucan::from_str(&server_ucan)?)
+}
 
-        Ok((vec![server_ucan, agent_ucan], account.did()))
-    }
+/// Generate a new resource keypair, delegate all of its rights to the server
+/// and have the server delegate them further to given agent DID.
+///
+/// The private key of the resource is discarded after the first delegation.
+/// Returns the UCAN chain and the resource DID.
+pub(crate) async fn issue_root_ucans(
+    server: &EdDidKey,
+    agent_did: &str,
+    conn: &mut Conn<'_>,
+) -> Result<(Vec<Ucan>, String)> {
+    let resource = EdDidKey::generate(); // Zeroized on drop
+
+    // Delegate all access to the fission server
+    let capability = Capability::new(Did(resource.did()), TopAbility, EmptyCaveat);
+    let server_ucan: Ucan = UcanBuilder::default()
+        .for_audience(server)
+        .claiming_capability(capability)
+        .sign(&resource)?;
+
+    // Persist UCAN in the DB
+    index_ucan(&server_ucan, conn).await?;
+
+    // Delegate the resource to the agent
+    let agent_ucan =
+        issue_agent_ucan(server, resource.did(), agent_did, &server_ucan, conn).await?;
+
+    Ok((vec![server_ucan, agent_ucan], resource.did()))
+}
 
-    async fn issue_agent_ucan(
-        server: &EdDidKey,
-        account_did: String,
-        agent_did: &str,
-        server_ucan: &Ucan,
-        conn: &mut Conn<'_>,
-    ) -> Result<Ucan> {
-        // Delegate the account to the agent
-        let capability = Capability::new(Did(account_did), TopAbility, EmptyCaveat);
-        let agent_ucan: Ucan = UcanBuilder::default()
-            .for_audience(agent_did)
-            .claiming_capability(capability)
-            .witnessed_by(server_ucan, None)
-            .sign(server)?;
-
-        index_ucan(&agent_ucan, conn).await?;
-
-        Ok(agent_ucan)
-    }
+/// Delegate access to a resource DID from the server to given agent DID,
+/// witnessed by the resource's delegation to the server.
+pub(crate) async fn issue_agent_ucan(
+    server: &EdDidKey,
+    resource_did: String,
+    agent_did: &str,
+    server_ucan: &Ucan,
+    conn: &mut Conn<'_>,
+) -> Result<Ucan> {
+    // Delegate the resource to the agent
+    let capability = Capability::new(Did(resource_did), TopAbility, EmptyCaveat);
+    let agent_ucan: Ucan = UcanBuilder::default()
+        .for_audience(agent_did)
+        .claiming_capability(capability)
+        .witnessed_by(server_ucan, None)
+        .sign(server)?;
+
+    index_ucan(&agent_ucan, conn).await?;
+
+    Ok(agent_ucan)
 }
@@ -0,0 +1,206 @@
+//! Account email address model
+
+use super::account::AccountRecord;
+use crate::db::{
+    schema::{account_emails, accounts},
+    Conn,
+};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use fission_core::common::AccountEmail;
+
+/// New account email address (for adding addresses to accounts)
+#[derive(Insertable)]
+#[diesel(table_name = account_emails)]
+struct NewAccountEmailRecord<'a> {
+    account_id: i32,
+    email: &'a str,
+    verified: bool,
+    is_primary: bool,
+}
+
+/// The model for a row in the account_emails table
+#[derive(Debug, Queryable, Selectable, Clone, Identifiable, Associations)]
+#[diesel(belongs_to(AccountRecord, foreign_key = account_id))]
+#[diesel(table_name = account_emails)]
+pub struct AccountEmailRecord {
+    /// Internal Database Identifier
+    pub id: i32,
+
+    /// Inserted at timestamp
+    pub inserted_at: NaiveDateTime,
+
+    /// Updated at timestamp
+    pub updated_at: NaiveDateTime,
+
+    /// Foreign key to the account
+    pub account_id: i32,
+
+    /// The email address
+    pub email: String,
+
+    /// Whether the account proved access to this address via verification code
+    pub verified: bool,
+
+    /// Whether this is the account's primary address, mirrored in `accounts.email`
+    pub is_primary: bool,
+}
+
+impl AccountEmailRecord {
+    /// Add an unverified email address to an account.
+    /// Does nothing if the account already has this address.
+    pub async fn add_unverified(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+        email: &str,
+    ) -> Result<(), diesel::result::Error> {
+        diesel::insert_into(account_emails::table)
+            .values(NewAccountEmailRecord {
+                account_id,
+                email,
+                verified: false,
+                is_primary: false,
+            })
+            .on_conflict((account_emails::account_id, account_emails::email))
+            .do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Make given (already verified) address the account's primary address,
+    /// replacing the previous primary address entirely.
+    pub async fn replace_primary(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+        email: &str,
+    ) -> Result<(), diesel::result::Error> {
+        diesel::delete(account_emails::table)
+            .filter(account_emails::account_id.eq(account_id))
+            .filter(account_emails::is_primary.or(account_emails::email.eq(email)))
+            .execute(conn)
+            .await?;
+
+        diesel::insert_into(account_emails::table)
+            .values(NewAccountEmailRecord {
+                account_id,
+                email,
+                verified: true,
+                is_primary: true,
+            })
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Find an account's email address record
+    pub async fn find(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+        email: &str,
+    ) -> Result<Self, diesel::result::Error> {
+        account_emails::table
+            .filter(account_emails::account_id.eq(account_id))
+            .filter(account_emails::email.eq(email))
+            .first(conn)
+            .await
+    }
+
+    /// List all email addresses of an account, primary address first
+    pub async fn list(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+    ) -> Result<Vec<Self>, diesel::result::Error> {
+        account_emails::table
+            .filter(account_emails::account_id.eq(account_id))
+            .order((account_emails::is_primary.desc(), account_emails::id))
+            .get_results(conn)
+            .await
+    }
+
+    /// List all verified email addresses of an account
+    pub async fn verified_addresses(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+    ) -> Result<Vec<String>, diesel::result::Error> {
+        account_emails::table
+            .filter(account_emails::account_id.eq(account_id))
+            .filter(account_emails::verified.eq(true))
+            .select(account_emails::email)
+            .get_results(conn)
+            .await
+    }
+
+    /// Whether the address is already verified by any other account than the one given.
+    pub async fn is_claimed(
+        conn: &mut Conn<'_>,
+        email: &str,
+        except_account_id: Option<i32>,
+    ) -> Result<bool, diesel::result::Error> {
+        let mut query = account_emails::table
+            .filter(account_emails::email.eq(email))
+            .filter(account_emails::verified.eq(true))
+            .select(account_emails::id)
+            .into_boxed();
+
+        if let Some(account_id) = except_account_id {
+            query = query.filter(account_emails::account_id.ne(account_id));
+        }
+
+        Ok(query.first::<i32>(conn).await.optional()?.is_some())
+    }
+
+    /// Mark this address as verified
+    pub async fn mark_verified(self, conn: &mut Conn<'_>) -> Result<Self, diesel::result::Error> {
+        diesel::update(account_emails::table)
+            .filter(account_emails::id.eq(self.id))
+            .set(account_emails::verified.eq(true))
+            .get_result(conn)
+            .await
+    }
+
+    /// Make this address the account's primary address.
+    /// Also updates `accounts.email` accordingly.
+    pub async fn set_primary(self, conn: &mut Conn<'_>) -> Result<Self, diesel::result::Error> {
+        diesel::update(account_emails::table)
+            .filter(account_emails::account_id.eq(self.account_id))
+            .filter(account_emails::is_primary.eq(true))
+            .set(account_emails::is_primary.eq(false))
+            .execute(conn)
+            .await?;
+
+        diesel::update(accounts::table)
+            .filter(accounts::id.eq(self.account_id))
+            .set(accounts::email.eq(&self.email))
+            .execute(conn)
+            .await?;
+
+        diesel::update(account_emails::table)
+            .filter(account_emails::id.eq(self.id))
+            .set(account_emails::is_primary.eq(true))
+            .get_result(conn)
+            .await
+    }
+
+    /// Remove this address from the account
+    pub async fn delete(self, conn: &mut Conn<'_>) -> Result<(), diesel::result::Error> {
+        diesel::delete(account_emails::table)
+            .filter(account_emails::id.eq(self.id))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Convert into the API representation
+    pub fn to_account_email(&self) -> AccountEmail {
+        AccountEmail {
+            email: self.email.clone(),
+            verified: self.verified,
+            primary: self.is_primary,
+        }
+    }
+}
@@ -1,21 +1,282 @@
 //! App model
 
+use super::{
+    account::{issue_root_ucans, AccountRecord},
+    revocation::revoke_resource_ucans,
+};
+use crate::{
+    db::{schema::apps, Conn},
+    models::volume::{NewVolumeRecord, Volume},
+    setups::IpfsDatabase,
+};
+use anyhow::{bail, Result};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use fission_core::{common::App, ed_did_key::EdDidKey};
+use rand::seq::SliceRandom;
+use rs_ucan::ucan::Ucan;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Debug, Queryable)]
-/// App model
-pub struct App {
+/// New App Struct (for creating new apps)
+#[derive(Insertable)]
+#[diesel(table_name = apps)]
+struct NewAppRecord {
+    owner_id: i32,
+    did: String,
+    name: String,
+}
+
+#[derive(
+    Debug,
+    Queryable,
+    Selectable,
+    Insertable,
+    Clone,
+    Identifiable,
+    Associations,
+    Serialize,
+    Deserialize,
+    ToSchema,
+)]
+#[diesel(belongs_to(AccountRecord, foreign_key = owner_id))]
+#[diesel(belongs_to(Volume))]
+#[diesel(table_name = apps)]
+/// The model for a row in the apps table
+pub struct AppRecord {
     /// Internal Database Identifier
     pub id: i32,
-    /// Foreign key to the owner account
-    pub owner_id: i32,
 
     /// Inserted at timestamp
+    #[schema(value_type = String)]
     pub inserted_at: NaiveDateTime,
+
     /// Updated at timestamp
+    #[schema(value_type = String)]
     pub updated_at: NaiveDateTime,
 
-    /// CID of the app
-    pub cid: String,
+    /// Foreign key to the owner account
+    pub owner_id: i32,
+
+    /// Volume ID
+    pub volume_id: Option<i32>,
+
+    /// App DID
+    pub did: String,
+
+    /// The app's subdomain name, e.g. `friendly-pink-dragon`
+    pub name: String,
+
+    /// Whether pulling the app's volume requires a `volume/read` capability
+    pub volume_private: bool,
+}
+
+impl AppRecord {
+    /// Create a new App. Inserts the app into the database.
+    pub async fn new(
+        conn: &mut Conn<'_>,
+        owner_id: i32,
+        did: String,
+        name: String,
+    ) -> Result<Self, diesel::result::Error> {
+        let new_app = NewAppRecord {
+            owner_id,
+            did,
+            name,
+        };
+
+        diesel::insert_into(apps::table)
+            .values(&new_app)
+            .get_result(conn)
+            .await
+    }
+
+    /// Fetch an app by DID
+    pub async fn find_by_did(
+        conn: &mut Conn<'_>,
+        did: impl AsRef<str>,
+    ) -> Result<Self, diesel::result::Error> {
+        let did = did.as_ref();
+        apps::table
+            .filter(apps::did.eq(did))
+            .first::<AppRecord>(conn)
+            .await
+    }
+
+    /// Fetch an app by its subdomain name
+    pub async fn find_by_name(
+        conn: &mut Conn<'_>,
+        name: impl AsRef<str>,
+    ) -> Result<Self, diesel::result::Error> {
+        let name = name.as_ref();
+        apps::table
+            .filter(apps::name.eq(name))
+            .first::<AppRecord>(conn)
+            .await
+    }
+
+    /// List all apps owned by given account
+    pub async fn find_by_owner(
+        conn: &mut Conn<'_>,
+        owner_id: i32,
+    ) -> Result<Vec<Self>, diesel::result::Error> {
+        apps::table
+            .filter(apps::owner_id.eq(owner_id))
+            .order(apps::id)
+            .get_results::<AppRecord>(conn)
+            .await
+    }
+
+    /// Generate a friendly subdomain name like `friendly-pink-dragon` that's not taken yet.
+    pub async fn generate_unused_name(conn: &mut Conn<'_>) -> Result<String> {
+        const MAX_ATTEMPTS: usize = 10;
+
+        for _ in 0..MAX_ATTEMPTS {
+            let name = generate_friendly_name();
+            if Self::find_by_name(conn, &name).await.optional()?.is_none() {
+                return Ok(name);
+            }
+        }
+
+        bail!("Couldn't find an unused app name after {MAX_ATTEMPTS} attempts")
+    }
+
+    /// Get the volume associated with the app.
+    pub async fn get_volume(&self, conn: &mut Conn<'_>) -> Result<Option<NewVolumeRecord>> {
+        if let Some(volume_id) = self.volume_id {
+            let volume = Volume::find_by_id(conn, volume_id).await?;
+            Ok(Some(volume.into()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Create a volume record and update the app to point to it.
+    pub async fn set_volume_cid(
+        &self,
+        conn: &mut Conn<'_>,
+        cid: &str,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<NewVolumeRecord> {
+        ipfs_db.pin_add(cid, true).await?;
+
+        let volume = Volume::new(conn, cid, &self.did, self.volume_private, ipfs_db).await?;
+
+        diesel::update(apps::table)
+            .filter(apps::id.eq(self.id))
+            .set(apps::volume_id.eq(volume.id))
+            .execute(conn)
+            .await?;
+
+        Ok(volume.into())
+    }
+
+    /// Make the app's volume private or public, including its current version
+    /// and all versions pushed from now on.
+    pub async fn set_volume_private(
+        &self,
+        conn: &mut Conn<'_>,
+        private: bool,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<()> {
+        diesel::update(apps::table)
+            .filter(apps::id.eq(self.id))
+            .set(apps::volume_private.eq(private))
+            .execute(conn)
+            .await?;
+
+        if let Some(volume_id) = self.volume_id {
+            Volume::set_private(conn, volume_id, private, ipfs_db).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete this app and revoke all UCANs the server issued for it.
+    pub async fn delete(self, server: &EdDidKey, conn: &mut Conn<'_>) -> Result<Self> {
+        revoke_resource_ucans(&self.did, server, conn).await?;
+
+        diesel::delete(apps::table)
+            .filter(apps::id.eq(self.id))
+            .execute(conn)
+            .await?;
+
+        Ok(self)
+    }
+
+    /// Turn this database record into an app struct used in APIs
+    pub async fn to_app(self, conn: &mut Conn<'_>) -> Result<App> {
+        let cid = self.get_volume(conn).await?.map(|volume| volume.cid);
+
+        Ok(App {
+            did: self.did,
+            name: self.name,
+            cid,
+        })
+    }
+}
+
+/// App with UCANs that give root auth to a specific DID
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AppAndAuth {
+    /// The Associated App
+    pub app: App,
+    /// UCANs that give root access
+    #[schema(value_type = Vec<String>)]
+    pub ucans: Vec<Ucan>,
+}
+
+impl AppAndAuth {
+    /// Create a new app owned by given account and generate UCANs that give
+    /// root rights to given agent DID.
+    ///
+    /// Just like accounts, each app gets its own resource DID that delegates
+    /// to the server, which in turn delegates to the agent.
+    pub async fn new(
+        owner: &AccountRecord,
+        agent_did: &str,
+        server: &EdDidKey,
+        conn: &mut Conn<'_>,
+    ) -> Result<Self> {
+        let name = AppRecord::generate_unused_name(conn).await?;
+        let (ucans, app_did) = issue_root_ucans(server, agent_did, conn).await?;
+        let record = AppRecord::new(conn, owner.id, app_did, name).await?;
+
+        Ok(Self {
+            ucans,
+            app: record.to_app(conn).await?,
+        })
+    }
+}
+
+const ADJECTIVES: &[&str] = &[
+    "agile", "bold", "brave", "bright", "calm", "clever", "cozy", "daring", "eager", "fancy",
+    "friendly", "gentle", "happy", "jolly", "keen", "kind", "lively", "lucky", "merry", "mighty",
+    "nimble", "noble", "playful", "proud", "quick", "quiet", "shiny", "silly", "sleepy", "swift",
+    "tidy", "witty",
+];
+
+const COLORS: &[&str] = &[
+    "amber", "azure", "beige", "black", "blue", "bronze", "coral", "crimson", "cyan", "gold",
+    "gray", "green", "indigo", "ivory", "jade", "lilac", "lime", "magenta", "maroon", "mint",
+    "navy", "olive", "orange", "pink", "plum", "purple", "red", "rose", "ruby", "silver", "teal",
+    "white", "yellow",
+];
+
+const ANIMALS: &[&str] = &[
+    "badger", "bear", "beaver", "bison", "camel", "cat", "crane", "crow", "deer", "dolphin",
+    "dragon", "duck", "eagle", "falcon", "ferret", "fox", "frog", "gecko", "goat", "hare", "hawk",
+    "heron", "koala", "lemur", "lion", "llama", "lynx", "moose", "otter", "owl", "panda",
+    "penguin", "rabbit", "raven", "seal", "sloth", "swan", "tiger", "turtle", "walrus", "whale",
+    "wolf", "yak", "zebra",
+];
+
+fn generate_friendly_name() -> String {
+    let rng = &mut rand::thread_rng();
+    // The word lists are constant and non-empty
+    let adjective = ADJECTIVES.choose(rng).unwrap_or(&"friendly");
+    let color = COLORS.choose(rng).unwrap_or(&"pink");
+    let animal = ANIMALS.choose(rng).unwrap_or(&"dragon");
+    format!("{adjective}-{color}-{animal}")
 }
@@ -1,25 +1,40 @@
 //! Email Verification Model
 use crate::db::{schema::email_verifications, Conn};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::NaiveDateTime;
 use diesel::{
     dsl::{now, IntervalDsl},
     pg::Pg,
-    ExpressionMethods, Insertable, QueryDsl, Queryable, Selectable, SelectableHelper,
+    ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, Selectable,
+    SelectableHelper,
 };
 use diesel_async::RunQueryDsl;
-use fission_core::common::EmailVerifyRequest;
+use ed25519::{
+    signature::{Signer, Verifier},
+    Signature,
+};
+use fission_core::{
+    common::{EmailVerifyRequest, VerificationMode, VerificationPurpose},
+    ed_did_key::EdDidKey,
+};
 use hex::ToHex;
 use rand::Rng;
 
+/// How many wrong codes can be tried for a code before it's invalidated
+pub const MAX_FAILED_ATTEMPTS: i32 = 5;
+
 /// Email Verification Request
 #[derive(Insertable, Debug)]
 #[diesel(table_name = email_verifications)]
 pub struct NewEmailVerification {
     /// Email address associated with the account
     pub email: String,
-    /// The verification code
-    pub code: String,
+    /// Hash of the verification code, see `hash_code`
+    pub code_hash: String,
+    /// What the code from the verification link may be used for, if a link was requested
+    pub link_purpose: Option<String>,
+    /// Which DID may use the code from the verification link, if a link was requested
+    pub link_did: Option<String>,
 }
 
 /// Email Verification Record
@@ -38,44 +53,216 @@ pub struct EmailVerification {
     /// Email address associated with the account
     pub email: String,
 
-    /// The verification code
-    pub code: String,
+    /// Hash of the verification code, see `hash_code`
+    pub code_hash: String,
+
+    /// How many wrong codes were tried while this was the latest code for its email address
+    pub failed_attempts: i32,
+
+    /// Whether the verification link for this record was opened already
+    pub link_used: bool,
+
+    /// What the code from the verification link may be used for, see `VerificationPurpose`.
+    /// `None` for codes that were sent by email directly.
+    pub link_purpose: Option<String>,
+
+    /// Which DID may use the code from the verification link
+    pub link_did: Option<String>,
 }
 
 impl EmailVerification {
-    /// Create a new instance of [EmailVerification]
+    /// Create a new instance of [EmailVerification].
+    /// Returns the record and the generated code, which is only stored as a hash.
     pub async fn new(
         conn: &mut Conn<'_>,
         request: &EmailVerifyRequest,
-    ) -> Result<Self, diesel::result::Error> {
+    ) -> Result<(Self, String), diesel::result::Error> {
+        let code = generate_code();
+
+        let (link_purpose, link_did) = match request.mode {
+            VerificationMode::Code => (None, None),
+            VerificationMode::Link => (
+                request.purpose.map(|purpose| purpose.as_str().to_string()),
+                request.did.clone(),
+            ),
+        };
+
         let record = NewEmailVerification {
             email: request.email.clone(),
-            code: generate_code(),
+            code_hash: hash_code(&request.email, code),
+            link_purpose,
+            link_did,
         };
 
         tracing::debug!("Creating new email verification record: {:?}", record);
 
-        diesel::insert_into(email_verifications::table)
+        let verification = diesel::insert_into(email_verifications::table)
             .values(&record)
             .returning(EmailVerification::as_select())
             .get_result(conn)
-            .await
+            .await?;
+
+        Ok((verification, format!("{code:0>6}")))
     }
 
-    /// Find a token by email, did, and code.
+    /// Find a token by email and code.
+    ///
+    /// Wrong codes count as failed attempts for the latest code sent to the email address.
+    /// Don't call this within a transaction that's rolled back on failure,
+    /// otherwise failed attempts aren't recorded.
     pub async fn find_token(conn: &mut Conn<'_>, email: &str, code: &str) -> Result<Self> {
-        tracing::debug!(
-            email = email,
-            code = code,
-            "Looking up email verification request",
-        );
-
-        Ok(email_verifications::table
-            .filter(email_verifications::email.eq(email))
-            .filter(email_verifications::code.eq(code))
+        Self::find_token_for_any(conn, &[email.to_string()], code).await
+    }
+
+    /// Find a token by code that was sent to any of the given email addresses.
+    ///
+    /// Wrong codes count as failed attempts for the latest code sent to any of them,
+    /// since that's the one being guessed.
+    /// Once a code reaches [`MAX_FAILED_ATTEMPTS`], it's deleted.
+    pub async fn find_token_for_any(
+        conn: &mut Conn<'_>,
+        emails: &[String],
+        code: &str,
+    ) -> Result<Self> {
+        tracing::debug!(?emails, "Looking up email verification request");
+
+        let code_hashes = match parse_code(code) {
+            Some(code) => emails.iter().map(|email| hash_code(email, code)).collect(),
+            None => Vec::new(),
+        };
+
+        let verification = email_verifications::table
+            .filter(email_verifications::email.eq_any(emails))
+            .filter(email_verifications::code_hash.eq_any(code_hashes))
             .filter(email_verifications::inserted_at.ge(now - 24.hours()))
+            .filter(email_verifications::failed_attempts.lt(MAX_FAILED_ATTEMPTS))
             .first(conn)
-            .await?)
+            .await
+            .optional()?;
+
+        if let Some(verification) = verification {
+            return Ok(verification);
+        }
+
+        Self::record_failed_attempt(conn, emails).await?;
+
+        bail!("Invalid or expired verification code")
+    }
+
+    async fn record_failed_attempt(conn: &mut Conn<'_>, emails: &[String]) -> Result<()> {
+        let guessed: Option<i32> = email_verifications::table
+            .filter(email_verifications::email.eq_any(emails))
+            .filter(email_verifications::inserted_at.ge(now - 24.hours()))
+            .filter(email_verifications::failed_attempts.lt(MAX_FAILED_ATTEMPTS))
+            .order(email_verifications::inserted_at.desc())
+            .select(email_verifications::id)
+            .first(conn)
+            .await
+            .optional()?;
+
+        let Some(id) = guessed else {
+            return Ok(());
+        };
+
+        diesel::update(email_verifications::table)
+            .filter(email_verifications::id.eq(id))
+            .set(email_verifications::failed_attempts.eq(email_verifications::failed_attempts + 1))
+            .execute(conn)
+            .await?;
+
+        let invalidated = diesel::delete(email_verifications::table)
+            .filter(email_verifications::id.eq(id))
+            .filter(email_verifications::failed_attempts.ge(MAX_FAILED_ATTEMPTS))
+            .execute(conn)
+            .await?;
+
+        if invalidated > 0 {
+            tracing::warn!(
+                ?emails,
+                id,
+                "Invalidated verification code after too many failed attempts"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Create the token for a single-use verification link.
+    ///
+    /// The token is signed by the server, so it can't be changed to point at
+    /// another record or relay topic.
+    pub fn link_token(&self, relay_topic: &str, server_keypair: &EdDidKey) -> String {
+        let signature = server_keypair.sign(&link_token_payload(self.id, relay_topic));
+        format!(
+            "{}.{relay_topic}.{}",
+            self.id,
+            base64_url::encode(&signature.to_bytes())
+        )
+    }
+
+    /// Find the record with given ID, if its verification link can still be opened.
+    pub async fn find_unused_link(
+        conn: &mut Conn<'_>,
+        id: i32,
+    ) -> Result<Self, diesel::result::Error> {
+        email_verifications::table
+            .filter(email_verifications::id.eq(id))
+            .filter(email_verifications::link_used.eq(false))
+            .filter(email_verifications::inserted_at.ge(now - 24.hours()))
+            .filter(email_verifications::failed_attempts.lt(MAX_FAILED_ATTEMPTS))
+            .first(conn)
+            .await
+    }
+
+    /// Open the verification link of the record with given ID, see `parse_link_token`.
+    ///
+    /// Marks the link as used. Since the record's code was never sent,
+    /// it's replaced with a new one, which is returned along with the record.
+    pub async fn confirm_link(
+        conn: &mut Conn<'_>,
+        id: i32,
+    ) -> Result<(Self, String), diesel::result::Error> {
+        let verification = Self::find_unused_link(conn, id).await?;
+
+        let code = generate_code();
+
+        // Filtering for `link_used` again makes sure concurrent requests can't both succeed
+        let verification = diesel::update(email_verifications::table)
+            .filter(email_verifications::id.eq(verification.id))
+            .filter(email_verifications::link_used.eq(false))
+            .set((
+                email_verifications::code_hash.eq(hash_code(&verification.email, code)),
+                email_verifications::link_used.eq(true),
+            ))
+            .returning(EmailVerification::as_select())
+            .get_result(conn)
+            .await?;
+
+        Ok((verification, format!("{code:0>6}")))
+    }
+
+    /// Whether the code was delivered through a verification link instead of by email
+    pub fn is_from_link(&self) -> bool {
+        self.link_purpose.is_some()
+    }
+
+    /// Make sure the code may be used for given purpose by given DID.
+    ///
+    /// Codes sent by email can be used for anything. Codes from verification links
+    /// are bound to what they were requested for, because they're delivered to
+    /// whoever requested the link, not to the owner of the email address.
+    pub fn ensure_usable_for(&self, purpose: VerificationPurpose, did: &str) -> Result<()> {
+        if !self.is_from_link() {
+            return Ok(());
+        }
+
+        if self.link_purpose.as_deref() != Some(purpose.as_str())
+            || self.link_did.as_deref() != Some(did)
+        {
+            bail!("This verification code was requested for something else");
+        }
+
+        Ok(())
     }
 
     /// *Use* a token, making it impossible for it to be used again
@@ -91,12 +278,52 @@ impl EmailVerification {
 }
 
 /// Generate a code that can be sent to the user.
-fn generate_code() -> String {
+/// Needs to be 0-padded to 6 digits when displayed.
+fn generate_code() -> u64 {
     let mut rng = rand::thread_rng();
     // This is maybe way too little entropy. That said, my bank sends me 5 digit codes. 🤷‍♂️
-    let code = rng.gen_range(0..=999_999);
-    // 0-pad the 6-digit code:
-    format!("{code:0>6}")
+    // Failed attempts are limited though, see `MAX_FAILED_ATTEMPTS`.
+    rng.gen_range(0..=999_999)
+}
+
+/// Parse a 6-digit code as entered by the user
+fn parse_code(code: &str) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != 6 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    code.parse().ok()
+}
+
+/// Whether a relay topic is random-looking enough to send codes on & can be embedded in links
+pub fn is_valid_relay_topic(relay_topic: &str) -> bool {
+    (16..=128).contains(&relay_topic.len())
+        && relay_topic
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn link_token_payload(id: i32, relay_topic: &str) -> Vec<u8> {
+    format!("fission server email verification link {id} {relay_topic}").into_bytes()
+}
+
+/// Check a link token's signature and return the record ID and relay topic it was made for
+pub fn parse_link_token(token: &str, server_keypair: &EdDidKey) -> Option<(i32, String)> {
+    let mut parts = token.split('.');
+    let (Some(id), Some(relay_topic), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    let id: i32 = id.parse().ok()?;
+    let signature = Signature::from_slice(&base64_url::decode(signature).ok()?).ok()?;
+
+    server_keypair
+        .verify(&link_token_payload(id, relay_topic), &signature)
+        .ok()?;
+
+    Some((id, relay_topic.to_string()))
 }
 
 /// Compute a hash given email and verification code.
@@ -1,7 +1,11 @@
 //! This module contains all the models used in the application.
 pub mod account;
+pub mod account_email;
 pub mod app;
 pub mod capability_indexing;
 pub mod email_verification;
+pub mod passkey;
+pub mod recovery_code;
 pub mod revocation;
 pub mod volume;
+pub mod webauthn_challenge;
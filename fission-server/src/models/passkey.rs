@@ -0,0 +1,150 @@
+//! Passkey model
+
+use super::account::AccountRecord;
+use crate::db::{schema::passkeys, Conn};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+};
+use diesel_async::RunQueryDsl;
+use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+/// New passkey (for registering passkeys)
+#[derive(Insertable)]
+#[diesel(table_name = passkeys)]
+struct NewPasskeyRecord {
+    credential_id: String,
+    did: String,
+    passkey: serde_json::Value,
+}
+
+/// The model for a row in the passkeys table
+#[derive(Debug, Queryable, Selectable, Clone, Identifiable, Associations)]
+#[diesel(belongs_to(AccountRecord, foreign_key = account_id))]
+#[diesel(table_name = passkeys)]
+pub struct PasskeyRecord {
+    /// Internal Database Identifier
+    pub id: i32,
+
+    /// Inserted at timestamp
+    pub inserted_at: NaiveDateTime,
+
+    /// Updated at timestamp
+    pub updated_at: NaiveDateTime,
+
+    /// Foreign key to the account. `None` until an account claims the passkey.
+    pub account_id: Option<i32>,
+
+    /// The WebAuthn credential ID, base64url-encoded
+    pub credential_id: String,
+
+    /// The agent DID derived from the passkey's public key
+    pub did: String,
+
+    /// The serialized `webauthn_rs::prelude::Passkey`
+    pub passkey: serde_json::Value,
+}
+
+impl PasskeyRecord {
+    /// Store a freshly registered passkey that's not associated with an account yet.
+    /// `did` is the agent DID derived from the passkey, see `passkeys::passkey_did`.
+    pub async fn new(conn: &mut Conn<'_>, passkey: &Passkey, did: String) -> Result<Self> {
+        let record = NewPasskeyRecord {
+            credential_id: encode_credential_id(passkey),
+            did,
+            passkey: serde_json::to_value(passkey)?,
+        };
+
+        Ok(diesel::insert_into(passkeys::table)
+            .values(&record)
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Associate a passkey that's not associated with any account yet with given account
+    pub async fn claim(
+        conn: &mut Conn<'_>,
+        credential_id: &str,
+        account_id: i32,
+    ) -> Result<Self, diesel::result::Error> {
+        diesel::update(passkeys::table)
+            .filter(passkeys::credential_id.eq(credential_id))
+            .filter(passkeys::account_id.is_null())
+            .set(passkeys::account_id.eq(account_id))
+            .get_result(conn)
+            .await
+    }
+
+    /// Find the passkey of an account with given base64url-encoded credential ID
+    pub async fn find_by_credential_id(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+        credential_id: &str,
+    ) -> Result<Self, diesel::result::Error> {
+        passkeys::table
+            .filter(passkeys::account_id.eq(account_id))
+            .filter(passkeys::credential_id.eq(credential_id))
+            .first(conn)
+            .await
+    }
+
+    /// Delete passkeys that were registered more than a day ago, but never claimed by an account.
+    /// That's as long as the email verification codes needed for creating an account are valid.
+    /// Returns the number of deleted passkeys.
+    pub async fn delete_unclaimed(conn: &mut Conn<'_>) -> Result<usize, diesel::result::Error> {
+        diesel::delete(passkeys::table)
+            .filter(passkeys::account_id.is_null())
+            .filter(passkeys::inserted_at.lt(now - 24.hours()))
+            .execute(conn)
+            .await
+    }
+
+    /// List all passkeys of an account
+    pub async fn find_by_account(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+    ) -> Result<Vec<Self>, diesel::result::Error> {
+        passkeys::table
+            .filter(passkeys::account_id.eq(account_id))
+            .order(passkeys::id)
+            .get_results(conn)
+            .await
+    }
+
+    /// Deserialize the stored passkey
+    pub fn passkey(&self) -> Result<Passkey> {
+        Ok(serde_json::from_value(self.passkey.clone())?)
+    }
+
+    /// Update the stored credential counter & backup state after a successful authentication
+    pub async fn update_credential(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+        result: &AuthenticationResult,
+    ) -> Result<()> {
+        for record in Self::find_by_account(conn, account_id).await? {
+            let mut passkey = record.passkey()?;
+            if passkey.update_credential(result) == Some(true) {
+                diesel::update(passkeys::table)
+                    .filter(passkeys::id.eq(record.id))
+                    .set(passkeys::passkey.eq(serde_json::to_value(&passkey)?))
+                    .execute(conn)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The base64url-encoded credential ID of a passkey, the same way browsers encode `PublicKeyCredential.id`
+pub fn encode_credential_id(passkey: &Passkey) -> String {
+    base64_url::encode(passkey.cred_id().as_ref())
+}
+
+/// The base64url-encoded ID of the credential that was used for an authentication
+pub fn authenticated_credential_id(result: &AuthenticationResult) -> String {
+    base64_url::encode(result.cred_id().as_ref())
+}
@@ -0,0 +1,126 @@
+//! Account recovery code model
+
+use crate::db::{schema::recovery_codes, Conn};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use hex::ToHex;
+use rand::Rng;
+
+/// New recovery code (for generating recovery codes)
+#[derive(Insertable)]
+#[diesel(table_name = recovery_codes)]
+struct NewRecoveryCodeRecord {
+    account_id: i32,
+    code_hash: String,
+}
+
+/// The model for a row in the recovery_codes table
+#[derive(Debug, Queryable, Selectable, Clone)]
+#[diesel(table_name = recovery_codes)]
+pub struct RecoveryCodeRecord {
+    /// Internal Database Identifier
+    pub id: i32,
+
+    /// Inserted at timestamp
+    pub inserted_at: NaiveDateTime,
+
+    /// Updated at timestamp
+    pub updated_at: NaiveDateTime,
+
+    /// Foreign key to the account
+    pub account_id: i32,
+
+    /// Hash of the recovery code, see `hash_recovery_code`
+    pub code_hash: String,
+}
+
+impl RecoveryCodeRecord {
+    /// Generate a new recovery code for an account, replacing any previous one.
+    /// Returns the code. It's not stored in plaintext, so it can't be retrieved later.
+    pub async fn generate(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+    ) -> Result<String, diesel::result::Error> {
+        let code = generate_recovery_code();
+
+        let record = NewRecoveryCodeRecord {
+            account_id,
+            code_hash: hash_recovery_code(&code),
+        };
+
+        diesel::insert_into(recovery_codes::table)
+            .values(&record)
+            .on_conflict(recovery_codes::account_id)
+            .do_update()
+            .set(recovery_codes::code_hash.eq(&record.code_hash))
+            .execute(conn)
+            .await?;
+
+        Ok(code)
+    }
+
+    /// Find the account's recovery code record, if the code matches.
+    pub async fn find(
+        conn: &mut Conn<'_>,
+        account_id: i32,
+        code: &str,
+    ) -> Result<Self, diesel::result::Error> {
+        recovery_codes::table
+            .filter(recovery_codes::account_id.eq(account_id))
+            .filter(recovery_codes::code_hash.eq(hash_recovery_code(code)))
+            .first(conn)
+            .await
+    }
+
+    /// *Use* the recovery code, making it impossible for it to be used again
+    pub async fn consume(self, conn: &mut Conn<'_>) -> Result<(), diesel::result::Error> {
+        diesel::delete(recovery_codes::table.filter(recovery_codes::id.eq(self.id)))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Generate a recovery code like `3f0c9a1e-77b2d4c8-0e5a6f13-c29d8b04`.
+/// Unlike email codes, these need to withstand guessing over a long time, so they have 128 bits of entropy.
+fn generate_recovery_code() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes
+        .chunks(4)
+        .map(|chunk| chunk.encode_hex::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Compute the hash of a recovery code that's stored in the database.
+/// Ignores casing and surrounding whitespace.
+fn hash_recovery_code(code: &str) -> String {
+    blake3::derive_key(
+        "fission server 2024-03-11 account recovery codes",
+        code.trim().to_lowercase().as_bytes(),
+    )
+    .encode_hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_code_format() {
+        let code = generate_recovery_code();
+
+        assert_eq!(code.len(), 35);
+        assert_eq!(code.split('-').count(), 4);
+        assert_eq!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&format!(" {} ", code.to_uppercase()))
+        );
+        assert_ne!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&generate_recovery_code())
+        );
+    }
+}
@@ -1,15 +1,19 @@
 //! Defines UCAN revocation models.
 
-use crate::db::{schema::revocations, Conn};
+use crate::db::{
+    schema::{capabilities, revocations, ucans},
+    Conn,
+};
 use anyhow::Result;
 use diesel::{
     associations::Identifiable, deserialize::Queryable, pg::Pg, prelude::Insertable,
     ExpressionMethods, QueryDsl, Selectable,
 };
 use diesel_async::RunQueryDsl;
-use fission_core::revocation::Revocation;
+use fission_core::{ed_did_key::EdDidKey, revocation::Revocation};
+use rs_ucan::ucan::Ucan;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, str::FromStr};
 use utoipa::ToSchema;
 
 /// Represents a revocation record in the database
@@ -28,6 +32,8 @@ pub struct RevocationRecord {
     pub iss: String,
     /// The revocation signature
     pub challenge: String,
+    /// Resources of the revoked UCAN's capabilities
+    pub resources: Vec<String>,
 }
 
 /// Represents a revocation that wasn't added to the database yet
@@ -41,21 +47,32 @@ pub struct NewRevocationRecord {
     pub iss: String,
     /// The revocation signature
     pub challenge: String,
+    /// Resources of the revoked UCAN's capabilities
+    pub resources: Vec<String>,
 }
 
 impl NewRevocationRecord {
-    /// Turn a fission-core revocation into a new revocation record for the DB
+    /// Turn a fission-core revocation of given UCAN into a new revocation record for the DB
     pub fn new(
         Revocation {
             revoke,
             iss,
             challenge,
         }: Revocation,
+        revoked: &Ucan,
     ) -> Self {
+        let resources = revoked
+            .capabilities()
+            .map(|cap| cap.resource().to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
         Self {
             cid: revoke,
             iss,
             challenge,
+            resources,
         }
     }
 
@@ -71,12 +88,14 @@ impl NewRevocationRecord {
             cid,
             iss,
             challenge,
+            resources,
         } = self;
 
         Ok(RevocationRecord {
             cid,
             iss,
             challenge,
+            resources,
             id,
         })
     }
@@ -96,3 +115,119 @@ pub async fn find_revoked_subset(
     // Convert into set
     Ok(revoked_cids.into_iter().collect())
 }
+
+/// Revoke all UCANs that the server issued for given resource DID.
+///
+/// All indexed UCANs with capabilities for that resource are deleted afterwards,
+/// since we don't need them anymore & they're a liability data-wise.
+///
+/// Returns the number of revocations that were stored.
+pub async fn revoke_resource_ucans(
+    resource_did: &str,
+    server: &EdDidKey,
+    conn: &mut Conn<'_>,
+) -> Result<usize> {
+    let indexed_ucans: Vec<(String, String, i32)> = capabilities::table
+        .inner_join(ucans::table)
+        .filter(capabilities::resource.eq(resource_did))
+        .select((ucans::issuer, ucans::encoded, ucans::id))
+        .get_results(conn)
+        .await?;
+
+    let ucan_ids = indexed_ucans.iter().map(|(_, _, ucan_id)| ucan_id);
+
+    let revocation_records = indexed_ucans
+        .iter()
+        .filter(|(issuer, _, _)| issuer == server.did_as_str())
+        .map(|(_, encoded, _)| ucan_revocation(server, encoded))
+        .collect::<Result<Vec<NewRevocationRecord>>>()?;
+
+    let revoked = revocation_records.len();
+
+    diesel::insert_into(revocations::table)
+        .values(revocation_records)
+        .execute(conn)
+        .await?;
+
+    diesel::delete(ucans::table)
+        .filter(ucans::id.eq_any(ucan_ids))
+        .execute(conn)
+        .await?;
+
+    Ok(revoked)
+}
+
+/// Revoke all UCANs that the server issued for given resource DID to agents,
+/// e.g. when recovering an account whose agents might be compromised.
+///
+/// Unlike `revoke_resource_ucans`, this keeps the resource's delegation to the server,
+/// so the server can issue new agent UCANs afterwards.
+/// The revoked UCANs are deleted from the index.
+///
+/// Returns the number of revocations that were stored.
+pub async fn revoke_agent_ucans(
+    resource_did: &str,
+    server: &EdDidKey,
+    conn: &mut Conn<'_>,
+) -> Result<usize> {
+    revoke_server_issued_ucans(resource_did, None, server, conn).await
+}
+
+/// Revoke the UCANs that the server issued for given resource DID to a single agent DID,
+/// e.g. when a device of an account got lost.
+///
+/// Returns the number of revocations that were stored.
+pub async fn revoke_device_ucans(
+    resource_did: &str,
+    agent_did: &str,
+    server: &EdDidKey,
+    conn: &mut Conn<'_>,
+) -> Result<usize> {
+    revoke_server_issued_ucans(resource_did, Some(agent_did), server, conn).await
+}
+
+async fn revoke_server_issued_ucans(
+    resource_did: &str,
+    audience: Option<&str>,
+    server: &EdDidKey,
+    conn: &mut Conn<'_>,
+) -> Result<usize> {
+    let mut query = capabilities::table
+        .inner_join(ucans::table)
+        .filter(capabilities::resource.eq(resource_did))
+        .filter(ucans::issuer.eq(server.did_as_str()))
+        .select((ucans::encoded, ucans::id))
+        .distinct()
+        .into_boxed();
+
+    if let Some(audience) = audience {
+        query = query.filter(ucans::audience.eq(audience));
+    }
+
+    let agent_ucans: Vec<(String, i32)> = query.get_results(conn).await?;
+
+    let revocation_records = agent_ucans
+        .iter()
+        .map(|(encoded, _)| ucan_revocation(server, encoded))
+        .collect::<Result<Vec<NewRevocationRecord>>>()?;
+
+    let revoked = revocation_records.len();
+
+    diesel::insert_into(revocations::table)
+        .values(revocation_records)
+        .execute(conn)
+        .await?;
+
+    diesel::delete(ucans::table)
+        .filter(ucans::id.eq_any(agent_ucans.iter().map(|(_, ucan_id)| ucan_id)))
+        .execute(conn)
+        .await?;
+
+    Ok(revoked)
+}
+
+fn ucan_revocation(issuer: &EdDidKey, encoded_ucan: &str) -> Result<NewRevocationRecord> {
+    let ucan: Ucan = Ucan::from_str(encoded_ucan)?;
+    let revocation = Revocation::new(issuer, &ucan)?;
+    Ok(NewRevocationRecord::new(revocation, &ucan))
+}
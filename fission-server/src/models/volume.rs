@@ -1,19 +1,31 @@
 //! Volume model
 
 use anyhow::Result;
+use cid::Cid;
+use libipld::{codec::Codec, Ipld, IpldCodec};
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    str::FromStr,
+};
 
 use chrono::NaiveDateTime;
-use diesel::prelude::*;
+use diesel::{dsl::exists, prelude::*};
 use utoipa::ToSchema;
 
 use diesel_async::RunQueryDsl;
 
 use crate::{
-    db::{schema::volumes, Conn},
+    db::{
+        schema::{private_volume_blocks, volumes},
+        Conn,
+    },
     setups::IpfsDatabase,
 };
 
+/// How many block CIDs of a private volume are inserted per query
+const INSERT_BATCH_SIZE: usize = 10_000;
+
 #[derive(Debug, Queryable, Insertable, Clone, Identifiable, Selectable, ToSchema)]
 #[diesel(table_name = volumes)]
 /// App model
@@ -30,6 +42,12 @@ pub struct Volume {
 
     /// CID of the Storage Volume
     pub cid: String,
+
+    /// DID of the account or app that pushed this volume
+    pub owner_did: Option<String>,
+
+    /// Whether pulling this volume requires a `volume/read` capability from its owner
+    pub private: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone, Insertable, Debug, ToSchema)]
@@ -38,6 +56,10 @@ pub struct Volume {
 pub struct NewVolumeRecord {
     /// Content ID of the volume
     pub cid: String,
+    /// DID of the account or app that pushed this volume
+    pub owner_did: Option<String>,
+    /// Whether the volume is private
+    pub private: bool,
 }
 
 impl Default for NewVolumeRecord {
@@ -45,27 +67,47 @@ impl Default for NewVolumeRecord {
     fn default() -> Self {
         Self {
             cid: "".to_string(),
+            owner_did: None,
+            private: false,
         }
     }
 }
 
 impl From<Volume> for NewVolumeRecord {
     fn from(volume: Volume) -> Self {
-        Self { cid: volume.cid }
+        Self {
+            cid: volume.cid,
+            owner_did: volume.owner_did,
+            private: volume.private,
+        }
     }
 }
 
 impl Volume {
     /// Create a new Volume. Inserts the volume into the database.
-    pub async fn new(conn: &mut Conn<'_>, cid: &str) -> Result<Self> {
+    pub async fn new(
+        conn: &mut Conn<'_>,
+        cid: &str,
+        owner_did: &str,
+        private: bool,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<Self> {
         let new_volume = NewVolumeRecord {
             cid: cid.to_string(),
+            owner_did: Some(owner_did.to_string()),
+            private,
         };
 
-        Ok(diesel::insert_into(volumes::table)
+        let volume: Self = diesel::insert_into(volumes::table)
             .values(new_volume)
             .get_result(conn)
-            .await?)
+            .await?;
+
+        if volume.private {
+            volume.index_blocks(conn, ipfs_db).await?;
+        }
+
+        Ok(volume)
     }
 
     /// Find a volume by its primary key
@@ -85,10 +127,250 @@ impl Volume {
     ) -> Result<Self> {
         ipfs_db.pin_add(cid, true).await?;
 
-        Ok(diesel::update(volumes::table)
+        let volume: Self = diesel::update(volumes::table)
             .filter(volumes::id.eq(self.id))
             .set(volumes::cid.eq(cid))
             .get_result(conn)
-            .await?)
+            .await?;
+
+        if volume.private {
+            volume.index_blocks(conn, ipfs_db).await?;
+        }
+
+        Ok(volume)
+    }
+
+    /// Mark a volume as private or public
+    pub async fn set_private(
+        conn: &mut Conn<'_>,
+        id: i32,
+        private: bool,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<()> {
+        let volume: Self = diesel::update(volumes::table)
+            .filter(volumes::id.eq(id))
+            .set(volumes::private.eq(private))
+            .get_result(conn)
+            .await?;
+
+        if private {
+            volume.index_blocks(conn, ipfs_db).await?;
+        } else {
+            volume.unindex_blocks(conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Remember all blocks in this volume's DAG,
+    /// so they're protected just like the volume's root while it's private.
+    ///
+    /// Blocks that were indexed before stay indexed, even if they're not part
+    /// of the volume anymore, since the blockstore still has them.
+    pub async fn index_blocks(
+        &self,
+        conn: &mut Conn<'_>,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<()> {
+        // The null volume doesn't have any blocks
+        let Ok(root) = Cid::from_str(&self.cid) else {
+            return Ok(());
+        };
+
+        let raw_cids = dag_cids(ipfs_db, root)
+            .await?
+            .iter()
+            .map(raw_cid)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        for batch in raw_cids.chunks(INSERT_BATCH_SIZE) {
+            let rows = batch
+                .iter()
+                .map(|raw_cid| {
+                    (
+                        private_volume_blocks::volume_id.eq(self.id),
+                        private_volume_blocks::raw_cid.eq(raw_cid),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            diesel::insert_into(private_volume_blocks::table)
+                .values(rows)
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop protecting the blocks of this volume after it was made public.
+    ///
+    /// Earlier versions of the volume are published along with it, since their blocks
+    /// stay indexed under them while the volume is private.
+    async fn unindex_blocks(&self, conn: &mut Conn<'_>) -> Result<()> {
+        let versions: Vec<i32> = match &self.owner_did {
+            Some(owner_did) => {
+                diesel::update(volumes::table)
+                    .filter(volumes::owner_did.eq(owner_did))
+                    .set(volumes::private.eq(false))
+                    .returning(volumes::id)
+                    .get_results(conn)
+                    .await?
+            }
+            None => vec![self.id],
+        };
+
+        diesel::delete(private_volume_blocks::table)
+            .filter(private_volume_blocks::volume_id.eq_any(versions))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Index the blocks of all private volumes that aren't indexed yet,
+    /// e.g. because they were made private before blocks were indexed.
+    ///
+    /// Returns the number of volumes that were indexed.
+    pub async fn index_unindexed_private(
+        conn: &mut Conn<'_>,
+        ipfs_db: &impl IpfsDatabase,
+    ) -> Result<usize> {
+        let unindexed: Vec<Self> = volumes::table
+            .filter(volumes::private.eq(true))
+            .filter(volumes::cid.ne(""))
+            .filter(
+                volumes::id
+                    .ne_all(private_volume_blocks::table.select(private_volume_blocks::volume_id)),
+            )
+            .get_results(conn)
+            .await?;
+
+        for volume in &unindexed {
+            volume.index_blocks(conn, ipfs_db).await?;
+        }
+
+        Ok(unindexed.len())
+    }
+
+    /// Whether there are any private volumes at all
+    pub async fn any_private(conn: &mut Conn<'_>) -> Result<bool> {
+        Ok(
+            diesel::select(exists(volumes::table.filter(volumes::private.eq(true))))
+                .get_result(conn)
+                .await?,
+        )
     }
+
+    /// Find the owners of private volumes containing given CIDs,
+    /// either as their root or as any block within them.
+    ///
+    /// Any of them can authorize pulling that CID.
+    /// CIDs that aren't part of any private volume are missing from the returned map.
+    pub async fn find_private_owners(
+        conn: &mut Conn<'_>,
+        cids: &[Cid],
+    ) -> Result<BTreeMap<Cid, BTreeSet<String>>> {
+        let mut owners = Self::find_private_block_owners(conn, cids).await?;
+
+        // Volumes that were made private before their blocks were indexed
+        let cid_strings = cids.iter().map(Cid::to_string).collect::<Vec<_>>();
+        let roots: Vec<(String, Option<String>)> = volumes::table
+            .filter(volumes::cid.eq_any(&cid_strings))
+            .filter(volumes::private.eq(true))
+            .select((volumes::cid, volumes::owner_did))
+            .distinct()
+            .get_results(conn)
+            .await?;
+
+        for (root, owner) in roots {
+            if let (Ok(root), Some(owner)) = (Cid::from_str(&root), owner) {
+                owners.entry(root).or_default().insert(owner);
+            }
+        }
+
+        Ok(owners)
+    }
+
+    /// Like `find_private_owners`, but only looks at indexed blocks, which is cheaper.
+    ///
+    /// Blocks are found no matter which codec or CID version they're requested with.
+    pub async fn find_private_block_owners(
+        conn: &mut Conn<'_>,
+        cids: &[Cid],
+    ) -> Result<BTreeMap<Cid, BTreeSet<String>>> {
+        let mut by_raw_cid = HashMap::<String, Vec<Cid>>::new();
+        for cid in cids {
+            by_raw_cid.entry(raw_cid(cid)).or_default().push(*cid);
+        }
+        let raw_cids = by_raw_cid.keys().collect::<Vec<_>>();
+
+        let blocks: Vec<(String, Option<String>)> = private_volume_blocks::table
+            .inner_join(volumes::table)
+            .filter(private_volume_blocks::raw_cid.eq_any(raw_cids))
+            .filter(volumes::private.eq(true))
+            .select((private_volume_blocks::raw_cid, volumes::owner_did))
+            .distinct()
+            .get_results(conn)
+            .await?;
+
+        let mut owners = BTreeMap::<Cid, BTreeSet<String>>::new();
+        for (raw_cid, owner) in blocks {
+            let Some(owner) = owner else {
+                continue;
+            };
+            for cid in by_raw_cid.get(&raw_cid).into_iter().flatten() {
+                owners.entry(*cid).or_default().insert(owner.clone());
+            }
+        }
+
+        Ok(owners)
+    }
+}
+
+/// The key that blocks of private volumes are indexed by.
+///
+/// The blockstore finds blocks by their multihash alone, so the same block can be
+/// requested with any codec or CID version. All of them map to the same CIDv1 with
+/// the raw codec.
+pub fn raw_cid(cid: &Cid) -> String {
+    Cid::new_v1(IpldCodec::Raw.into(), *cid.hash()).to_string()
+}
+
+/// The CIDs that given block links to.
+///
+/// Blocks with codecs we don't know don't link anywhere as far as we can tell.
+pub fn block_links(cid: &Cid, block: &[u8]) -> Result<Vec<Cid>> {
+    let mut links = Vec::new();
+
+    if let Ok(codec) = IpldCodec::try_from(cid.codec()) {
+        codec.references::<Ipld, _>(block, &mut links)?;
+    }
+
+    Ok(links)
+}
+
+/// The CIDs of all blocks in the DAG below `root`, including `root` itself.
+///
+/// Blocks that the server doesn't have are included, but can't be descended into.
+pub async fn dag_cids(ipfs_db: &impl IpfsDatabase, root: Cid) -> Result<BTreeSet<Cid>> {
+    let mut cids = BTreeSet::from([root]);
+    let mut queue = VecDeque::from([root]);
+
+    while let Some(cid) = queue.pop_front() {
+        let Some(block) = ipfs_db.block_get(&cid.to_string()).await? else {
+            continue;
+        };
+
+        for link in block_links(&cid, &block)? {
+            if cids.insert(link) {
+                queue.push_back(link);
+            }
+        }
+    }
+
+    Ok(cids)
 }
@@ -0,0 +1,92 @@
+//! WebAuthn ceremony state model
+
+use crate::db::{schema::webauthn_challenges, Conn};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{
+    dsl::{now, IntervalDsl},
+    prelude::*,
+};
+use diesel_async::RunQueryDsl;
+use hex::ToHex;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// New WebAuthn ceremony state
+#[derive(Insertable)]
+#[diesel(table_name = webauthn_challenges)]
+struct NewWebauthnChallengeRecord {
+    challenge_id: String,
+    account_id: Option<i32>,
+    state: serde_json::Value,
+}
+
+/// The model for a row in the webauthn_challenges table.
+/// Stores the server-side state of an in-progress registration or authentication ceremony.
+#[derive(Debug, Queryable, Selectable, Clone)]
+#[diesel(table_name = webauthn_challenges)]
+pub struct WebauthnChallengeRecord {
+    /// Internal Database Identifier
+    pub id: i32,
+
+    /// Inserted at timestamp
+    pub inserted_at: NaiveDateTime,
+
+    /// Updated at timestamp
+    pub updated_at: NaiveDateTime,
+
+    /// The random ID that clients refer to this ceremony with
+    pub challenge_id: String,
+
+    /// The account that's authenticating, if this is an authentication ceremony
+    pub account_id: Option<i32>,
+
+    /// Serialized `PasskeyRegistration` or `PasskeyAuthentication` state
+    pub state: serde_json::Value,
+}
+
+impl WebauthnChallengeRecord {
+    /// Store the state of a new ceremony. Returns the generated challenge ID.
+    pub async fn new(
+        conn: &mut Conn<'_>,
+        state: &impl Serialize,
+        account_id: Option<i32>,
+    ) -> Result<String> {
+        let challenge_id = rand::thread_rng().gen::<[u8; 16]>().encode_hex::<String>();
+
+        diesel::insert_into(webauthn_challenges::table)
+            .values(NewWebauthnChallengeRecord {
+                challenge_id: challenge_id.clone(),
+                account_id,
+                state: serde_json::to_value(state)?,
+            })
+            .execute(conn)
+            .await?;
+
+        Ok(challenge_id)
+    }
+
+    /// Remove the ceremony state from the database, so it can't be used again, and return it.
+    /// Ceremonies expire after 5 minutes.
+    pub async fn take<T: DeserializeOwned>(
+        conn: &mut Conn<'_>,
+        challenge_id: &str,
+    ) -> Result<(T, Option<i32>)> {
+        let record: Self = diesel::delete(webauthn_challenges::table)
+            .filter(webauthn_challenges::challenge_id.eq(challenge_id))
+            .filter(webauthn_challenges::inserted_at.ge(now - 5.minutes()))
+            .get_result(conn)
+            .await?;
+
+        Ok((serde_json::from_value(record.state)?, record.account_id))
+    }
+
+    /// Delete the state of all ceremonies that expired without being finished.
+    /// Returns the number of deleted ceremonies.
+    pub async fn delete_expired(conn: &mut Conn<'_>) -> Result<usize, diesel::result::Error> {
+        diesel::delete(webauthn_challenges::table)
+            .filter(webauthn_challenges::inserted_at.lt(now - 5.minutes()))
+            .execute(conn)
+            .await
+    }
+}
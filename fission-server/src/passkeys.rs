@@ -0,0 +1,88 @@
+//! WebAuthn relying party setup and deriving agent DIDs from passkeys
+
+use crate::settings;
+use anyhow::{bail, Context, Result};
+use url::Url;
+use webauthn_rs::{Webauthn, WebauthnBuilder};
+use webauthn_rs_core::proto::{COSEKey, COSEKeyType, ECDSACurve, EDDSACurve};
+
+/// Multicodec prefix (varint-encoded) for ed25519 public keys
+const ED25519_PUB_MULTICODEC: [u8; 2] = [0xed, 0x01];
+/// Multicodec prefix (varint-encoded) for compressed P-256 public keys
+const P256_PUB_MULTICODEC: [u8; 2] = [0x80, 0x24];
+
+/// Set up the WebAuthn relying party from settings
+pub fn build_webauthn(settings: &settings::Webauthn) -> Result<Webauthn> {
+    let rp_origin = Url::parse(&settings.rp_origin).context("Invalid webauthn.rp_origin")?;
+
+    let webauthn = WebauthnBuilder::new(&settings.rp_id, &rp_origin)?
+        .rp_name(&settings.rp_name)
+        .build()?;
+
+    Ok(webauthn)
+}
+
+/// Derive the `did:key` of a passkey from its public key.
+///
+/// Only ES256 (P-256) and EdDSA (Ed25519) passkeys are supported.
+pub fn passkey_did(public_key: &COSEKey) -> Result<String> {
+    match &public_key.key {
+        COSEKeyType::EC_EC2(ec2) if matches!(ec2.curve, ECDSACurve::SECP256R1) => {
+            p256_did_key(ec2.x.as_ref(), ec2.y.as_ref())
+        }
+        COSEKeyType::EC_OKP(okp) if matches!(okp.curve, EDDSACurve::ED25519) => {
+            Ok(ed25519_did_key(okp.x.as_ref()))
+        }
+        _ => bail!("Unsupported passkey algorithm: {:?}", public_key.type_),
+    }
+}
+
+fn ed25519_did_key(public_key: &[u8]) -> String {
+    multicodec_did_key(&ED25519_PUB_MULTICODEC, public_key)
+}
+
+fn p256_did_key(x: &[u8], y: &[u8]) -> Result<String> {
+    if x.len() != 32 || y.len() != 32 {
+        bail!("Invalid P-256 public key coordinates");
+    }
+
+    // SEC1 point compression: The prefix encodes the parity of y
+    let prefix = if y[31] & 1 == 0 { 0x02 } else { 0x03 };
+    let compressed = [&[prefix], x].concat();
+
+    Ok(multicodec_did_key(&P256_PUB_MULTICODEC, &compressed))
+}
+
+fn multicodec_did_key(multicodec: &[u8], public_key: &[u8]) -> String {
+    let bytes = [multicodec, public_key].concat();
+    format!("did:key:z{}", bs58::encode(bytes).into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fission_core::ed_did_key::EdDidKey;
+    use testresult::TestResult;
+
+    #[test]
+    fn test_ed25519_did_key_matches_ed_did_key() -> TestResult {
+        let signing_key = ed25519_dalek::SigningKey::generate(&mut rand::thread_rng());
+        let public_key = signing_key.verifying_key().to_bytes();
+        let expected = EdDidKey::new(signing_key).did();
+
+        assert_eq!(ed25519_did_key(&public_key), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_p256_did_key_prefix() -> TestResult {
+        let did = p256_did_key(&[1; 32], &[2; 32])?;
+
+        assert!(did.starts_with("did:key:zDn"));
+        assert_ne!(did, p256_did_key(&[1; 32], &[3; 32])?);
+        assert!(p256_did_key(&[1; 31], &[2; 32]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+//! Rate limiting for unauthenticated endpoints that cause side effects, e.g. sending emails
+
+use crate::{
+    error::{AppError, AppResult},
+    settings,
+    setups::RateLimitStore,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use http::{HeaderMap, StatusCode};
+use std::{
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The de-facto standard header that reverse proxies append the client IP to
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Only prune expired windows once there are more than this many keys
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// IPv6 clients usually get at least a /64 prefix, so they're limited per prefix
+const IPV6_PREFIX_LEN: u32 = 64;
+
+/// Counters that start over from zero once their fixed window passed.
+///
+/// Each key may use a different window length.
+#[derive(Debug)]
+pub struct ExpiringCounters<K: Eq + Hash> {
+    windows: DashMap<K, Window>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started_at: Instant,
+    length: Duration,
+    count: u64,
+}
+
+impl Window {
+    fn new(started_at: Instant, length: Duration) -> Self {
+        Self {
+            started_at,
+            length,
+            count: 0,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.started_at) >= self.length
+    }
+}
+
+impl<K: Eq + Hash> Default for ExpiringCounters<K> {
+    fn default() -> Self {
+        Self {
+            windows: DashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> ExpiringCounters<K> {
+    /// The current count of `key`, zero if its window passed
+    pub fn get(&self, key: &K) -> u64 {
+        let now = Instant::now();
+        self.windows
+            .get(key)
+            .filter(|w| !w.is_expired(now))
+            .map_or(0, |w| w.count)
+    }
+
+    /// Add `amount` to the count of `key`.
+    /// Starts a new window of given length, if there is none yet or it passed.
+    ///
+    /// Returns the new count and how long until the window passes.
+    pub fn add(&self, key: K, amount: u64, window: Duration) -> (u64, Duration) {
+        let now = Instant::now();
+
+        // Keys may use different windows, so each is pruned by its own
+        if self.windows.len() > PRUNE_THRESHOLD {
+            self.windows.retain(|_, w| !w.is_expired(now));
+        }
+
+        let mut entry = self.windows.entry(key).or_insert(Window::new(now, window));
+
+        if entry.is_expired(now) {
+            *entry = Window::new(now, window);
+        }
+
+        entry.count = entry.count.saturating_add(amount);
+        (
+            entry.count,
+            entry.length - now.duration_since(entry.started_at),
+        )
+    }
+
+    /// Forget about the count of `key`
+    pub fn remove(&self, key: &K) {
+        self.windows.remove(key);
+    }
+}
+
+/// A `RateLimitStore` that keeps fixed windows in memory.
+///
+/// Limits aren't shared between multiple server instances and reset on restart.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRateLimitStore {
+    hits: Arc<ExpiringCounters<String>>,
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn hit(&self, key: &str, max_hits: u32, window: Duration) -> Result<Option<Duration>> {
+        let (hits, remaining) = self.hits.add(key.to_string(), 1, window);
+
+        if hits > u64::from(max_hits) {
+            return Ok(Some(remaining));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Enforces the configured rate limits using a `RateLimitStore`
+#[derive(Debug, Clone)]
+pub struct RateLimiter<R: RateLimitStore> {
+    store: R,
+    settings: Arc<settings::RateLimits>,
+}
+
+impl<R: RateLimitStore> RateLimiter<R> {
+    /// Create a rate limiter backed by given store
+    pub fn new(store: R, settings: settings::RateLimits) -> Self {
+        Self {
+            store,
+            settings: Arc::new(settings),
+        }
+    }
+
+    /// Determine the IP of the client that made a request.
+    ///
+    /// Uses the `X-Forwarded-For` header only if the direct peer is a trusted proxy.
+    /// Entries added by other trusted proxies are skipped, so the rightmost untrusted
+    /// address is used. Anything to the left of it may have been forged by the client.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let trusted = &self.settings.trusted_proxies;
+
+        if !trusted.contains(&peer) {
+            return peer;
+        }
+
+        let forwarded = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|entry| entry.trim().parse::<IpAddr>())
+            .collect::<Vec<_>>();
+
+        let mut client_ip = peer;
+        for entry in forwarded.into_iter().rev() {
+            let Ok(ip) = entry else {
+                break;
+            };
+            client_ip = ip;
+            if !trusted.contains(&ip) {
+                break;
+            }
+        }
+
+        client_ip
+    }
+
+    /// Count a request for an email verification code.
+    ///
+    /// Returns status 429 with a `Retry-After` header, if either the client IP
+    /// or the email address exceeded their limit.
+    pub async fn check_email_verification(&self, email: &str, client_ip: IpAddr) -> AppResult<()> {
+        let settings = &self.settings;
+
+        let limits = [
+            (
+                format!("email-verification:ip:{}", ip_key(client_ip)),
+                settings.ip_max_requests,
+                settings.ip_window(),
+            ),
+            (
+                format!("email-verification:email:{}", email.to_lowercase()),
+                settings.email_max_requests,
+                settings.email_window(),
+            ),
+        ];
+
+        for (key, max_hits, window) in limits {
+            self.check(
+                &key,
+                max_hits,
+                window,
+                "Too many verification code requests. Please try again later.",
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Count an attempt to use an email verification code.
+    ///
+    /// Returns status 429 with a `Retry-After` header, if the client IP exceeded its limit.
+    /// Other than the failed attempts counted per code, this also limits guessing
+    /// the codes of many different email addresses.
+    pub async fn check_code_attempt(&self, client_ip: IpAddr) -> AppResult<()> {
+        let settings = &self.settings;
+
+        self.check(
+            &format!("code-attempt:ip:{}", ip_key(client_ip)),
+            settings.ip_max_code_attempts,
+            settings.ip_window(),
+            "Too many verification code attempts. Please try again later.",
+        )
+        .await
+    }
+
+    async fn check(
+        &self,
+        key: &str,
+        max_hits: u32,
+        window: Duration,
+        message: &'static str,
+    ) -> AppResult<()> {
+        if let Some(retry_after) = self.store.hit(key, max_hits, window).await? {
+            tracing::warn!(key, ?retry_after, "Rate limit exceeded");
+            return Err(AppError::new(StatusCode::TOO_MANY_REQUESTS, Some(message))
+                .with_retry_after(retry_after));
+        }
+
+        Ok(())
+    }
+}
+
+/// The part of an IP address that identifies a client for rate limiting.
+///
+/// A single IPv6 client can pick from many addresses within its prefix,
+/// so these are limited by prefix instead.
+fn ip_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.to_string(),
+            None => {
+                let mask = u128::MAX << (128 - IPV6_PREFIX_LEN);
+                let prefix = Ipv6Addr::from(u128::from(ip) & mask);
+                format!("{prefix}/{IPV6_PREFIX_LEN}")
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testresult::TestResult;
+
+    #[test_log::test(tokio::test)]
+    async fn test_in_memory_store_limits_within_window() -> TestResult {
+        let store = InMemoryRateLimitStore::default();
+        let window = Duration::from_secs(60);
+
+        assert_eq!(store.hit("a", 2, window).await?, None);
+        assert_eq!(store.hit("a", 2, window).await?, None);
+
+        let retry_after = store.hit("a", 2, window).await?;
+        assert!(matches!(retry_after, Some(d) if d <= window));
+
+        // Other keys are counted separately
+        assert_eq!(store.hit("b", 2, window).await?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_ip_uses_forwarded_header_from_trusted_proxies() -> TestResult {
+        let proxy: IpAddr = "10.0.0.1".parse()?;
+        let limiter = RateLimiter::new(
+            InMemoryRateLimitStore::default(),
+            settings::RateLimits {
+                trusted_proxies: vec![proxy],
+                ..Default::default()
+            },
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, "1.1.1.1, 203.0.113.7, 10.0.0.1".parse()?);
+
+        // The forged leftmost entry and the trusted proxy entry are skipped
+        assert_eq!(
+            limiter.client_ip(proxy, &headers),
+            "203.0.113.7".parse::<IpAddr>()?
+        );
+
+        // Other peers can't pick their IP
+        let peer: IpAddr = "198.51.100.2".parse()?;
+        assert_eq!(limiter.client_ip(peer, &headers), peer);
+
+        // Without the header, the proxy itself is the client
+        assert_eq!(limiter.client_ip(proxy, &HeaderMap::new()), proxy);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ip_key_groups_ipv6_prefixes() -> TestResult {
+        assert_eq!(ip_key("203.0.113.7".parse()?), "203.0.113.7");
+        assert_eq!(ip_key("::ffff:203.0.113.7".parse()?), "203.0.113.7");
+        assert_eq!(
+            ip_key("2001:db8:1:2:3:4:5:6".parse()?),
+            ip_key("2001:db8:1:2:ffff::1".parse()?)
+        );
+        assert_ne!(
+            ip_key("2001:db8:1:2::1".parse()?),
+            ip_key("2001:db8:1:3::1".parse()?)
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_in_memory_store_prunes_by_each_window() -> TestResult {
+        let store = InMemoryRateLimitStore::default();
+        let long = Duration::from_secs(60);
+
+        assert_eq!(store.hit("long", 1, long).await?, None);
+        for i in 0..=PRUNE_THRESHOLD {
+            store
+                .hit(&format!("short-{i}"), 1, Duration::from_millis(1))
+                .await?;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+
+        // Pruning with a short window doesn't forget about longer ones
+        assert_eq!(store.hit("other", 1, Duration::from_millis(1)).await?, None);
+        assert!(store.hit("long", 1, long).await?.is_some());
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_in_memory_store_resets_after_window() -> TestResult {
+        let store = InMemoryRateLimitStore::default();
+        let window = Duration::from_millis(10);
+
+        assert_eq!(store.hit("a", 1, window).await?, None);
+        assert!(store.hit("a", 1, window).await?.is_some());
+
+        tokio::time::sleep(window).await;
+
+        assert_eq!(store.hit("a", 1, window).await?, None);
+
+        Ok(())
+    }
+}
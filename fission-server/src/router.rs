@@ -4,8 +4,8 @@ use crate::{
     app_state::AppState,
     middleware::logging::{log_request_response, DebugOnlyLogger, Logger},
     routes::{
-        account, auth, capability_indexing, doh, fallback::notfound_404, health, ipfs, ping,
-        revocations, volume, ws,
+        account, app, auth, capability_indexing, doh, fallback::notfound_404, health, ipfs, ping,
+        revocations, volume, webauthn, ws,
     },
     setups::ServerSetup,
 };
@@ -32,20 +32,66 @@ pub fn setup_app_router<S: ServerSetup>(app_state: AppState<S>) -> Router {
     let api_router = Router::new()
         .route("/relay/:topic", get(ws::handler))
         .route("/auth/email/verify", post(auth::request_token))
+        .route(
+            "/auth/email/link/:token",
+            get(auth::open_link).post(auth::confirm_link),
+        )
+        .route(
+            "/auth/webauthn/register/start",
+            post(webauthn::start_registration),
+        )
+        .route(
+            "/auth/webauthn/register/finish",
+            post(webauthn::finish_registration),
+        )
+        .route("/auth/webauthn/login/start", post(webauthn::start_login))
+        .route("/auth/webauthn/login/finish", post(webauthn::finish_login))
         .route("/account", post(account::create_account))
         .route("/account", delete(account::delete_account))
         .route("/account", get(account::get_account))
         .route("/account/member-number", get(account::get_member_number))
+        .route("/account/export", get(account::export_account))
         .route("/account/:did/link", post(account::link_account))
+        .route("/account/:did/restore", post(account::restore_account))
+        .route("/account/:did/recover", post(account::recover_account))
+        .route(
+            "/account/recovery-code",
+            post(account::create_recovery_code),
+        )
         .route(
             "/account/username/:username",
             patch(account::patch_username),
         )
+        .route(
+            "/account/username/:username/available",
+            get(account::get_username_available),
+        )
+        .route("/account/email/verify", post(account::request_email_change))
+        .route("/account/email", patch(account::patch_email))
+        .route("/account/emails", get(account::list_emails))
+        .route("/account/emails", post(account::add_email))
+        .route("/account/emails/:email", delete(account::remove_email))
+        .route("/account/emails/:email/verify", post(account::verify_email))
+        .route(
+            "/account/emails/:email/primary",
+            put(account::set_primary_email),
+        )
+        .route("/account/devices", get(account::list_devices))
+        .route("/account/devices/:did", delete(account::revoke_device))
         .route("/account/handle/:handle", patch(account::patch_handle))
+        .route(
+            "/account/handle/:handle/check",
+            get(account::check_handle_setup),
+        )
         .route("/account/handle", delete(account::delete_handle))
+        .route("/apps", post(app::create_app))
+        .route("/apps", get(app::list_apps))
+        .route("/apps/:did", delete(app::delete_app))
+        .route("/apps/volume/push/:cid", put(app::push_app_volume_cid))
         .route("/volume/push/:cid", put(volume::push_volume_cid))
         .route("/volume/pull/:cid", get(volume::pull_volume_cid))
         .route("/volume/pull/:cid", post(volume::pull_volume_cid))
+        .route("/volume/visibility", patch(volume::patch_volume_visibility))
         .route("/capabilities", get(capability_indexing::get_capabilities))
         .route("/revocations", post(revocations::post_revocation))
         .with_state(app_state.clone())
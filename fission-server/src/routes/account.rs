@@ -3,41 +3,43 @@
 use crate::{
     app_state::AppState,
     authority::Authority,
-    db::{self, schema::accounts},
+    db::{self, schema::accounts, Conn},
     error::{AppError, AppResult},
-    extract::{
-        doh::{encode_query_as_request, DnsQuery},
-        json::Json,
-    },
+    extract::{client_ip::ClientIp, dag_cbor::DagCbor, json::Json},
+    handle_verification::check_handle,
     models::{
         account::{AccountAndAuth, AccountRecord},
+        account_email::AccountEmailRecord,
         email_verification::EmailVerification,
-        revocation::NewRevocationRecord,
+        passkey::PasskeyRecord,
+        recovery_code::RecoveryCodeRecord,
     },
-    setups::ServerSetup,
+    routes::auth::send_verification,
+    settings,
+    setups::{ServerSetup, VerificationCodeSender},
 };
-use anyhow::{anyhow, Result};
 use axum::{
     extract::{Path, State},
     http::StatusCode,
 };
+use chrono::{NaiveDateTime, Utc};
 use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
 use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
 use fission_core::{
-    capabilities::{did::Did, fission::FissionAbility},
+    capabilities::{
+        did::Did,
+        fission::{AccountOperation, FissionAbility},
+    },
     common::{
-        Account, AccountCreationRequest, AccountLinkRequest, MemberNumberResponse, SuccessResponse,
+        Account, AccountCreationRequest, AccountEmailVerifyRequest, AccountEmailsResponse,
+        AccountExport, AccountLinkRequest, AccountRecoveryRequest, AccountRestoreRequest,
+        DevicesResponse, EmailChangeRequest, EmailVerifyRequest, HandleCheckResponse,
+        MemberNumberResponse, RecoveryCodeResponse, SuccessResponse, UsernameAvailableResponse,
+        VerificationPurpose,
     },
-    ed_did_key::EdDidKey,
-    revocation::Revocation,
     username::{Handle, Username},
 };
-use hickory_server::proto::{rr::RecordType, serialize::binary::BinDecodable};
-use rs_ucan::ucan::Ucan;
-use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    str::FromStr,
-};
+use std::str::FromStr;
 use tracing::debug;
 use validator::Validate;
 
@@ -53,29 +55,41 @@ use validator::Validate;
         (status = 201, description = "Successfully created account", body = AccountAndAuth),
         (status = 400, description = "Bad Request"),
         (status = 403, description = "Forbidden"),
+        (status = 422, description = "Username not allowed or unknown passkey", body = AppError),
+        (status = 429, description = "Too many verification code attempts from this client"),
     )
 )]
 pub async fn create_account<S: ServerSetup>(
     State(state): State<AppState<S>>,
     authority: Authority,
+    client_ip: ClientIp,
     Json(request): Json<AccountCreationRequest>,
 ) -> AppResult<(StatusCode, Json<AccountAndAuth>)> {
     request
         .validate()
         .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
 
+    if state.username_denylist.is_denied(&request.username) {
+        return Err(username_denied_error());
+    }
+
     let Did(did) = authority
         .get_capability(&state, FissionAbility::AccountCreate)
         .await?;
 
     let conn = &mut db::connect(&state.db_pool).await?;
+
+    // Looked up outside the transaction, so failed attempts are recorded
+    let verification = find_token(&state, client_ip, &request.email, &request.code, conn).await?;
+
+    ensure_verification_usable_for(&verification, VerificationPurpose::Signup, &did)?;
+
+    debug!("Found EmailVerification {verification:?}");
+
     conn.transaction(|conn| {
         async move {
-            let verification = EmailVerification::find_token(conn, &request.email, &request.code)
-                .await
-                .map_err(|err| AppError::new(StatusCode::FORBIDDEN, Some(err.to_string())))?;
-
-            debug!("Found EmailVerification {verification:?}");
+            ensure_username_not_confusable(&request.username, &state.dns_settings, None, conn)
+                .await?;
 
             let new_account = AccountAndAuth::new(
                 request.username,
@@ -87,6 +101,31 @@ pub async fn create_account<S: ServerSetup>(
             )
             .await?;
 
+            if let Some(credential_id) = request.credential_id {
+                let record = AccountRecord::find_by_did(conn, &new_account.account.did).await?;
+
+                let passkey = PasskeyRecord::claim(conn, &credential_id, record.id)
+                    .await
+                    .optional()?
+                    .ok_or_else(|| {
+                        AppError::new(
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            Some("Unknown passkey credential ID"),
+                        )
+                    })?;
+
+                // Make the passkey a root agent for the account.
+                // It can fetch its UCANs via the capabilities endpoint.
+                AccountAndAuth::link_agent(
+                    record,
+                    &passkey.did,
+                    &state.server_keypair,
+                    &state.dns_settings,
+                    conn,
+                )
+                .await?;
+            }
+
             verification.consume_token(conn).await?;
 
             Ok((StatusCode::CREATED, Json(new_account)))
@@ -110,12 +149,14 @@ pub async fn create_account<S: ServerSetup>(
         (status = 403, description = "Forbidden"),
         (status = 404, description = "Not found"),
         (status = 422, description = "Unprocessable entity"),
+        (status = 429, description = "Too many verification code attempts from this client"),
     )
 )]
 pub async fn link_account<S: ServerSetup>(
     State(state): State<AppState<S>>,
     Path(account_did): Path<String>,
     authority: Authority,
+    client_ip: ClientIp,
     Json(request): Json<AccountLinkRequest>,
 ) -> AppResult<(StatusCode, Json<AccountAndAuth>)> {
     let Did(agent_did) = authority
@@ -123,21 +164,18 @@ pub async fn link_account<S: ServerSetup>(
         .await?;
 
     let conn = &mut db::connect(&state.db_pool).await?;
-    conn.transaction(|conn| {
-        async move {
-            let account = AccountRecord::find_by_did(conn, account_did).await?;
 
-            let email = account.email.clone().ok_or(AppError::new(
-                StatusCode::UNPROCESSABLE_ENTITY,
-                Some("No email address associated"),
-            ))?;
+    let account = AccountRecord::find_by_did(conn, account_did).await?;
 
-            let verification = EmailVerification::find_token(conn, &email, &request.code)
-                .await
-                .map_err(|err| AppError::new(StatusCode::FORBIDDEN, Some(err.to_string())))?;
+    let verification =
+        find_token_for_account(&state, client_ip, &account, &request.code, conn).await?;
 
-            debug!("Found EmailVerification {verification:?}");
+    ensure_verification_usable_for(&verification, VerificationPurpose::LinkDevice, &agent_did)?;
 
+    debug!("Found EmailVerification {verification:?}");
+
+    conn.transaction(|conn| {
+        async move {
             let account = AccountAndAuth::link_agent(
                 account,
                 &agent_did,
@@ -191,6 +229,39 @@ pub async fn get_account<S: ServerSetup>(
     Ok((StatusCode::OK, Json(account)))
 }
 
+/// GET handler for exporting all data stored about an account as a DAG-CBOR document
+#[utoipa::path(
+    get,
+    path = "/api/v0/account/export",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Exported account data", body = AccountExport, content_type = "application/vnd.ipld.dag-cbor"),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn export_account<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, DagCbor<AccountExport>)> {
+    let Did(did) = authority
+        .get_capability(&state, FissionAbility::AccountInfo)
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let export = AccountRecord::find_by_did(conn, did)
+        .await?
+        .export(&state.dns_settings, conn)
+        .await?;
+
+    Ok((StatusCode::OK, DagCbor(export)))
+}
+
 /// GET handler for an account's member number. Mostly for fun.
 #[utoipa::path(
     get,
@@ -242,6 +313,7 @@ pub async fn get_member_number<S: ServerSetup>(
         (status = 400, description = "Invalid request", body = AppError),
         (status = 401, description = "Unauthorized"),
         (status = 404, description = "Not found"),
+        (status = 422, description = "Username not allowed", body = AppError),
         (status = 429, description = "Conflict"),
     )
 )]
@@ -251,25 +323,72 @@ pub async fn patch_username<S: ServerSetup>(
     Path(username): Path<String>,
 ) -> AppResult<(StatusCode, Json<SuccessResponse>)> {
     // Validate the handle as a username
-    Username::from_str(&username)?;
+    let username = Username::from_str(&username)?;
+
+    if state.username_denylist.is_denied(&username) {
+        return Err(username_denied_error());
+    }
 
     let Did(did) = authority
-        .get_capability(&state, FissionAbility::AccountManage)
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Username,
+        )
         .await?;
 
     let conn = &mut db::connect(&state.db_pool).await?;
 
+    ensure_username_not_confusable(&username, &state.dns_settings, Some(&did), conn).await?;
+
     // conflicts are handled via the `impl From<diesel::result::Error> for AppError`
     use crate::db::schema::*;
     diesel::update(accounts::table)
         .filter(accounts::did.eq(&did))
-        .set(accounts::username.eq(&username))
+        .set((
+            accounts::username.eq(username.as_str()),
+            accounts::username_skeleton.eq(username.skeleton()),
+        ))
         .execute(conn)
         .await?;
 
     Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
 }
 
+/// GET handler for checking whether a username can still be registered
+#[utoipa::path(
+    get,
+    path = "/api/v0/account/username/{username}/available",
+    responses(
+        (status = 200, description = "Found out whether the username is available", body = UsernameAvailableResponse),
+        (status = 400, description = "Invalid username", body = AppError),
+    )
+)]
+pub async fn get_username_available<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(username): Path<String>,
+) -> AppResult<(StatusCode, Json<UsernameAvailableResponse>)> {
+    let username = Username::from_str(&username)?;
+
+    let available = if state.username_denylist.is_denied(&username) {
+        false
+    } else {
+        let conn = &mut db::connect(&state.db_pool).await?;
+        AccountRecord::find_by_username(conn, &username)
+            .await
+            .optional()?
+            .is_none()
+            && find_confusable_username(&username, &state.dns_settings, None, conn)
+                .await?
+                .is_none()
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(UsernameAvailableResponse { available }),
+    ))
+}
+
 /// PATCH Handler for changing the account handle
 #[utoipa::path(
     patch,
@@ -281,7 +400,7 @@ pub async fn patch_username<S: ServerSetup>(
         (status = 200, description = "Updated account", body = SuccessResponse),
         (status = 400, description = "Invalid request", body = AppError),
         (status = 401, description = "Unauthorized"),
-        (status = 403, description = "Forbidden"),
+        (status = 403, description = "Handle doesn't point to the account", body = AppError),
         (status = 404, description = "Not found"),
         (status = 429, description = "Conflict"),
     )
@@ -295,451 +414,2533 @@ pub async fn patch_handle<S: ServerSetup>(
     handle.validate()?;
 
     let Did(did) = authority
-        .get_capability(&state, FissionAbility::AccountManage)
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Handle,
+        )
         .await?;
 
-    // TODO Better APIs. It should be easier to ask our own DNS server some Qs
-    let localhost_dns_v4 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 53));
+    let check = check_handle(&state, &handle, &did).await;
 
-    let message_bytes = state
-        .dns_server
-        .answer_request(encode_query_as_request(
-            DnsQuery::new(format!("_did.{handle}"), RecordType::TXT),
-            localhost_dns_v4,
-        )?)
-        .await?;
-
-    let message = hickory_server::proto::op::Message::from_bytes(message_bytes.as_ref())
-        .map_err(|e| anyhow!(e))?;
-
-    let response = fission_core::dns::Response::from_message(message)?;
-
-    let record = response
-        .answer
-        .iter()
-        .find(|answer| answer.data == did)
-        .ok_or(AppError::new(
+    if !check.verified {
+        return Err(AppError::new(
             StatusCode::FORBIDDEN,
             Some(format!(
-                "Couldn't find DNS TXT record for _did.{handle} set to {did}"
+                "Couldn't find DNS TXT record for _did.{handle} or a file at {} set to {did}",
+                check.well_known.url
             )),
-        ))?;
+        )
+        .with_code(HANDLE_UNVERIFIED_CODE)
+        .with_meta(check));
+    }
 
-    tracing::info!(?record, "Found DNS record. Changing handle.");
+    tracing::info!(?check, "Verified handle. Changing handle.");
 
     let conn = &mut db::connect(&state.db_pool).await?;
 
+    let handle_skeleton = handle.skeleton();
+
+    if AccountRecord::find_confusable(conn, None, &handle_skeleton, Some(&did))
+        .await?
+        .is_some()
+    {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            Some("This handle is taken or too similar to an existing handle"),
+        )
+        .with_code(CONFUSABLE_CODE));
+    }
+
     // conflicts are handled via the `impl From<diesel::result::Error> for AppError`
     use crate::db::schema::*;
     diesel::update(accounts::table)
         .filter(accounts::did.eq(&did))
-        .set(accounts::handle.eq(handle.as_str()))
+        .set((
+            accounts::handle.eq(handle.as_str()),
+            accounts::handle_skeleton.eq(handle_skeleton),
+            accounts::handle_verified_at.eq(Some(Utc::now().naive_utc())),
+            accounts::handle_unverified_since.eq(None::<NaiveDateTime>),
+        ))
         .execute(conn)
         .await?;
 
     Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
 }
 
-/// DELETE Handler for removing domain name association
+/// GET handler for checking whether a handle points to the account, without changing anything
 #[utoipa::path(
-    delete,
-    path = "/api/v0/account/handle",
+    get,
+    path = "/api/v0/account/handle/{handle}/check",
     security(
         ("ucan_bearer" = []),
     ),
     responses(
-        (status = 200, description = "Updated account", body = SuccessResponse),
+        (status = 200, description = "Handle verification diagnostics", body = HandleCheckResponse),
         (status = 400, description = "Invalid request", body = AppError),
         (status = 401, description = "Unauthorized"),
         (status = 403, description = "Forbidden"),
-        (status = 404, description = "Not found"),
     )
 )]
-pub async fn delete_handle<S: ServerSetup>(
+pub async fn check_handle_setup<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+    Path(handle): Path<Handle>,
+) -> AppResult<(StatusCode, Json<HandleCheckResponse>)> {
+    handle.validate()?;
+
+    let Did(did) = authority
+        .get_capability(&state, FissionAbility::AccountInfo)
+        .await?;
+
+    let check = check_handle(&state, &handle, &did).await;
+
+    Ok((StatusCode::OK, Json(check)))
+}
+
+/// POST handler for requesting a verification code for changing the account's email address
+#[utoipa::path(
+    post,
+    path = "/api/v0/account/email/verify",
+    request_body = EmailVerifyRequest,
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Successfully sent verification code", body = SuccessResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 409, description = "Conflict"),
+        (status = 429, description = "Too many requests for this email address or client"),
+    )
+)]
+pub async fn request_email_change<S: ServerSetup>(
     State(state): State<AppState<S>>,
     authority: Authority,
+    client_ip: ClientIp,
+    Json(request): Json<EmailVerifyRequest>,
 ) -> AppResult<(StatusCode, Json<SuccessResponse>)> {
+    request
+        .validate()
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
+
     let Did(did) = authority
-        .get_capability(&state, FissionAbility::AccountManage)
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Email,
+        )
         .await?;
 
     let conn = &mut db::connect(&state.db_pool).await?;
 
-    use crate::db::schema::*;
-    diesel::update(accounts::table)
-        .filter(accounts::did.eq(&did))
-        .set(accounts::handle.eq(&None::<String>))
-        .execute(conn)
-        .await?;
+    let account = AccountRecord::find_by_did(conn, &did).await?;
+
+    if AccountEmailRecord::is_claimed(conn, &request.email, Some(account.id)).await? {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            Some("This email address is already associated with an account."),
+        ));
+    }
+
+    send_verification(&state, conn, &request, client_ip).await?;
 
     Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
 }
 
-/// Handler for deleting an account
+/// PATCH Handler for changing the account's email address given a verification code
 #[utoipa::path(
-    delete,
-    path = "/api/v0/account",
+    patch,
+    path = "/api/v0/account/email",
+    request_body = EmailChangeRequest,
     security(
         ("ucan_bearer" = []),
     ),
     responses(
-        (status = 200, description = "Deleted", body = Account),
+        (status = 200, description = "Updated account", body = Account),
         (status = 400, description = "Invalid request", body = AppError),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
         (status = 404, description = "Not found"),
+        (status = 409, description = "Conflict"),
+        (status = 429, description = "Too many verification code attempts from this client"),
     )
 )]
-pub async fn delete_account<S: ServerSetup>(
+pub async fn patch_email<S: ServerSetup>(
     State(state): State<AppState<S>>,
     authority: Authority,
+    client_ip: ClientIp,
+    Json(request): Json<EmailChangeRequest>,
 ) -> AppResult<(StatusCode, Json<Account>)> {
+    request
+        .validate()
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
+
     let Did(did) = authority
-        .get_capability(&state, FissionAbility::AccountDelete)
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Email,
+        )
         .await?;
 
     let conn = &mut db::connect(&state.db_pool).await?;
-    let server_keypair = state.server_keypair;
-    conn.transaction(|conn| {
-        async move {
-            use crate::db::schema::{accounts, capabilities, ucans, revocations};
-            let account = diesel::delete(accounts::table)
-                .filter(accounts::did.eq(&did))
-                .get_result::<AccountRecord>(conn)
-                .await
-                .optional()?
-                .ok_or_else(|| {
-                    AppError::new(
-                        StatusCode::NOT_FOUND,
-                        Some("Couldn't find an account with this DID."),
-                    )
-                })?;
 
-            let indexed_ucans: Vec<(String, String, i32)> = capabilities::table
-                .inner_join(ucans::table)
-                .filter(capabilities::resource.eq(&did))
-                .select((ucans::issuer, ucans::encoded, ucans::id))
-                .get_results(conn)
-                .await?;
+    let verification = find_token(&state, client_ip, &request.email, &request.code, conn).await?;
 
-            let ucan_ids = indexed_ucans.iter().map(|(_, _, ucan_id)| ucan_id);
+    ensure_verification_usable_for(&verification, VerificationPurpose::ChangeEmail, &did)?;
 
-            // Revoke the server to user UCANs
+    debug!("Found EmailVerification {verification:?}");
 
-            fn ucan_revocation(issuer: &EdDidKey, encoded_ucan: &str) -> Result<NewRevocationRecord> {
-                let ucan: Ucan = Ucan::from_str(encoded_ucan)?;
-                let revocation = Revocation::new(issuer, &ucan)?;
-                Ok(NewRevocationRecord::new(revocation))
-            }
+    let (old_email, account) = conn
+        .transaction(|conn| {
+            async move {
+                let account = AccountRecord::find_by_did(conn, &did).await?;
 
-            let revocation_records = indexed_ucans
-                .iter()
-                .filter(|(issuer, _, _)| (issuer == server_keypair.did_as_str()))
-                .map(|(_, encoded, _)| ucan_revocation(&server_keypair, encoded))
-                .collect::<Result<Vec<NewRevocationRecord>>>()?;
+                // conflicts are handled via the `impl From<diesel::result::Error> for AppError`
+                let updated: AccountRecord = diesel::update(accounts::table)
+                    .filter(accounts::id.eq(account.id))
+                    .set(accounts::email.eq(&verification.email))
+                    .get_result(conn)
+                    .await?;
 
-            if revocation_records.is_empty() {
-                tracing::warn!(?account, "Trouble revoking UCANs associated with this account: Couldn't find UCANs to revoke");
-            }
+                AccountEmailRecord::replace_primary(conn, account.id, &verification.email).await?;
 
-            diesel::insert_into(revocations::table)
-                .values(revocation_records)
-                .execute(conn).await?;
+                verification.consume_token(conn).await?;
 
-            // We also delete any revoked ucans, since we don't need them anymore & they're a liability data-wise.
-            diesel::delete(ucans::table)
-                .filter(ucans::id.eq_any(ucan_ids))
-                .execute(conn)
-                .await?;
+                Ok::<_, AppError>((account.email, updated))
+            }
+            .scope_boxed()
+        })
+        .await?;
 
-            Ok((StatusCode::OK, Json(account.to_account(&state.dns_settings)?)))
+    if let (Some(old_email), Some(new_email)) = (old_email, account.email.as_ref()) {
+        if let Err(err) = state
+            .verification_code_sender
+            .send_email_changed_notice(&old_email, new_email)
+            .await
+        {
+            tracing::warn!(
+                ?err,
+                "Failed notifying the old email address about the change"
+            );
         }
-        .scope_boxed()
-    })
-    .await
-}
-
-#[cfg(test)]
-mod tests {
-    use self::helpers::*;
-    use crate::{
-        dns::user_dids::did_record_set,
-        error::{AppError, ErrorResponse},
-        models::account::AccountAndAuth,
-        test_utils::test_context::TestContext,
-    };
-    use anyhow::{bail, Result};
-    use assert_matches::assert_matches;
-    use fission_core::{
-        capabilities::{did::Did, fission::FissionAbility},
-        common::{Account, MemberNumberResponse, SuccessResponse},
-        ed_did_key::EdDidKey,
-        username::Handle,
-    };
-    use hickory_server::{proto::rr::RecordType, resolver::Name};
-    use http::{Method, StatusCode};
-    use rs_ucan::{
-        builder::UcanBuilder, capability::Capability, semantics::caveat::EmptyCaveat, ucan::Ucan,
-    };
-    use serde::de::DeserializeOwned;
-    use serde_json::{json, Value};
-    use std::str::FromStr;
-    use testresult::TestResult;
-
-    #[test_log::test(tokio::test)]
-    async fn test_create_account_ok() -> TestResult {
-        let ctx = &TestContext::new().await?;
-
-        let username = "oedipa";
-        let email = "oedipa@trystero.com";
-        let issuer = &EdDidKey::generate();
+    }
 
-        let (status, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+    Ok((
+        StatusCode::OK,
+        Json(account.to_account(&state.dns_settings)?),
+    ))
+}
 
-        assert_eq!(status, StatusCode::CREATED);
-        assert_eq!(auth.account.username, Some(ctx.user_handle(username)?));
-        assert_eq!(auth.account.email, Some(email.to_string()));
-        assert!(auth
-            .ucans
-            .iter()
-            .any(|ucan| ucan.audience() == issuer.as_ref()));
+/// GET handler for listing the account's email addresses
+#[utoipa::path(
+    get,
+    path = "/api/v0/account/emails",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "The account's email addresses", body = AccountEmailsResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn list_emails<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<AccountEmailsResponse>)> {
+    let Did(did) = authority
+        .get_capability(&state, FissionAbility::AccountInfo)
+        .await?;
 
-        Ok(())
-    }
+    let conn = &mut db::connect(&state.db_pool).await?;
 
-    #[test_log::test(tokio::test)]
-    async fn test_create_account_same_username_conflict() -> TestResult {
-        let ctx = &TestContext::new().await?;
+    let account = AccountRecord::find_by_did(conn, &did).await?;
 
-        let username = "oedipa";
-        let email = "oedipa@trystero.com";
-        let issuer = &EdDidKey::generate();
+    Ok((
+        StatusCode::OK,
+        Json(emails_response(account.id, conn).await?),
+    ))
+}
 
-        create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+/// POST handler for adding an unverified email address to the account.
+/// Sends a verification code to the new address.
+#[utoipa::path(
+    post,
+    path = "/api/v0/account/emails",
+    request_body = EmailVerifyRequest,
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Added email address and sent verification code", body = AccountEmailsResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 409, description = "Conflict"),
+        (status = 429, description = "Too many requests for this email address or client"),
+    )
+)]
+pub async fn add_email<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+    client_ip: ClientIp,
+    Json(request): Json<EmailVerifyRequest>,
+) -> AppResult<(StatusCode, Json<AccountEmailsResponse>)> {
+    request
+        .validate()
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
 
-        let username = "oedipa";
-        let email = "oedipa2@trystero.com";
-        let issuer = &EdDidKey::generate();
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Email,
+        )
+        .await?;
 
-        let (status, _) = create_account::<Value>(username, email, issuer, ctx).await?;
+    let conn = &mut db::connect(&state.db_pool).await?;
 
-        assert_eq!(status, StatusCode::CONFLICT);
+    let account = AccountRecord::find_by_did(conn, &did).await?;
 
-        Ok(())
+    if AccountEmailRecord::is_claimed(conn, &request.email, Some(account.id)).await? {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            Some("This email address is already associated with an account."),
+        ));
     }
 
-    #[test_log::test(tokio::test)]
-    async fn test_create_account_err_wrong_code() -> TestResult {
-        let ctx = &TestContext::new().await?;
-
-        let username = "oedipa";
-        let email = "oedipa@trystero.com";
-        let issuer = &EdDidKey::generate();
+    // Rate limited requests shouldn't leave an address behind
+    send_verification(&state, conn, &request, client_ip).await?;
 
-        let (status, _) = ctx
-            .request(Method::POST, "/api/v0/auth/email/verify")
-            .with_json_body(json!({ "email": email }))?
-            .into_json_response::<SuccessResponse>()
-            .await?;
+    AccountEmailRecord::add_unverified(conn, account.id, &request.email).await?;
 
-        assert_eq!(status, StatusCode::OK);
+    Ok((
+        StatusCode::OK,
+        Json(emails_response(account.id, conn).await?),
+    ))
+}
+
+/// POST handler for verifying an email address that was added to the account
+#[utoipa::path(
+    post,
+    path = "/api/v0/account/emails/{email}/verify",
+    request_body = AccountEmailVerifyRequest,
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Verified email address", body = AccountEmailsResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+        (status = 409, description = "Conflict"),
+        (status = 429, description = "Too many verification code attempts from this client"),
+    )
+)]
+pub async fn verify_email<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(email): Path<String>,
+    authority: Authority,
+    client_ip: ClientIp,
+    Json(request): Json<AccountEmailVerifyRequest>,
+) -> AppResult<(StatusCode, Json<AccountEmailsResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Email,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, &did).await?;
+    let account_email = AccountEmailRecord::find(conn, account.id, &email).await?;
+
+    let verification = find_token(&state, client_ip, &email, &request.code, conn).await?;
+
+    ensure_verification_usable_for(&verification, VerificationPurpose::AddEmail, &did)?;
+
+    conn.transaction(|conn| {
+        async move {
+            // conflicts with other accounts are handled via the `impl From<diesel::result::Error> for AppError`
+            account_email.mark_verified(conn).await?;
+
+            verification.consume_token(conn).await?;
+
+            Ok((
+                StatusCode::OK,
+                Json(emails_response(account.id, conn).await?),
+            ))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// PUT handler for making a verified email address the account's primary address
+#[utoipa::path(
+    put,
+    path = "/api/v0/account/emails/{email}/primary",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Changed the primary email address", body = AccountEmailsResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+        (status = 422, description = "Email address not verified", body = AppError),
+    )
+)]
+pub async fn set_primary_email<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(email): Path<String>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<AccountEmailsResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Email,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+    conn.transaction(|conn| {
+        async move {
+            let account = AccountRecord::find_by_did(conn, &did).await?;
+            let account_email = AccountEmailRecord::find(conn, account.id, &email).await?;
+
+            if !account_email.verified {
+                return Err(AppError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Some("Only verified email addresses can be made primary"),
+                ));
+            }
+
+            account_email.set_primary(conn).await?;
+
+            Ok((
+                StatusCode::OK,
+                Json(emails_response(account.id, conn).await?),
+            ))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// DELETE handler for removing an email address from the account
+#[utoipa::path(
+    delete,
+    path = "/api/v0/account/emails/{email}",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Removed email address", body = AccountEmailsResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+        (status = 422, description = "Can't remove the primary email address", body = AppError),
+    )
+)]
+pub async fn remove_email<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(email): Path<String>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<AccountEmailsResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Email,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, &did).await?;
+    let account_email = AccountEmailRecord::find(conn, account.id, &email).await?;
+
+    if account_email.is_primary {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("Can't remove the primary email address. Make another address primary first."),
+        ));
+    }
+
+    account_email.delete(conn).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(emails_response(account.id, conn).await?),
+    ))
+}
+
+async fn emails_response(account_id: i32, conn: &mut Conn<'_>) -> AppResult<AccountEmailsResponse> {
+    let emails = AccountEmailRecord::list(conn, account_id).await?;
+    Ok(AccountEmailsResponse {
+        emails: emails
+            .iter()
+            .map(AccountEmailRecord::to_account_email)
+            .collect(),
+    })
+}
+
+/// GET handler for listing the devices that have access to the account
+#[utoipa::path(
+    get,
+    path = "/api/v0/account/devices",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "The devices with access to the account", body = DevicesResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn list_devices<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<DevicesResponse>)> {
+    let Did(did) = authority
+        .get_capability(&state, FissionAbility::AccountInfo)
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, &did).await?;
+    let devices = account.list_devices(&state.server_keypair, conn).await?;
+
+    Ok((StatusCode::OK, Json(DevicesResponse { devices })))
+}
+
+/// DELETE handler for revoking a device's access to the account and its apps
+#[utoipa::path(
+    delete,
+    path = "/api/v0/account/devices/{did}",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Revoked device, returns the remaining devices", body = DevicesResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn revoke_device<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(device_did): Path<String>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<DevicesResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Devices,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+    conn.transaction(|conn| {
+        async move {
+            let account = AccountRecord::find_by_did(conn, &did).await?;
+
+            let revoked = account
+                .revoke_device(&device_did, &state.server_keypair, conn)
+                .await?;
+
+            if revoked == 0 {
+                return Err(AppError::new(
+                    StatusCode::NOT_FOUND,
+                    Some("Couldn't find a device with this DID that has access to the account."),
+                ));
+            }
+
+            let devices = account.list_devices(&state.server_keypair, conn).await?;
+
+            Ok((StatusCode::OK, Json(DevicesResponse { devices })))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Find a verification token with given code that was sent to given email address.
+///
+/// Codes tried by the same client IP are limited, no matter which addresses they're for.
+async fn find_token<S: ServerSetup>(
+    state: &AppState<S>,
+    ClientIp(client_ip): ClientIp,
+    email: &str,
+    code: &str,
+    conn: &mut Conn<'_>,
+) -> AppResult<EmailVerification> {
+    state.rate_limiter.check_code_attempt(client_ip).await?;
+
+    EmailVerification::find_token(conn, email, code)
+        .await
+        .map_err(|err| AppError::new(StatusCode::FORBIDDEN, Some(err.to_string())))
+}
+
+/// Find a verification token with given code that was sent to any of the account's verified email addresses
+async fn find_token_for_account<S: ServerSetup>(
+    state: &AppState<S>,
+    ClientIp(client_ip): ClientIp,
+    account: &AccountRecord,
+    code: &str,
+    conn: &mut Conn<'_>,
+) -> AppResult<EmailVerification> {
+    state.rate_limiter.check_code_attempt(client_ip).await?;
+
+    let emails = AccountEmailRecord::verified_addresses(conn, account.id).await?;
+
+    if emails.is_empty() {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("No email address associated"),
+        ));
+    }
+
+    EmailVerification::find_token_for_any(conn, &emails, code)
+        .await
+        .map_err(|err| AppError::new(StatusCode::FORBIDDEN, Some(err.to_string())))
+}
+
+fn ensure_verification_usable_for(
+    verification: &EmailVerification,
+    purpose: VerificationPurpose,
+    did: &str,
+) -> AppResult<()> {
+    verification
+        .ensure_usable_for(purpose, did)
+        .map_err(|err| AppError::new(StatusCode::FORBIDDEN, Some(err.to_string())))
+}
+
+/// DELETE Handler for removing domain name association
+#[utoipa::path(
+    delete,
+    path = "/api/v0/account/handle",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Updated account", body = SuccessResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn delete_handle<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<SuccessResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Handle,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    use crate::db::schema::*;
+    diesel::update(accounts::table)
+        .filter(accounts::did.eq(&did))
+        .set((
+            accounts::handle.eq(&None::<String>),
+            accounts::handle_skeleton.eq(&None::<String>),
+            accounts::handle_verified_at.eq(None::<NaiveDateTime>),
+            accounts::handle_unverified_since.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)
+        .await?;
+
+    Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
+}
+
+/// Handler for deleting an account
+#[utoipa::path(
+    delete,
+    path = "/api/v0/account",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Deleted", body = Account),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn delete_account<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<Account>)> {
+    let Did(did) = authority
+        .get_capability(&state, FissionAbility::AccountDelete)
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, &did)
+        .await
+        .optional()?
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                Some("Couldn't find an account with this DID."),
+            )
+        })?;
+
+    // The account is only purged after the grace period, until then it can be restored
+    let account = account.mark_deleted(conn).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(account.to_account(&state.dns_settings)?),
+    ))
+}
+
+/// POST handler for restoring an account that's pending deletion via email challenge
+#[utoipa::path(
+    post,
+    path = "/api/v0/account/{did}/restore",
+    request_body = AccountRestoreRequest,
+    responses(
+        (status = 200, description = "Restored account", body = Account),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+        (status = 422, description = "Unprocessable entity"),
+        (status = 429, description = "Too many verification code attempts from this client"),
+    )
+)]
+pub async fn restore_account<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(account_did): Path<String>,
+    client_ip: ClientIp,
+    Json(request): Json<AccountRestoreRequest>,
+) -> AppResult<(StatusCode, Json<Account>)> {
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, account_did).await?;
+
+    if account.deleted_at.is_none() {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("Account is not pending deletion"),
+        ));
+    }
+
+    let verification =
+        find_token_for_account(&state, client_ip, &account, &request.code, conn).await?;
+
+    ensure_verification_usable_for(&verification, VerificationPurpose::Restore, &account.did)?;
+
+    conn.transaction(|conn| {
+        async move {
+            let account = account.restore(conn).await?;
+
+            verification.consume_token(conn).await?;
+
+            Ok((
+                StatusCode::OK,
+                Json(account.to_account(&state.dns_settings)?),
+            ))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// POST handler for generating a new recovery code for the account.
+/// Any previously generated recovery code stops working.
+#[utoipa::path(
+    post,
+    path = "/api/v0/account/recovery-code",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Generated recovery code", body = RecoveryCodeResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn create_recovery_code<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<RecoveryCodeResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::RecoveryCode,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, did).await?;
+    let recovery_code = RecoveryCodeRecord::generate(conn, account.id).await?;
+
+    Ok((StatusCode::OK, Json(RecoveryCodeResponse { recovery_code })))
+}
+
+/// POST handler for recovering access to an account from a new device.
+/// Revokes access of all previously linked devices and links the DID from the authorization instead.
+#[utoipa::path(
+    post,
+    path = "/api/v0/account/{did}/recover",
+    request_body = AccountRecoveryRequest,
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Successfully recovered account", body = AccountAndAuth),
+        (status = 400, description = "Bad Request"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+        (status = 422, description = "Unprocessable entity"),
+        (status = 429, description = "Too many verification code attempts from this client"),
+    )
+)]
+pub async fn recover_account<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(account_did): Path<String>,
+    authority: Authority,
+    client_ip: ClientIp,
+    Json(request): Json<AccountRecoveryRequest>,
+) -> AppResult<(StatusCode, Json<AccountAndAuth>)> {
+    let Did(agent_did) = authority
+        .get_capability(&state, FissionAbility::AccountLink)
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_did(conn, account_did).await?;
+
+    // Codes are looked up outside the transaction, so failed attempts are recorded
+    let (recovery_code, verification) = match (&request.recovery_code, &request.code) {
+        (Some(recovery_code), None) => {
+            let record = RecoveryCodeRecord::find(conn, account.id, recovery_code)
+                .await
+                .optional()?
+                .ok_or_else(|| {
+                    AppError::new(StatusCode::FORBIDDEN, Some("Invalid recovery code"))
+                })?;
+            (Some(record), None)
+        }
+        (None, Some(code)) => {
+            let verification =
+                find_token_for_account(&state, client_ip, &account, code, conn).await?;
+            // Whoever requested the link got the code, not necessarily the email's owner
+            if verification.is_from_link() {
+                return Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    Some("Codes from verification links can't be used for account recovery"),
+                ));
+            }
+            (None, Some(verification))
+        }
+        _ => {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                Some("Expected exactly one of recoveryCode or code"),
+            ))
+        }
+    };
+
+    conn.transaction(|conn| {
+        async move {
+            if let Some(recovery_code) = recovery_code {
+                recovery_code.consume(conn).await?;
+            }
+
+            if let Some(verification) = verification {
+                verification.consume_token(conn).await?;
+            }
+
+            let account = AccountAndAuth::recover(
+                account,
+                &agent_did,
+                &state.server_keypair,
+                &state.dns_settings,
+                conn,
+            )
+            .await?;
+
+            Ok((StatusCode::OK, Json(account)))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// The error returned when trying to register a reserved or otherwise disallowed username.
+/// Has a distinct error code, so clients can tell it apart from the username being taken.
+fn username_denied_error() -> AppError {
+    AppError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Some("This username is not allowed"),
+    )
+    .with_code(USERNAME_DENIED_CODE)
+}
+
+/// Error code for usernames that are reserved or on the server's denylist
+pub const USERNAME_DENIED_CODE: &str = "username-denied";
+
+/// Error code for usernames or handles that are visually confusable with existing ones
+pub const CONFUSABLE_CODE: &str = "confusable";
+
+/// Error code for handles whose domain doesn't point to the account.
+/// The error's `meta` contains the handle check diagnostics.
+pub const HANDLE_UNVERIFIED_CODE: &str = "handle-unverified";
+
+/// Find an account whose username is visually confusable with the given username,
+/// or whose handle is confusable with it when combined with the users origin.
+async fn find_confusable_username(
+    username: &Username,
+    dns_settings: &settings::Dns,
+    except_did: Option<&str>,
+    conn: &mut Conn<'_>,
+) -> AppResult<Option<AccountRecord>> {
+    let handle = Handle::new(username.as_str(), &dns_settings.users_origin)?;
+
+    Ok(AccountRecord::find_confusable(
+        conn,
+        Some(&username.skeleton()),
+        &handle.skeleton(),
+        except_did,
+    )
+    .await?)
+}
+
+/// Makes sure the username is neither visually confusable with another account's username,
+/// nor with another account's handle, when combined with the users origin.
+async fn ensure_username_not_confusable(
+    username: &Username,
+    dns_settings: &settings::Dns,
+    except_did: Option<&str>,
+    conn: &mut Conn<'_>,
+) -> AppResult<()> {
+    let confusable = find_confusable_username(username, dns_settings, except_did, conn).await?;
+
+    if let Some(account) = confusable {
+        debug!(%username, ?account.username, ?account.handle, "Rejecting confusable username");
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            Some("This username is taken or too similar to an existing username"),
+        )
+        .with_code(CONFUSABLE_CODE));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use self::helpers::*;
+    use crate::{
+        db::schema::accounts,
+        dns::user_dids::did_record_set,
+        error::{AppError, ErrorResponse},
+        handle_verification::reverify_handles,
+        models::{
+            account::{AccountAndAuth, AccountRecord},
+            email_verification::MAX_FAILED_ATTEMPTS,
+        },
+        settings,
+        test_utils::test_context::TestContext,
+        username_denylist::UsernameDenylist,
+    };
+    use anyhow::{bail, Result};
+    use assert_matches::assert_matches;
+    use axum::extract::ws::Message;
+    use diesel::{ExpressionMethods, OptionalExtension};
+    use diesel_async::RunQueryDsl;
+    use fission_core::{
+        capabilities::{
+            did::Did,
+            fission::{AccountOperation, FissionAbility, FissionCaveat},
+        },
+        common::{
+            Account, AccountEmailsResponse, AccountExport, DevicesResponse, DnsLookupOutcome,
+            HandleCheckResponse, MemberNumberResponse, RecoveryCodeResponse, SuccessResponse,
+            UsernameAvailableResponse, WellKnownOutcome,
+        },
+        ed_did_key::EdDidKey,
+        revocation::canonical_cid,
+        username::{Handle, Username},
+    };
+    use hickory_server::{proto::rr::RecordType, resolver::Name};
+    use http::{Method, StatusCode};
+    use rs_ucan::{
+        builder::UcanBuilder, capability::Capability, semantics::caveat::EmptyCaveat, ucan::Ucan,
+    };
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+    use std::{str::FromStr, time::Duration};
+    use testresult::TestResult;
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (status, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(auth.account.username, Some(ctx.user_handle(username)?));
+        assert_eq!(auth.account.email, Some(email.to_string()));
+        assert!(auth
+            .ucans
+            .iter()
+            .any(|ucan| ucan.audience() == issuer.as_ref()));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account_same_username_conflict() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let username = "oedipa";
+        let email = "oedipa2@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (status, _) = create_account::<Value>(username, email, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account_confusable_username_conflict() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        create_account::<AccountAndAuth>("paypal", "oedipa@trystero.com", issuer, ctx).await?;
+
+        // "paypal" with cyrillic letters, punycode-encoded
+        let username = Username::from_unicode("раураl")?;
+        let issuer = &EdDidKey::generate();
+
+        let (status, body) =
+            create_account::<ErrorResponse>(username.as_str(), "oedipa2@trystero.com", issuer, ctx)
+                .await?;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body.errors[0].code.as_deref(), Some(super::CONFUSABLE_CODE));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account_err_wrong_code() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        ctx.verification_code_sender()
+            .get_emails()
+            .into_iter()
+            .last()
+            .expect("No email Sent");
+
+        let ucan: Ucan = UcanBuilder::default()
+            .for_audience(ctx.server_did())
+            .claiming_capability(Capability::new(
+                Did(issuer.did()),
+                FissionAbility::AccountCreate,
+                EmptyCaveat,
+            ))
+            .sign(issuer)?;
+
+        let (status, body) = ctx
+            .request(Method::POST, "/api/v0/account")
+            .with_ucan(ucan)
+            .with_json_body(json!({
+                "username": username,
+                "email": email,
+                "code": "1000000", // wrong code
+            }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        assert_matches!(
+            body.errors.as_slice(),
+            [AppError {
+                status: StatusCode::FORBIDDEN,
+                ..
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_get_account_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "donnie";
+        let email = "donnie@example.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account(username, email, issuer, ctx).await?;
+
+        let (status, body) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.username, Some(ctx.user_handle(username)?));
+        assert_eq!(body.email, Some(email.to_string()));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_username_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let username2 = "oedipa2";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, resp) =
+            patch_username::<SuccessResponse>(username2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(resp.success);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_username_conflict() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let username2 = "oedipa2";
+        let email = "oedipa@trystero.com";
+        let email2 = "oedipa2@trystero.com";
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        let (_, _) = create_account::<AccountAndAuth>(username2, email2, issuer2, ctx).await?;
+
+        let (status, _) = patch_username::<ErrorResponse>(username2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_username_confusable_conflict() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let (_, auth) =
+            create_account::<AccountAndAuth>("modern", "oedipa@trystero.com", issuer, ctx).await?;
+        let (_, auth2) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa2@trystero.com", issuer2, ctx)
+                .await?;
+
+        let (status, _) = patch_username::<ErrorResponse>("rnodern", &auth2, issuer2, ctx).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Renaming to something confusable with your own username is fine
+        let (status, _) = patch_username::<SuccessResponse>("rnodern", &auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_username_reserved_denied() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, body) = patch_username::<ErrorResponse>("admin", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body.errors[0].code.as_deref(),
+            Some(super::USERNAME_DENIED_CODE)
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account_denylisted_username_err() -> TestResult {
+        let denylist = UsernameDenylist::new(&settings::Usernames {
+            denylist: vec!["oedipa".to_string()],
+            denylist_patterns: vec!["tristero.*".to_string()],
+        })?;
+        let ctx = &TestContext::new_with_state(|builder| builder.with_username_denylist(denylist))
+            .await?;
+
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        for username in ["oedipa", "tristero-system"] {
+            let (status, body) =
+                create_account::<ErrorResponse>(username, email, issuer, ctx).await?;
+
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(
+                body.errors[0].code.as_deref(),
+                Some(super::USERNAME_DENIED_CODE)
+            );
+        }
+
+        let (status, _) = create_account::<AccountAndAuth>("oedipa2", email, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::CREATED);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account_unknown_passkey_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        ctx.request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        let (_, code) = ctx
+            .verification_code_sender()
+            .get_emails()
+            .into_iter()
+            .last()
+            .expect("No email Sent");
+
+        let ucan: Ucan = UcanBuilder::default()
+            .for_audience(ctx.server_did())
+            .claiming_capability(Capability::new(
+                Did(issuer.did()),
+                FissionAbility::AccountCreate,
+                EmptyCaveat,
+            ))
+            .sign(issuer)?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/account")
+            .with_ucan(ucan)
+            .with_json_body(json!({
+                "username": "oedipa",
+                "email": email,
+                "code": code,
+                "credentialID": "bm90LWEtY3JlZGVudGlhbA",
+            }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // The account creation got rolled back
+        let (status, response) =
+            get_username_available::<UsernameAvailableResponse>("oedipa", ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.available);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_username_available() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, resp) = get_username_available::<UsernameAvailableResponse>(username, ctx).await?;
+        assert!(resp.available);
+
+        let (_, _) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, resp) =
+            get_username_available::<UsernameAvailableResponse>(username, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!resp.available);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_username_available_confusable() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        create_account::<AccountAndAuth>("paypal", "oedipa@trystero.com", issuer, ctx).await?;
+
+        // "paypal" with cyrillic letters, punycode-encoded
+        let username = Username::from_unicode("раураl")?;
+
+        let (status, resp) =
+            get_username_available::<UsernameAvailableResponse>(username.as_str(), ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(!resp.available);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_backfill_skeletons_skips_invalid_names_once() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        diesel::insert_into(accounts::table)
+            .values(&vec![
+                (
+                    accounts::username.eq("paypal"),
+                    accounts::email.eq("oedipa@trystero.com"),
+                    accounts::did.eq("did:key:oedipa"),
+                ),
+                (
+                    accounts::username.eq("Legacy_Name"),
+                    accounts::email.eq("mucho@trystero.com"),
+                    accounts::did.eq("did:key:mucho"),
+                ),
+            ])
+            .execute(conn)
+            .await?;
+
+        assert_eq!(AccountRecord::backfill_skeletons(conn).await?, 1);
+        assert_eq!(AccountRecord::backfill_skeletons(conn).await?, 0);
+
+        let account = AccountRecord::find_by_did(conn, "did:key:oedipa").await?;
+        assert_eq!(
+            account.username_skeleton,
+            Some(Username::from_str("paypal")?.skeleton())
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_username_available_reserved() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, resp) =
+            get_username_available::<UsernameAvailableResponse>("support", ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(!resp.available);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_username_available_invalid_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, _) = get_username_available::<ErrorResponse>("_invalid", ctx).await?;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_delete_account_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, account) = delete_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(account.username, auth.account.username);
+        assert_eq!(account.email, auth.account.email);
+        assert_eq!(account.did, auth.account.did);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_revoked_account_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let username2 = "oedipa2";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        delete_account::<Account>(&auth, issuer, ctx).await?;
+
+        let (status, _) =
+            patch_username::<serde_json::Value>(username2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_restore_account_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        delete_account::<Account>(&auth, issuer, ctx).await?;
+
+        let (status, _) = get_account::<Value>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, account) = restore_account::<Account>(&auth.account.did, email, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(account.did, auth.account.did);
+
+        let (status, _) = get_account::<Account>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_restore_account_not_deleted_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, _) = restore_account::<Value>(&auth.account.did, email, ctx).await?;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_purge_deleted_accounts() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+        let (_, auth2) =
+            create_account::<AccountAndAuth>("mucho", "mucho@trystero.com", issuer2, ctx).await?;
+
+        delete_account::<Account>(&auth, issuer, ctx).await?;
+
+        let conn = &mut ctx.get_db_conn().await?;
+
+        // Still within the grace period
+        let purged =
+            AccountRecord::purge_deleted(Duration::from_secs(3600), ctx.server_did(), conn).await?;
+        assert_eq!(purged, 0);
+
+        let purged = AccountRecord::purge_deleted(Duration::ZERO, ctx.server_did(), conn).await?;
+        assert_eq!(purged, 1);
+
+        assert!(AccountRecord::find_by_did(conn, &auth.account.did)
+            .await
+            .optional()?
+            .is_none());
+
+        let (status, _) = restore_account::<Value>(&auth.account.did, email, ctx).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get_account::<Account>(&auth2, issuer2, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_list_and_revoke_devices_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+        let (_, auth2) =
+            link_account::<AccountAndAuth>(&auth.account.did, email, issuer2, ctx).await?;
+
+        let (status, DevicesResponse { devices }) =
+            list_devices::<DevicesResponse>(&auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        let mut dids = devices.into_iter().map(|d| d.did).collect::<Vec<_>>();
+        dids.sort();
+        let mut expected = vec![issuer.did(), issuer2.did()];
+        expected.sort();
+        assert_eq!(dids, expected);
+
+        let (status, DevicesResponse { devices }) =
+            revoke_device::<DevicesResponse>(&issuer2.did(), &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            devices.into_iter().map(|d| d.did).collect::<Vec<_>>(),
+            vec![issuer.did()]
+        );
+
+        let (status, _) = get_account::<ErrorResponse>(&auth2, issuer2, ctx).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = get_account::<Account>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_revoke_unknown_device_not_found() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+
+        let (status, _) =
+            revoke_device::<ErrorResponse>(&EdDidKey::generate().did(), &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_recover_account_with_recovery_code_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+
+        let (status, RecoveryCodeResponse { recovery_code }) =
+            create_recovery_code::<RecoveryCodeResponse>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let issuer2 = &EdDidKey::generate();
+        let (status, recovered) = recover_account::<AccountAndAuth>(
+            &auth.account.did,
+            json!({ "recoveryCode": recovery_code }),
+            issuer2,
+            ctx,
+        )
+        .await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(recovered.account.did, auth.account.did);
+
+        // The previous device's access got revoked
+        let (status, _) = get_account::<ErrorResponse>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = get_account::<Account>(&recovered, issuer2, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        // Recovery codes are single-use
+        let (status, _) = recover_account::<ErrorResponse>(
+            &auth.account.did,
+            json!({ "recoveryCode": recovery_code }),
+            &EdDidKey::generate(),
+            ctx,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_recover_account_with_secondary_email_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email2 = "oedipa@yoyodyne.com";
+        let issuer = &EdDidKey::generate();
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+
+        add_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+        verify_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+
+        ctx.request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email2 }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        let (_, code) = ctx
+            .verification_code_sender()
+            .get_emails()
+            .into_iter()
+            .last()
+            .expect("No email Sent");
+
+        let issuer2 = &EdDidKey::generate();
+        let (status, recovered) = recover_account::<AccountAndAuth>(
+            &auth.account.did,
+            json!({ "code": code }),
+            issuer2,
+            ctx,
+        )
+        .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = get_account::<ErrorResponse>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = get_account::<Account>(&recovered, issuer2, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_link_code_bound_to_purpose_and_did() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+
+        let issuer2 = &EdDidKey::generate();
+        let code = request_link_code(email, "link-device", issuer2.did_as_str(), ctx).await?;
+
+        // Whoever requested the link receives the code, so it can't take over the account
+        let (status, _) = recover_account::<ErrorResponse>(
+            &auth.account.did,
+            json!({ "code": code }),
+            issuer2,
+            ctx,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Nor can another DID use it
+        let (status, _) = link_account_with_code::<ErrorResponse>(
+            &auth.account.did,
+            &code,
+            &EdDidKey::generate(),
+            ctx,
+        )
+        .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) =
+            link_account_with_code::<AccountAndAuth>(&auth.account.did, &code, issuer2, ctx)
+                .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_recover_account_without_code_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+
+        let (status, _) = recover_account::<ErrorResponse>(
+            &auth.account.did,
+            json!({}),
+            &EdDidKey::generate(),
+            ctx,
+        )
+        .await?;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_account_link_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let issuer2 = &EdDidKey::generate();
+
+        let (status, link_response) =
+            link_account::<AccountAndAuth>(&auth.account.did, email, issuer2, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(link_response.account.did, auth.account.did);
+        assert_eq!(link_response.account.email, Some(email.to_string()));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_account_link_unknown_did_not_found() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let issuer2 = &EdDidKey::generate();
+
+        let (status, _) =
+            link_account::<Value>(EdDidKey::generate().did_as_str(), email, issuer2, ctx).await?;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_account_link_wrong_email_forbidden() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, response) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let issuer2 = &EdDidKey::generate();
+        let email2 = "someone.else@trystero.com";
+
+        let (status, _) =
+            link_account::<Value>(&response.account.did, email2, issuer2, ctx).await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_handle_invalid_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, _) = patch_handle::<Value>("oedipa.example.com", &auth, issuer, ctx).await?;
 
-        ctx.verification_code_sender()
-            .get_emails()
-            .into_iter()
-            .last()
-            .expect("No email Sent");
+        assert_eq!(status, StatusCode::FORBIDDEN);
 
-        let ucan: Ucan = UcanBuilder::default()
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_caveat_restricted_account_manage() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let delegate = &EdDidKey::generate();
+
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let account_did = Did(auth.account.did.clone());
+        let renaming_only = FissionCaveat::only([AccountOperation::Username]);
+        let Some(account_ucan) = auth
+            .ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing account UCAN");
+        };
+
+        let delegation: Ucan = UcanBuilder::default()
+            .for_audience(delegate)
+            .claiming_capability(Capability::new(
+                account_did.clone(),
+                FissionAbility::AccountManage,
+                renaming_only.clone(),
+            ))
+            .witnessed_by(account_ucan, None)
+            .sign(issuer)?;
+
+        let mut proofs = auth.ucans.clone();
+        proofs.push(delegation.clone());
+
+        let invoke = |caveat: FissionCaveat| -> Result<Ucan> {
+            Ok(UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    account_did.clone(),
+                    FissionAbility::AccountManage,
+                    caveat,
+                ))
+                .witnessed_by(&delegation, None)
+                .sign(delegate)?)
+        };
+
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/account/username/oedipa2")
+            .with_ucan(invoke(renaming_only.clone())?)
+            .with_ucan_proofs(proofs.clone())
+            .into_json_response::<SuccessResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = ctx
+            .request(Method::DELETE, "/api/v0/account/handle")
+            .with_ucan(invoke(renaming_only)?)
+            .with_ucan_proofs(proofs.clone())
+            .into_json_response::<ErrorResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Dropping the caveat in the invocation doesn't lift the delegation's restriction
+        let (status, _) = ctx
+            .request(Method::DELETE, "/api/v0/account/handle")
+            .with_ucan(invoke(FissionCaveat::default())?)
+            .with_ucan_proofs(proofs)
+            .into_json_response::<ErrorResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_caveats_only_checked_along_delegation_chain() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let delegate = &EdDidKey::generate();
+
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let account_did = Did(auth.account.did.clone());
+        let Some(account_ucan) = auth
+            .ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing account UCAN");
+        };
+
+        let delegate_with = |caveat: FissionCaveat| -> Result<Ucan> {
+            Ok(UcanBuilder::default()
+                .for_audience(delegate)
+                .claiming_capability(Capability::new(
+                    account_did.clone(),
+                    FissionAbility::AccountManage,
+                    caveat,
+                ))
+                .witnessed_by(account_ucan, None)
+                .sign(issuer)?)
+        };
+        let restricted = delegate_with(FissionCaveat::only([AccountOperation::Handle]))?;
+        let unrestricted = delegate_with(FissionCaveat::default())?;
+
+        // Both delegations are sent along, but only one of them proves each invocation
+        let mut proofs = auth.ucans.clone();
+        proofs.push(restricted.clone());
+        proofs.push(unrestricted.clone());
+
+        let invoke = |proof: &Ucan| -> Result<Ucan> {
+            Ok(UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    account_did.clone(),
+                    FissionAbility::AccountManage,
+                    FissionCaveat::default(),
+                ))
+                .witnessed_by(proof, None)
+                .sign(delegate)?)
+        };
+
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/account/username/oedipa2")
+            .with_ucan(invoke(&restricted)?)
+            .with_ucan_proofs(proofs.clone())
+            .into_json_response::<ErrorResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/account/username/oedipa2")
+            .with_ucan(invoke(&unrestricted)?)
+            .with_ucan_proofs(proofs)
+            .into_json_response::<SuccessResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_invocation_with_multiple_capabilities() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let (_, auth2) =
+            create_account::<AccountAndAuth>("pierce", "pierce@trystero.com", issuer2, ctx).await?;
+        let account_did = Did(auth.account.did.clone());
+        let Some(account_ucan) = auth
+            .ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing account UCAN");
+        };
+
+        // One token for both reading and renaming the account
+        let invocation: Ucan = UcanBuilder::default()
             .for_audience(ctx.server_did())
             .claiming_capability(Capability::new(
-                Did(issuer.did()),
-                FissionAbility::AccountCreate,
+                account_did.clone(),
+                FissionAbility::AccountInfo,
+                EmptyCaveat,
+            ))
+            .claiming_capability(Capability::new(
+                account_did.clone(),
+                FissionAbility::AccountManage,
                 EmptyCaveat,
             ))
+            .witnessed_by(account_ucan, None)
             .sign(issuer)?;
 
-        let (status, body) = ctx
-            .request(Method::POST, "/api/v0/account")
-            .with_ucan(ucan)
-            .with_json_body(json!({
-                "username": username,
-                "email": email,
-                "code": "1000000", // wrong code
-            }))?
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/account/username/oedipa2")
+            .with_ucan(invocation.clone())
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_json_response::<SuccessResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, account) = ctx
+            .request(Method::GET, "/api/v0/account")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_json_response::<Account>()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(account.username, Some(ctx.user_handle("oedipa2")?));
+
+        // Managing two different accounts at once is ambiguous
+        let invocation: Ucan = UcanBuilder::default()
+            .for_audience(ctx.server_did())
+            .claiming_capability(Capability::new(
+                account_did,
+                FissionAbility::AccountManage,
+                EmptyCaveat,
+            ))
+            .claiming_capability(Capability::new(
+                Did(auth2.account.did.clone()),
+                FissionAbility::AccountManage,
+                EmptyCaveat,
+            ))
+            .witnessed_by(account_ucan, None)
+            .sign(issuer)?;
+
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/account/username/oedipa3")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans)
             .into_json_response::<ErrorResponse>()
             .await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_handle_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+
+        let (status, response) =
+            patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.success);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_handle_wrong_did_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let wrong_did = EdDidKey::generate().did();
+        register_test_dns_handle(username, wrong_did, ctx).await?;
+
+        let (status, _) = patch_handle::<Value>("oedipa.test", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_handle_unverified_returns_diagnostics() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let wrong_did = EdDidKey::generate().did();
+        register_test_dns_handle(username, wrong_did.clone(), ctx).await?;
+
+        let (status, body) =
+            patch_handle::<ErrorResponse>("oedipa.test", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            body.errors[0].code.as_deref(),
+            Some(super::HANDLE_UNVERIFIED_CODE)
+        );
+
+        let meta = body.errors[0].meta.clone().ok_or("Missing diagnostics")?;
+        let check: HandleCheckResponse = serde_json::from_value(meta)?;
+
+        assert!(!check.verified);
+        assert_eq!(check.dns.name, "_did.oedipa.test");
+        assert_eq!(check.dns.outcome, DnsLookupOutcome::Found);
+        assert_eq!(check.dns.values, vec![wrong_did]);
+        assert!(check.dns.resolver.contains("test records"));
+        assert_eq!(check.well_known.outcome, WellKnownOutcome::NotFound);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_check_handle_doesnt_change_handle() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        let (status, check) =
+            check_handle::<HandleCheckResponse>("oedipa.test", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(!check.verified);
+        assert_eq!(check.dns.outcome, DnsLookupOutcome::NxDomain);
+
+        ctx.http_client().set_response(
+            "https://oedipa.test/.well-known/did.txt",
+            auth.account.did.clone(),
+        );
+
+        let (status, check) =
+            check_handle::<HandleCheckResponse>("oedipa.test", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(check.verified);
+        assert!(check.well_known.matches);
+        assert_eq!(check.well_known.outcome, WellKnownOutcome::Found);
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+        assert_eq!(
+            account.username,
+            Some(Handle::from_str("oedipa.localhost")?)
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_handle_via_well_known_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        ctx.http_client().set_response(
+            "https://oedipa.example.com/.well-known/did.txt",
+            format!("{}\n", auth.account.did),
+        );
+
+        let (status, response) =
+            patch_handle::<SuccessResponse>("oedipa.example.com", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.success);
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(
+            account.username,
+            Some(Handle::from_str("oedipa.example.com")?)
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_patch_handle_via_well_known_wrong_did_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+
+        ctx.http_client().set_response(
+            "https://oedipa.example.com/.well-known/did.txt",
+            EdDidKey::generate().did(),
+        );
+
+        let (status, _) = patch_handle::<Value>("oedipa.example.com", &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_handle_is_returned_as_username() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
+        let issuer = &EdDidKey::generate();
 
-        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
 
-        assert_matches!(
-            body.errors.as_slice(),
-            [AppError {
-                status: StatusCode::FORBIDDEN,
-                ..
-            }]
-        );
+        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+
+        patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(account.username, Some(Handle::from_str("oedipa.test")?));
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_get_account_ok() -> TestResult {
+    async fn test_delete_handle_causes_username_to_be_reset() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "donnie";
-        let email = "donnie@example.com";
+        let username = "oedipa";
+        let email = "oedipa@trystero.com";
         let issuer = &EdDidKey::generate();
 
-        let (_, auth) = create_account(username, email, issuer, ctx).await?;
+        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
 
-        let (status, body) = get_account::<Account>(&auth, issuer, ctx).await?;
+        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+
+        patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(account.username, Some(Handle::from_str("oedipa.test")?));
+
+        let (status, response) = delete_handle::<SuccessResponse>(&auth, issuer, ctx).await?;
 
         assert_eq!(status, StatusCode::OK);
-        assert_eq!(body.username, Some(ctx.user_handle(username)?));
-        assert_eq!(body.email, Some(email.to_string()));
+        assert!(response.success);
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(
+            account.username,
+            Some(Handle::from_str("oedipa.localhost")?)
+        );
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_patch_username_ok() -> TestResult {
+    async fn test_reverify_handles_marks_and_clears_lapsed_handles() -> TestResult {
         let ctx = &TestContext::new().await?;
 
         let username = "oedipa";
-        let username2 = "oedipa2";
         let email = "oedipa@trystero.com";
         let issuer = &EdDidKey::generate();
+        let well_known = "https://oedipa.example.com/.well-known/did.txt";
+        // Re-verify everything & clear handles as soon as they're found unverified twice
+        let handle_settings = settings::Handles {
+            reverify_interval_secs: 0,
+            verification_ttl_secs: 0,
+            unverified_grace_period_secs: 0,
+        };
 
         let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
 
-        let (status, resp) =
-            patch_username::<SuccessResponse>(username2, &auth, issuer, ctx).await?;
+        ctx.http_client()
+            .set_response(well_known, auth.account.did.clone());
 
-        assert_eq!(status, StatusCode::OK);
-        assert!(resp.success);
+        patch_handle::<SuccessResponse>("oedipa.example.com", &auth, issuer, ctx).await?;
+
+        let summary = reverify_handles(ctx.app_state(), &handle_settings).await?;
+        assert_eq!(summary.verified, 1);
+
+        // The domain lapses
+        ctx.http_client().remove_response(well_known);
+
+        let summary = reverify_handles(ctx.app_state(), &handle_settings).await?;
+        assert_eq!(summary.unverified, 1);
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+        assert_eq!(
+            account.username,
+            Some(Handle::from_str("oedipa.localhost")?)
+        );
+
+        let conn = &mut ctx.get_db_conn().await?;
+        let record = AccountRecord::find_by_did(conn, &auth.account.did).await?;
+        assert_eq!(record.handle.as_deref(), Some("oedipa.example.com"));
+        assert!(record.handle_unverified_since.is_some());
+
+        let summary = reverify_handles(ctx.app_state(), &handle_settings).await?;
+        assert_eq!(summary.cleared, 1);
+
+        let record = AccountRecord::find_by_did(conn, &auth.account.did).await?;
+        assert_eq!(record.handle, None);
+        assert_eq!(record.handle_verified_at, None);
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_patch_username_conflict() -> TestResult {
+    async fn test_reverify_handles_restores_handle_that_verifies_again() -> TestResult {
         let ctx = &TestContext::new().await?;
 
         let username = "oedipa";
-        let username2 = "oedipa2";
         let email = "oedipa@trystero.com";
-        let email2 = "oedipa2@trystero.com";
         let issuer = &EdDidKey::generate();
-        let issuer2 = &EdDidKey::generate();
+        let handle_settings = settings::Handles {
+            verification_ttl_secs: 0,
+            ..Default::default()
+        };
 
         let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
-        let (_, _) = create_account::<AccountAndAuth>(username2, email2, issuer2, ctx).await?;
 
-        let (status, _) = patch_username::<ErrorResponse>(username2, &auth, issuer, ctx).await?;
+        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+        patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
 
-        assert_eq!(status, StatusCode::CONFLICT);
+        // Someone else's DID was put into the record
+        register_test_dns_handle(username, EdDidKey::generate().did(), ctx).await?;
+        let summary = reverify_handles(ctx.app_state(), &handle_settings).await?;
+        assert_eq!(summary.unverified, 1);
+
+        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+        let summary = reverify_handles(ctx.app_state(), &handle_settings).await?;
+        assert_eq!(summary.verified, 1);
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+        assert_eq!(account.username, Some(Handle::from_str("oedipa.test")?));
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_delete_account_ok() -> TestResult {
+    async fn test_patch_email_ok() -> TestResult {
         let ctx = &TestContext::new().await?;
 
         let username = "oedipa";
         let email = "oedipa@trystero.com";
+        let new_email = "oedipa@yoyodyne.com";
         let issuer = &EdDidKey::generate();
 
         let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
 
-        let (status, account) = delete_account::<Account>(&auth, issuer, ctx).await?;
+        let (status, account) = change_email::<Account>(new_email, &auth, issuer, ctx).await?;
 
         assert_eq!(status, StatusCode::OK);
-        assert_eq!(account.username, auth.account.username);
-        assert_eq!(account.email, auth.account.email);
-        assert_eq!(account.did, auth.account.did);
+        assert_eq!(account.email, Some(new_email.to_string()));
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(account.email, Some(new_email.to_string()));
+        assert_eq!(
+            ctx.verification_code_sender().get_email_changed_notices(),
+            vec![(email.to_string(), new_email.to_string())]
+        );
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_patch_revoked_account_err() -> TestResult {
+    async fn test_patch_email_wrong_code_forbidden() -> TestResult {
         let ctx = &TestContext::new().await?;
 
         let username = "oedipa";
-        let username2 = "oedipa2";
         let email = "oedipa@trystero.com";
+        let new_email = "oedipa@yoyodyne.com";
         let issuer = &EdDidKey::generate();
 
         let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
 
-        delete_account::<Account>(&auth, issuer, ctx).await?;
+        let invocation = build_acc_invocation(FissionAbility::AccountManage, &auth, issuer, ctx)?;
 
-        let (status, _) =
-            patch_username::<serde_json::Value>(username2, &auth, issuer, ctx).await?;
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/account/email")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .with_json_body(json!({ "email": new_email, "code": "1000000" }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
 
         assert_eq!(status, StatusCode::FORBIDDEN);
 
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(account.email, Some(email.to_string()));
+
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_account_link_ok() -> TestResult {
+    async fn test_request_email_change_taken_conflict() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
         let email = "oedipa@trystero.com";
+        let email2 = "oedipa2@trystero.com";
         let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
 
-        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+        create_account::<AccountAndAuth>("oedipa2", email2, issuer2, ctx).await?;
+
+        let invocation = build_acc_invocation(FissionAbility::AccountManage, &auth, issuer, ctx)?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/account/email/verify")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .with_json_body(json!({ "email": email2 }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_add_and_verify_email_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email = "oedipa@trystero.com";
+        let email2 = "oedipa@yoyodyne.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+
+        let (status, response) =
+            add_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.emails.len(), 2);
+        assert_eq!(response.emails[0].email, email);
+        assert!(response.emails[0].primary);
+        assert_eq!(response.emails[1].email, email2);
+        assert!(!response.emails[1].verified);
+
+        let (status, response) =
+            verify_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.emails[1].verified);
+        assert!(!response.emails[1].primary);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_link_account_secondary_email_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let email = "oedipa@trystero.com";
+        let email2 = "oedipa@yoyodyne.com";
+        let issuer = &EdDidKey::generate();
+
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+
+        add_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+        verify_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
 
         let issuer2 = &EdDidKey::generate();
 
         let (status, link_response) =
-            link_account::<AccountAndAuth>(&auth.account.did, email, issuer2, ctx).await?;
+            link_account::<AccountAndAuth>(&auth.account.did, email2, issuer2, ctx).await?;
 
         assert_eq!(status, StatusCode::OK);
         assert_eq!(link_response.account.did, auth.account.did);
-        assert_eq!(link_response.account.email, Some(email.to_string()));
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_account_link_unknown_did_not_found() -> TestResult {
+    async fn test_link_account_wrong_codes_invalidate_code() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
         let email = "oedipa@trystero.com";
         let issuer = &EdDidKey::generate();
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
 
-        create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        ctx.request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        let (_, code) = ctx
+            .verification_code_sender()
+            .get_emails()
+            .into_iter()
+            .last()
+            .expect("No email Sent");
 
+        let wrong_code = format!("{:0>6}", (code.parse::<u32>()? + 1) % 1_000_000);
         let issuer2 = &EdDidKey::generate();
 
-        let (status, _) =
-            link_account::<Value>(EdDidKey::generate().did_as_str(), email, issuer2, ctx).await?;
+        // After too many wrong codes, the correct code doesn't work anymore either
+        let attempts = std::iter::repeat(wrong_code)
+            .take(MAX_FAILED_ATTEMPTS as usize)
+            .chain([code]);
 
-        assert_eq!(status, StatusCode::NOT_FOUND);
+        for attempt in attempts {
+            let ucan: Ucan = UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    Did(issuer2.did()),
+                    FissionAbility::AccountLink,
+                    EmptyCaveat,
+                ))
+                .sign(issuer2)?;
+
+            let (status, _) = ctx
+                .request(
+                    Method::POST,
+                    &format!("/api/v0/account/{}/link", auth.account.did),
+                )
+                .with_ucan(ucan)
+                .with_json_body(json!({ "code": attempt }))?
+                .into_json_response::<ErrorResponse>()
+                .await?;
+
+            assert_eq!(status, StatusCode::FORBIDDEN);
+        }
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_account_link_wrong_email_forbidden() -> TestResult {
+    async fn test_link_account_unverified_email_forbidden() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
         let email = "oedipa@trystero.com";
+        let email2 = "oedipa@yoyodyne.com";
         let issuer = &EdDidKey::generate();
 
-        let (_, response) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+
+        add_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
 
         let issuer2 = &EdDidKey::generate();
-        let email2 = "someone.else@trystero.com";
 
         let (status, _) =
-            link_account::<Value>(&response.account.did, email2, issuer2, ctx).await?;
+            link_account::<ErrorResponse>(&auth.account.did, email2, issuer2, ctx).await?;
 
         assert_eq!(status, StatusCode::FORBIDDEN);
 
@@ -747,65 +2948,176 @@ mod tests {
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_patch_handle_invalid_err() -> TestResult {
+    async fn test_add_email_claimed_conflict() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
         let email = "oedipa@trystero.com";
+        let email2 = "oedipa2@trystero.com";
         let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
 
-        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
+        create_account::<AccountAndAuth>("oedipa2", email2, issuer2, ctx).await?;
 
-        let (status, _) = patch_handle::<Value>("oedipa.example.com", &auth, issuer, ctx).await?;
+        let (status, _) = add_email::<ErrorResponse>(email2, &auth, issuer, ctx).await?;
 
-        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(status, StatusCode::CONFLICT);
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_patch_handle_ok() -> TestResult {
+    async fn test_add_email_rate_limited() -> TestResult {
+        let ctx = &TestContext::new_with_state(|builder| {
+            builder.with_rate_limits(settings::RateLimits {
+                ip_max_requests: 2,
+                ..Default::default()
+            })
+        })
+        .await?;
+
+        let issuer = &EdDidKey::generate();
+
+        // Creating the account uses up the first request
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+
+        let (status, _) =
+            add_email::<AccountEmailsResponse>("oedipa@yoyodyne.com", &auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) =
+            add_email::<ErrorResponse>("oedipa@scope.com", &auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        let invocation = build_acc_invocation(FissionAbility::AccountManage, &auth, issuer, ctx)?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/account/email/verify")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .with_json_body(json!({ "email": "oedipa@scope.com" }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_code_attempts_rate_limited() -> TestResult {
+        let ctx = &TestContext::new_with_state(|builder| {
+            builder.with_rate_limits(settings::RateLimits {
+                ip_max_code_attempts: 2,
+                ..Default::default()
+            })
+        })
+        .await?;
+
+        let issuer = &EdDidKey::generate();
+
+        // Guesses for different addresses count towards the same limit
+        let mut statuses = Vec::new();
+        for email in [
+            "oedipa@trystero.com",
+            "mucho@trystero.com",
+            "pierce@trystero.com",
+        ] {
+            let ucan: Ucan = UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    Did(issuer.did()),
+                    FissionAbility::AccountCreate,
+                    EmptyCaveat,
+                ))
+                .sign(issuer)?;
+
+            let (status, _) = ctx
+                .request(Method::POST, "/api/v0/account")
+                .with_ucan(ucan)
+                .with_json_body(json!({
+                    "username": "oedipa",
+                    "email": email,
+                    "code": "123456",
+                }))?
+                .into_json_response::<ErrorResponse>()
+                .await?;
+            statuses.push(status);
+        }
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::FORBIDDEN,
+                StatusCode::FORBIDDEN,
+                StatusCode::TOO_MANY_REQUESTS
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_set_primary_email_ok() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
         let email = "oedipa@trystero.com";
+        let email2 = "oedipa@yoyodyne.com";
         let issuer = &EdDidKey::generate();
 
-        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
 
-        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+        add_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+
+        let (status, _) = set_primary_email::<ErrorResponse>(email2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        verify_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
 
         let (status, response) =
-            patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
+            set_primary_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
 
         assert_eq!(status, StatusCode::OK);
-        assert!(response.success);
+        assert_eq!(response.emails[0].email, email2);
+        assert!(response.emails[0].primary);
+        assert!(!response.emails[1].primary);
+
+        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+
+        assert_eq!(account.email, Some(email2.to_string()));
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_patch_handle_wrong_did_err() -> TestResult {
+    async fn test_remove_email_ok() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
         let email = "oedipa@trystero.com";
+        let email2 = "oedipa@yoyodyne.com";
         let issuer = &EdDidKey::generate();
 
-        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
+        let (_, auth) = create_account::<AccountAndAuth>("oedipa", email, issuer, ctx).await?;
 
-        let wrong_did = EdDidKey::generate().did();
-        register_test_dns_handle(username, wrong_did, ctx).await?;
+        add_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
 
-        let (status, _) = patch_handle::<Value>("oedipa.test", &auth, issuer, ctx).await?;
+        let (status, _) = remove_email::<ErrorResponse>(email, &auth, issuer, ctx).await?;
 
-        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (status, response) =
+            remove_email::<AccountEmailsResponse>(email2, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.emails.len(), 1);
+        assert_eq!(response.emails[0].email, email);
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_handle_is_returned_as_username() -> TestResult {
+    async fn test_export_account_ok() -> TestResult {
         let ctx = &TestContext::new().await?;
 
         let username = "oedipa";
@@ -814,46 +3126,69 @@ mod tests {
 
         let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
 
-        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
+        let invocation = build_acc_invocation(FissionAbility::AccountInfo, &auth, issuer, ctx)?;
 
-        patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
+        let (status, body) = ctx
+            .request(Method::GET, "/api/v0/account/export")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_raw_response()
+            .await?;
 
-        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+        assert_eq!(status, StatusCode::OK);
 
-        assert_eq!(account.username, Some(Handle::from_str("oedipa.test")?));
+        let export: AccountExport = serde_ipld_dagcbor::from_slice(&body)?;
+
+        assert_eq!(export.account.did, auth.account.did);
+        assert_eq!(export.account.email, auth.account.email);
+        assert_eq!(export.emails.len(), 1);
+        assert_eq!(export.emails[0].email, email);
+        assert!(export.emails[0].verified && export.emails[0].primary);
+        assert!(export.passkeys.is_empty());
+        assert_eq!(export.volume_cid, None);
+        assert!(export.apps.is_empty());
+        for ucan in auth.ucans.iter() {
+            assert!(export.ucans.contains(&ucan.encode()?));
+        }
 
         Ok(())
     }
 
     #[test_log::test(tokio::test)]
-    async fn test_delete_handle_causes_username_to_be_reset() -> TestResult {
+    async fn test_export_account_includes_revocations() -> TestResult {
         let ctx = &TestContext::new().await?;
 
-        let username = "oedipa";
-        let email = "oedipa@trystero.com";
         let issuer = &EdDidKey::generate();
 
-        let (_, auth) = create_account::<AccountAndAuth>(username, email, issuer, ctx).await?;
-
-        register_test_dns_handle(username, auth.account.did.clone(), ctx).await?;
-
-        patch_handle::<SuccessResponse>("oedipa.test", &auth, issuer, ctx).await?;
+        let (_, auth) =
+            create_account::<AccountAndAuth>("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
 
-        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+        let Some(device_ucan) = auth
+            .ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing Ucan!");
+        };
 
-        assert_eq!(account.username, Some(Handle::from_str("oedipa.test")?));
+        let device_cid = canonical_cid(device_ucan)?;
 
-        let (status, response) = delete_handle::<SuccessResponse>(&auth, issuer, ctx).await?;
+        let conn = &mut ctx.get_db_conn().await?;
+        let account = AccountRecord::find_by_did(conn, &auth.account.did).await?;
 
-        assert_eq!(status, StatusCode::OK);
-        assert!(response.success);
+        // Revoking deletes the UCAN from the index
+        let revoked = account
+            .revoke_device(issuer.did_as_str(), ctx.server_did(), conn)
+            .await?;
+        assert!(revoked > 0);
 
-        let (_, account) = get_account::<Account>(&auth, issuer, ctx).await?;
+        let export = account.export(&ctx.app_state().dns_settings, conn).await?;
 
-        assert_eq!(
-            account.username,
-            Some(Handle::from_str("oedipa.localhost")?)
-        );
+        assert!(!export.ucans.contains(&device_ucan.encode()?));
+        assert!(export
+            .revocations
+            .iter()
+            .any(|revocation| revocation.revoke == device_cid));
 
         Ok(())
     }
@@ -977,6 +3312,15 @@ mod tests {
                 .last()
                 .expect("No email Sent");
 
+            link_account_with_code(account_did, &code, issuer, ctx).await
+        }
+
+        pub(super) async fn link_account_with_code<T: DeserializeOwned>(
+            account_did: &str,
+            code: &str,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
             let ucan: Ucan = UcanBuilder::default()
                 .for_audience(ctx.server_did())
                 .claiming_capability(Capability::new(
@@ -996,7 +3340,155 @@ mod tests {
             Ok((status, root_account))
         }
 
-        fn build_acc_invocation(
+        /// Request a verification link, confirm it and return the code sent on the relay topic
+        pub(super) async fn request_link_code(
+            email: &str,
+            purpose: &str,
+            did: &str,
+            ctx: &TestContext,
+        ) -> Result<String> {
+            let relay_topic = "dc1b8f4e52a97d03";
+
+            let (status, _) = ctx
+                .request(Method::POST, "/api/v0/auth/email/verify")
+                .with_json_body(json!({
+                    "email": email,
+                    "mode": "link",
+                    "relayTopic": relay_topic,
+                    "purpose": purpose,
+                    "did": did,
+                }))?
+                .into_json_response::<SuccessResponse>()
+                .await?;
+
+            assert_eq!(status, StatusCode::OK);
+
+            let (_, link) = ctx
+                .verification_code_sender()
+                .get_links()
+                .into_iter()
+                .last()
+                .expect("No link sent");
+
+            let Some(path) = link.strip_prefix("http://localhost:3000") else {
+                bail!("Link should point to the server");
+            };
+
+            let (_, mut messages) = ctx.app_state().ws_peer_map.subscribe(relay_topic);
+
+            let (status, _) = ctx.request(Method::POST, path).into_raw_response().await?;
+            assert_eq!(status, StatusCode::OK);
+
+            match messages.try_next()? {
+                Some(Message::Text(code)) => Ok(code),
+                other => bail!("Expected a code on the relay topic, got {other:?}"),
+            }
+        }
+
+        pub(super) async fn restore_account<T: DeserializeOwned>(
+            account_did: &str,
+            email: &str,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let (status, _) = ctx
+                .request(Method::POST, "/api/v0/auth/email/verify")
+                .with_json_body(json!({ "email": email }))?
+                .into_json_response::<SuccessResponse>()
+                .await?;
+
+            assert_eq!(status, StatusCode::OK);
+
+            let (_, code) = ctx
+                .verification_code_sender()
+                .get_emails()
+                .into_iter()
+                .last()
+                .expect("No email Sent");
+
+            ctx.request(
+                Method::POST,
+                &format!("/api/v0/account/{account_did}/restore"),
+            )
+            .with_json_body(json!({ "code": code }))?
+            .into_json_response::<T>()
+            .await
+        }
+
+        pub(super) async fn list_devices<T: DeserializeOwned>(
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountInfo, account, issuer, ctx)?;
+
+            ctx.request(Method::GET, "/api/v0/account/devices")
+                .with_ucan(invocation)
+                .with_ucan_proofs(account.ucans.clone())
+                .into_json_response()
+                .await
+        }
+
+        pub(super) async fn revoke_device<T: DeserializeOwned>(
+            device_did: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(
+                Method::DELETE,
+                &format!("/api/v0/account/devices/{device_did}"),
+            )
+            .with_ucan(invocation)
+            .with_ucan_proofs(account.ucans.clone())
+            .into_json_response()
+            .await
+        }
+
+        pub(super) async fn create_recovery_code<T: DeserializeOwned>(
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(Method::POST, "/api/v0/account/recovery-code")
+                .with_ucan(invocation)
+                .with_ucan_proofs(account.ucans.clone())
+                .into_json_response()
+                .await
+        }
+
+        pub(super) async fn recover_account<T: DeserializeOwned>(
+            account_did: &str,
+            request: Value,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let ucan: Ucan = UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    Did(issuer.did()),
+                    FissionAbility::AccountLink,
+                    EmptyCaveat,
+                ))
+                .sign(issuer)?;
+
+            ctx.request(
+                Method::POST,
+                &format!("/api/v0/account/{account_did}/recover"),
+            )
+            .with_ucan(ucan)
+            .with_json_body(request)?
+            .into_json_response()
+            .await
+        }
+
+        pub(super) fn build_acc_invocation(
             ability: FissionAbility,
             account: &AccountAndAuth,
             issuer: &EdDidKey,
@@ -1047,6 +3539,18 @@ mod tests {
             .await
         }
 
+        pub(super) async fn get_username_available<T: DeserializeOwned>(
+            username: &str,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            ctx.request(
+                Method::GET,
+                format!("/api/v0/account/username/{username}/available"),
+            )
+            .into_json_response()
+            .await
+        }
+
         pub(super) async fn patch_handle<T: DeserializeOwned>(
             new_handle: &str,
             account: &AccountAndAuth,
@@ -1066,6 +3570,143 @@ mod tests {
             .await
         }
 
+        pub(super) async fn check_handle<T: DeserializeOwned>(
+            handle: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountInfo, account, issuer, ctx)?;
+
+            ctx.request(
+                Method::GET,
+                format!("/api/v0/account/handle/{handle}/check"),
+            )
+            .with_ucan(invocation)
+            .with_ucan_proofs(account.ucans.clone())
+            .into_json_response()
+            .await
+        }
+
+        pub(super) async fn change_email<T: DeserializeOwned>(
+            new_email: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            let (status, response) = ctx
+                .request(Method::POST, "/api/v0/account/email/verify")
+                .with_ucan(invocation)
+                .with_ucan_proofs(account.ucans.clone())
+                .with_json_body(json!({ "email": new_email }))?
+                .into_json_response::<SuccessResponse>()
+                .await?;
+
+            assert_eq!(status, StatusCode::OK);
+            assert!(response.success);
+
+            let (_, code) = ctx
+                .verification_code_sender()
+                .get_emails()
+                .into_iter()
+                .last()
+                .expect("No email Sent");
+
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(Method::PATCH, "/api/v0/account/email")
+                .with_ucan(invocation)
+                .with_ucan_proofs(account.ucans.clone())
+                .with_json_body(json!({ "email": new_email, "code": code }))?
+                .into_json_response()
+                .await
+        }
+
+        pub(super) async fn add_email<T: DeserializeOwned>(
+            email: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(Method::POST, "/api/v0/account/emails")
+                .with_ucan(invocation)
+                .with_ucan_proofs(account.ucans.clone())
+                .with_json_body(json!({ "email": email }))?
+                .into_json_response()
+                .await
+        }
+
+        /// Verifies the email address using the last code that was sent
+        pub(super) async fn verify_email<T: DeserializeOwned>(
+            email: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let (_, code) = ctx
+                .verification_code_sender()
+                .get_emails()
+                .into_iter()
+                .last()
+                .expect("No email Sent");
+
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(
+                Method::POST,
+                &format!("/api/v0/account/emails/{email}/verify"),
+            )
+            .with_ucan(invocation)
+            .with_ucan_proofs(account.ucans.clone())
+            .with_json_body(json!({ "code": code }))?
+            .into_json_response()
+            .await
+        }
+
+        pub(super) async fn set_primary_email<T: DeserializeOwned>(
+            email: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(
+                Method::PUT,
+                &format!("/api/v0/account/emails/{email}/primary"),
+            )
+            .with_ucan(invocation)
+            .with_ucan_proofs(account.ucans.clone())
+            .into_json_response()
+            .await
+        }
+
+        pub(super) async fn remove_email<T: DeserializeOwned>(
+            email: &str,
+            account: &AccountAndAuth,
+            issuer: &EdDidKey,
+            ctx: &TestContext,
+        ) -> Result<(StatusCode, T)> {
+            let invocation =
+                build_acc_invocation(FissionAbility::AccountManage, account, issuer, ctx)?;
+
+            ctx.request(Method::DELETE, &format!("/api/v0/account/emails/{email}"))
+                .with_ucan(invocation)
+                .with_ucan_proofs(account.ucans.clone())
+                .into_json_response()
+                .await
+        }
+
         pub(super) async fn register_test_dns_handle(
             username: &str,
             did: String,
@@ -0,0 +1,992 @@
+//! Fission App Routes
+
+use crate::{
+    app_state::AppState,
+    authority::{Authority, CaveatRequirement},
+    db,
+    error::{AppError, AppResult},
+    extract::{dag_cbor::DagCbor, json::Json},
+    models::{
+        account::AccountRecord,
+        app::{AppAndAuth, AppRecord},
+    },
+    routes::volume::receive_push,
+    setups::ServerSetup,
+};
+use axum::{
+    extract::{BodyStream, Path, State},
+    http::StatusCode,
+    TypedHeader,
+};
+use car_mirror::messages::PushResponse;
+use cid::Cid;
+use diesel::OptionalExtension;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+use fission_core::{
+    capabilities::{
+        did::Did,
+        fission::{AccountOperation, FissionAbility},
+        volume::VolumeAbility,
+    },
+    common::{App, AppsResponse},
+};
+use headers::ContentLength;
+use std::str::FromStr;
+
+/// POST handler for creating a new app
+#[utoipa::path(
+    post,
+    path = "/api/v0/apps",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 201, description = "Successfully created app", body = AppAndAuth),
+        (status = 400, description = "Bad Request"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn create_app<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<AppAndAuth>)> {
+    let Did(owner_did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Apps,
+        )
+        .await?;
+
+    // The app's UCANs are delegated to whoever invoked the creation
+    let agent_did = authority.ucan.issuer().to_string();
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+    conn.transaction(|conn| {
+        async move {
+            let owner = AccountRecord::find_by_did(conn, owner_did).await?;
+
+            let app =
+                AppAndAuth::new(&owner, &agent_did, state.server_keypair.as_ref(), conn).await?;
+
+            Ok((StatusCode::CREATED, Json(app)))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// GET handler for listing an account's apps
+#[utoipa::path(
+    get,
+    path = "/api/v0/apps",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Found apps", body = AppsResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn list_apps<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+) -> AppResult<(StatusCode, Json<AppsResponse>)> {
+    let Did(owner_did) = authority
+        .get_capability(&state, FissionAbility::AccountInfo)
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let owner = AccountRecord::find_by_did(conn, owner_did).await?;
+
+    let mut apps = Vec::new();
+    for record in AppRecord::find_by_owner(conn, owner.id).await? {
+        apps.push(record.to_app(conn).await?);
+    }
+
+    Ok((StatusCode::OK, Json(AppsResponse { apps })))
+}
+
+/// PUT uploading a new app volume CID
+#[utoipa::path(
+    put,
+    path = "/api/v0/apps/volume/push/{cid}",
+    request_body = BodyStream,
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Successfully uploaded data"),
+        (status = 202, description = "Data partially uploaded"),
+        (status = 400, description = "Bad Request"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn push_app_volume_cid<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+    Path(cid_string): Path<String>,
+    content_length_header: Option<TypedHeader<ContentLength>>,
+    body: BodyStream,
+) -> AppResult<(StatusCode, DagCbor<PushResponse>)> {
+    let cid = Cid::from_str(&cid_string)?;
+    let content_length = content_length_header.map(|TypedHeader(ContentLength(len))| len);
+
+    tracing::info!(content_length, "Parsed content length hint");
+
+    let Did(app_did) = authority
+        .get_capability_for(
+            &state,
+            VolumeAbility::Update,
+            CaveatRequirement::VolumeUpdate,
+        )
+        .await?;
+    let max_size = authority.volume_max_size(&app_did);
+
+    // Fail early for unknown apps, before receiving anything
+    AppRecord::find_by_did(&mut db::connect(&state.db_pool).await?, &app_did).await?;
+
+    let response = receive_push(&state, &app_did, cid, max_size, content_length, body).await?;
+
+    if !response.indicates_finished() {
+        return Ok((StatusCode::ACCEPTED, DagCbor(response)));
+    }
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+    conn.transaction(|conn| {
+        async move {
+            AppRecord::find_by_did(conn, &app_did)
+                .await?
+                .set_volume_cid(conn, &cid_string, &state.blocks.ipfs_db())
+                .await?;
+
+            Ok((StatusCode::OK, DagCbor(response)))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Handler for deleting an app
+#[utoipa::path(
+    delete,
+    path = "/api/v0/apps/{did}",
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Deleted", body = App),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn delete_app<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+    Path(app_did): Path<String>,
+) -> AppResult<(StatusCode, Json<App>)> {
+    let Did(owner_did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::Apps,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+    let server_keypair = state.server_keypair;
+    conn.transaction(|conn| {
+        async move {
+            let owner = AccountRecord::find_by_did(conn, owner_did).await?;
+
+            let app = AppRecord::find_by_did(conn, app_did)
+                .await
+                .optional()?
+                .filter(|app| app.owner_id == owner.id)
+                .ok_or_else(|| {
+                    AppError::new(
+                        StatusCode::NOT_FOUND,
+                        Some("Couldn't find an app with this DID owned by this account."),
+                    )
+                })?;
+
+            let app = app.delete(&server_keypair, conn).await?;
+
+            Ok((StatusCode::OK, Json(app.to_app(conn).await?)))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        authority::{Authority, CaveatRequirement},
+        error::ErrorResponse,
+        models::{
+            account::{AccountAndAuth, AccountRecord},
+            app::{AppAndAuth, AppRecord},
+            revocation::find_revoked_subset,
+        },
+        test_utils::test_context::TestContext,
+    };
+    use anyhow::{bail, Result};
+    use cid::{
+        multihash::{Code, MultihashDigest},
+        Cid,
+    };
+    use diesel::OptionalExtension;
+    use fission_core::{
+        capabilities::{
+            did::Did,
+            fission::FissionAbility,
+            volume::{VolumeAbility, VolumeCaveat, VolumeResource},
+        },
+        common::{App, AppsResponse, SuccessResponse},
+        ed_did_key::EdDidKey,
+        revocation::canonical_cid,
+    };
+    use http::{Method, StatusCode};
+    use libipld::{codec::Codec, Ipld, IpldCodec};
+    use rs_ucan::{
+        builder::UcanBuilder, capability::Capability, semantics::caveat::EmptyCaveat, ucan::Ucan,
+    };
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        time::Duration,
+    };
+    use testresult::TestResult;
+    use wnfs::common::BlockStore;
+
+    async fn create_account(
+        username: &str,
+        email: &str,
+        issuer: &EdDidKey,
+        ctx: &TestContext,
+    ) -> Result<AccountAndAuth> {
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, code) = ctx
+            .verification_code_sender()
+            .get_emails()
+            .into_iter()
+            .last()
+            .expect("No email Sent");
+
+        let ucan: Ucan = UcanBuilder::default()
+            .for_audience(ctx.server_did())
+            .claiming_capability(Capability::new(
+                Did(issuer.did()),
+                FissionAbility::AccountCreate,
+                EmptyCaveat,
+            ))
+            .sign(issuer)?;
+
+        let (status, auth) = ctx
+            .request(Method::POST, "/api/v0/account")
+            .with_ucan(ucan)
+            .with_json_body(json!({
+                "username": username,
+                "email": email,
+                "code": code,
+            }))?
+            .into_json_response::<AccountAndAuth>()
+            .await?;
+
+        assert_eq!(status, StatusCode::CREATED);
+
+        Ok(auth)
+    }
+
+    fn build_invocation(
+        ability: FissionAbility,
+        resource_did: &str,
+        ucans: &[Ucan],
+        issuer: &EdDidKey,
+        ctx: &TestContext,
+    ) -> Result<Ucan> {
+        let Some(proof) = ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing Ucan!");
+        };
+
+        Ok(UcanBuilder::default()
+            .claiming_capability(Capability::new(
+                Did(resource_did.to_string()),
+                ability,
+                EmptyCaveat,
+            ))
+            .for_audience(ctx.server_did())
+            .witnessed_by(proof, None)
+            .sign(issuer)?)
+    }
+
+    fn build_volume_invocation(
+        ability: VolumeAbility,
+        app_did: &str,
+        ucans: &[Ucan],
+        issuer: &EdDidKey,
+        ctx: &TestContext,
+    ) -> Result<Ucan> {
+        let Some(proof) = ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing Ucan!");
+        };
+
+        Ok(UcanBuilder::default()
+            .claiming_capability(Capability::new(
+                VolumeResource::new(app_did),
+                ability,
+                EmptyCaveat,
+            ))
+            .for_audience(ctx.server_did())
+            .witnessed_by(proof, None)
+            .sign(issuer)?)
+    }
+
+    /// A CAR file with given blocks, i.e. a car-mirror push of blocks the server is missing
+    fn car_file(root: Cid, blocks: &[(Cid, Vec<u8>)]) -> Result<Vec<u8>> {
+        let header = IpldCodec::DagCbor.encode(&Ipld::Map(BTreeMap::from([
+            ("roots".to_string(), Ipld::List(vec![Ipld::Link(root)])),
+            ("version".to_string(), Ipld::Integer(1)),
+        ])))?;
+
+        // The header and each block are prefixed with their length as an unsigned varint
+        fn push_varint(car: &mut Vec<u8>, mut len: usize) {
+            while len >= 0x80 {
+                car.push((len & 0x7f) as u8 | 0x80);
+                len >>= 7;
+            }
+            car.push(len as u8);
+        }
+
+        let mut car = Vec::new();
+        push_varint(&mut car, header.len());
+        car.extend(header);
+
+        for (cid, data) in blocks {
+            let cid_bytes = cid.to_bytes();
+            push_varint(&mut car, cid_bytes.len() + data.len());
+            car.extend(cid_bytes);
+            car.extend(data);
+        }
+
+        Ok(car)
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    async fn create_app<T: DeserializeOwned>(
+        auth: &AccountAndAuth,
+        issuer: &EdDidKey,
+        ctx: &TestContext,
+    ) -> Result<(StatusCode, T)> {
+        let invocation = build_invocation(
+            FissionAbility::AccountManage,
+            &auth.account.did,
+            &auth.ucans,
+            issuer,
+            ctx,
+        )?;
+
+        ctx.request(Method::POST, "/api/v0/apps")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_json_response()
+            .await
+    }
+
+    async fn list_apps<T: DeserializeOwned>(
+        auth: &AccountAndAuth,
+        issuer: &EdDidKey,
+        ctx: &TestContext,
+    ) -> Result<(StatusCode, T)> {
+        let invocation = build_invocation(
+            FissionAbility::AccountInfo,
+            &auth.account.did,
+            &auth.ucans,
+            issuer,
+            ctx,
+        )?;
+
+        ctx.request(Method::GET, "/api/v0/apps")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_json_response()
+            .await
+    }
+
+    async fn delete_app<T: DeserializeOwned>(
+        app_did: &str,
+        auth: &AccountAndAuth,
+        issuer: &EdDidKey,
+        ctx: &TestContext,
+    ) -> Result<(StatusCode, T)> {
+        let invocation = build_invocation(
+            FissionAbility::AccountManage,
+            &auth.account.did,
+            &auth.ucans,
+            issuer,
+            ctx,
+        )?;
+
+        ctx.request(Method::DELETE, format!("/api/v0/apps/{app_did}"))
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_json_response()
+            .await
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_app_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+
+        let (status, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(app_auth.app.name.split('-').count(), 3);
+        assert_eq!(app_auth.app.cid, None);
+        assert_ne!(app_auth.app.did, auth.account.did);
+        assert!(app_auth
+            .ucans
+            .iter()
+            .any(|ucan| ucan.audience() == issuer.did_as_str()));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_list_apps_only_own() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let auth2 = create_account("mucho", "mucho@trystero.com", issuer2, ctx).await?;
+
+        let (_, app1) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        let (_, app2) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        create_app::<AppAndAuth>(&auth2, issuer2, ctx).await?;
+
+        let (status, response) = list_apps::<AppsResponse>(&auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        let dids = response
+            .apps
+            .into_iter()
+            .map(|app| app.did)
+            .collect::<Vec<_>>();
+        assert_eq!(dids, vec![app1.app.did, app2.app.did]);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_push_app_volume_requires_volume_update() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+        let daemon = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        let app_did = &app_auth.app.did;
+
+        let invocation = build_invocation(
+            FissionAbility::AccountManage,
+            app_did,
+            &app_auth.ucans,
+            issuer,
+            ctx,
+        )?;
+
+        let cid = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+        let (status, _) = ctx
+            .request(Method::PUT, format!("/api/v0/apps/volume/push/{cid}"))
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth.ucans.clone())
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // A write-only token, e.g. for a sync daemon
+        let Some(proof) = app_auth
+            .ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing Ucan!");
+        };
+
+        let delegation: Ucan = UcanBuilder::default()
+            .for_audience(daemon)
+            .claiming_capability(Capability::new(
+                VolumeResource::new(app_did),
+                VolumeAbility::Update,
+                EmptyCaveat,
+            ))
+            .witnessed_by(proof, None)
+            .sign(issuer)?;
+
+        let mut proofs = app_auth.ucans.clone();
+        proofs.push(delegation.clone());
+
+        let authority = Authority {
+            ucan: UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    VolumeResource::new(app_did),
+                    VolumeAbility::Update,
+                    EmptyCaveat,
+                ))
+                .witnessed_by(&delegation, None)
+                .sign(daemon)?,
+            proofs: proofs.clone(),
+        };
+
+        let Did(did) = authority
+            .get_capability_for(
+                ctx.app_state(),
+                VolumeAbility::Update,
+                CaveatRequirement::VolumeUpdate,
+            )
+            .await?;
+
+        assert_eq!(&did, app_did);
+
+        let authority = Authority {
+            ucan: UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    Did(app_did.clone()),
+                    FissionAbility::AccountManage,
+                    EmptyCaveat,
+                ))
+                .witnessed_by(&delegation, None)
+                .sign(daemon)?,
+            proofs,
+        };
+
+        assert!(authority
+            .get_capability(ctx.app_state(), FissionAbility::AccountManage)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_push_app_volume_max_size_counts_all_requests() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+        let daemon = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        let app_did = &app_auth.app.did;
+
+        let Some(proof) = app_auth
+            .ucans
+            .iter()
+            .find(|ucan| ucan.audience() == issuer.did_as_str())
+        else {
+            bail!("Missing Ucan!");
+        };
+
+        let limited = || VolumeCaveat { max_size: Some(64) };
+
+        let delegation: Ucan = UcanBuilder::default()
+            .for_audience(daemon)
+            .claiming_capability(Capability::new(
+                VolumeResource::new(app_did),
+                VolumeAbility::Update,
+                limited(),
+            ))
+            .witnessed_by(proof, None)
+            .sign(issuer)?;
+
+        let mut proofs = app_auth.ucans.clone();
+        proofs.push(delegation.clone());
+
+        let push = |body: Vec<u8>| -> Result<_> {
+            let invocation: Ucan = UcanBuilder::default()
+                .for_audience(ctx.server_did())
+                .claiming_capability(Capability::new(
+                    VolumeResource::new(app_did),
+                    VolumeAbility::Update,
+                    limited(),
+                ))
+                .witnessed_by(&delegation, None)
+                .sign(daemon)?;
+
+            let cid = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+            Ok(ctx
+                .request(Method::PUT, format!("/api/v0/apps/volume/push/{cid}"))
+                .with_ucan(invocation)
+                .with_ucan_proofs(proofs.clone())
+                .with_body(mime::APPLICATION_OCTET_STREAM, body)
+                .into_raw_response())
+        };
+
+        // Neither request is too large on its own, but together they upload more than allowed
+        let (status, _) = push(vec![0; 40])?.await?;
+        assert_ne!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = push(vec![0; 40])?.await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(String::from_utf8_lossy(&body).contains("at most 64 bytes per push"));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_private_app_volume_requires_volume_read() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let auth2 = create_account("mucho", "mucho@trystero.com", issuer2, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        let (_, app_auth2) = create_app::<AppAndAuth>(&auth2, issuer2, ctx).await?;
+        let app_did = &app_auth.app.did;
+
+        let cid = ctx
+            .app_state()
+            .blocks
+            .store
+            .put_block(b"The Crying of Lot 49".to_vec(), IpldCodec::Raw.into())
+            .await?;
+        let conn = &mut ctx.get_db_conn().await?;
+        AppRecord::find_by_did(conn, app_did)
+            .await?
+            .set_volume_cid(conn, &cid.to_string(), ctx.ipfs_db())
+            .await?;
+
+        let pull_path = format!("/api/v0/volume/pull/{cid}");
+        let (status, _) = ctx
+            .request(Method::GET, &pull_path)
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let invocation = build_invocation(
+            FissionAbility::AccountManage,
+            app_did,
+            &app_auth.ucans,
+            issuer,
+            ctx,
+        )?;
+        let (status, _) = ctx
+            .request(Method::PATCH, "/api/v0/volume/visibility")
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth.ucans.clone())
+            .with_json_body(json!({ "private": true }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = ctx
+            .request(Method::GET, &pull_path)
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let invocation =
+            build_volume_invocation(VolumeAbility::Read, app_did, &app_auth.ucans, issuer, ctx)?;
+        let (status, _) = ctx
+            .request(Method::GET, &pull_path)
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth.ucans.clone())
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+
+        // Another account can neither read it with their own volume capabilities,
+        // nor claim the CID for their own volume
+        let invocation = build_volume_invocation(
+            VolumeAbility::Read,
+            &app_auth2.app.did,
+            &app_auth2.ucans,
+            issuer2,
+            ctx,
+        )?;
+        let (status, _) = ctx
+            .request(Method::GET, &pull_path)
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth2.ucans.clone())
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let invocation = build_volume_invocation(
+            VolumeAbility::Update,
+            &app_auth2.app.did,
+            &app_auth2.ucans,
+            issuer2,
+            ctx,
+        )?;
+        let (status, _) = ctx
+            .request(Method::PUT, format!("/api/v0/apps/volume/push/{cid}"))
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth2.ucans.clone())
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_private_volume_blocks_not_reachable_elsewhere() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let auth2 = create_account("mucho", "mucho@trystero.com", issuer2, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        let (_, app_auth2) = create_app::<AppAndAuth>(&auth2, issuer2, ctx).await?;
+        let app_did = &app_auth.app.did;
+
+        let store = &ctx.app_state().blocks.store;
+        let secret = b"We Await Silent Tristero's Empire".to_vec();
+        let child = store
+            .put_block(secret.clone(), IpldCodec::Raw.into())
+            .await?;
+        let root = store
+            .put_block(
+                IpldCodec::DagCbor.encode(&Ipld::List(vec![Ipld::Link(child)]))?,
+                IpldCodec::DagCbor.into(),
+            )
+            .await?;
+
+        let conn = &mut ctx.get_db_conn().await?;
+        AppRecord::find_by_did(conn, app_did)
+            .await?
+            .set_volume_cid(conn, &root.to_string(), ctx.ipfs_db())
+            .await?;
+        AppRecord::find_by_did(conn, app_did)
+            .await?
+            .set_volume_private(conn, true, ctx.ipfs_db())
+            .await?;
+
+        // Blocks within a private volume are just as private as its root
+        let child_path = format!("/api/v0/volume/pull/{child}");
+        let (status, _) = ctx
+            .request(Method::GET, &child_path)
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let invocation =
+            build_volume_invocation(VolumeAbility::Read, app_did, &app_auth.ucans, issuer, ctx)?;
+        let (status, body) = ctx
+            .request(Method::GET, &child_path)
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth.ucans.clone())
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(contains(&body, &secret));
+
+        // Blocks are addressed by their multihash, so other codecs can't get around that
+        for reencoded in [
+            Cid::new_v1(IpldCodec::DagCbor.into(), *child.hash()),
+            Cid::new_v1(IpldCodec::Raw.into(), *root.hash()),
+        ] {
+            let (status, _) = ctx
+                .request(Method::GET, format!("/api/v0/volume/pull/{reencoded}"))
+                .into_raw_response()
+                .await?;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        // Another account can't claim the private volume by wrapping its root.
+        // The server has the root already, so the push only needs to upload the wrapper.
+        let wrapper_bytes = IpldCodec::DagCbor.encode(&Ipld::List(vec![Ipld::Link(root)]))?;
+        let wrapper = Cid::new_v1(
+            IpldCodec::DagCbor.into(),
+            Code::Sha2_256.digest(&wrapper_bytes),
+        );
+
+        let invocation = build_volume_invocation(
+            VolumeAbility::Update,
+            &app_auth2.app.did,
+            &app_auth2.ucans,
+            issuer2,
+            ctx,
+        )?;
+        let (status, _) = ctx
+            .request(Method::PUT, format!("/api/v0/apps/volume/push/{wrapper}"))
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth2.ucans.clone())
+            .with_body(
+                "application/vnd.ipld.car".parse()?,
+                car_file(wrapper, &[(wrapper, wrapper_bytes)])?,
+            )
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Nor can anyone pull the private volume by pulling the wrapper
+        let leaked = match ctx
+            .request(Method::GET, format!("/api/v0/volume/pull/{wrapper}"))
+            .into_raw_response()
+            .await
+        {
+            Ok((status, body)) => status.is_success() && contains(&body, &secret),
+            // The response stream is cut off once the pull reaches the private volume
+            Err(_) => false,
+        };
+        assert!(!leaked);
+
+        // Blocks of earlier versions of a private volume stay private
+        let new_root = store
+            .put_block(
+                IpldCodec::DagCbor.encode(&Ipld::List(vec![]))?,
+                IpldCodec::DagCbor.into(),
+            )
+            .await?;
+        AppRecord::find_by_did(conn, app_did)
+            .await?
+            .set_volume_cid(conn, &new_root.to_string(), ctx.ipfs_db())
+            .await?;
+
+        let (status, _) = ctx
+            .request(Method::GET, &child_path)
+            .into_raw_response()
+            .await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_delete_app_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+
+        let (status, app) = delete_app::<App>(&app_auth.app.did, &auth, issuer, ctx).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.name, app_auth.app.name);
+
+        let (_, response) = list_apps::<AppsResponse>(&auth, issuer, ctx).await?;
+        assert!(response.apps.is_empty());
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_delete_app_of_other_account_not_found() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+        let issuer2 = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let auth2 = create_account("mucho", "mucho@trystero.com", issuer2, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+
+        let (status, _) =
+            delete_app::<ErrorResponse>(&app_auth.app.did, &auth2, issuer2, ctx).await?;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_delete_account_deletes_apps() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let issuer = &EdDidKey::generate();
+
+        let auth = create_account("oedipa", "oedipa@trystero.com", issuer, ctx).await?;
+        let (_, app_auth) = create_app::<AppAndAuth>(&auth, issuer, ctx).await?;
+        let app_did = &app_auth.app.did;
+
+        let invocation = build_invocation(
+            FissionAbility::AccountDelete,
+            &auth.account.did,
+            &auth.ucans,
+            issuer,
+            ctx,
+        )?;
+
+        let (status, _) = ctx
+            .request(Method::DELETE, "/api/v0/account")
+            .with_ucan(invocation)
+            .with_ucan_proofs(auth.ucans.clone())
+            .into_json_response::<serde_json::Value>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        // While the account is pending deletion, its apps can't be used anymore
+        let invocation =
+            build_volume_invocation(VolumeAbility::Update, app_did, &app_auth.ucans, issuer, ctx)?;
+
+        let cid = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+        let (status, _) = ctx
+            .request(Method::PUT, format!("/api/v0/apps/volume/push/{cid}"))
+            .with_ucan(invocation)
+            .with_ucan_proofs(app_auth.ucans.clone())
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let conn = &mut ctx.get_db_conn().await?;
+        let purged = AccountRecord::purge_deleted(Duration::ZERO, ctx.server_did(), conn).await?;
+        assert_eq!(purged, 1);
+
+        assert!(AppRecord::find_by_did(conn, app_did)
+            .await
+            .optional()?
+            .is_none());
+
+        let app_ucan_cids = app_auth
+            .ucans
+            .iter()
+            .filter(|ucan| ucan.issuer() == ctx.server_did().did_as_str())
+            .map(canonical_cid)
+            .collect::<Result<BTreeSet<_>>>()?;
+
+        assert!(!app_ucan_cids.is_empty());
+        assert_eq!(
+            find_revoked_subset(app_ucan_cids.clone(), conn).await?,
+            app_ucan_cids
+        );
+
+        Ok(())
+    }
+}
@@ -2,18 +2,25 @@
 
 use crate::{
     app_state::AppState,
-    db::{self},
+    db::{self, Conn},
     error::{AppError, AppResult},
-    models::email_verification::EmailVerification,
-    setups::{ServerSetup, VerificationCodeSender},
+    extract::client_ip::ClientIp,
+    models::email_verification::{is_valid_relay_topic, parse_link_token, EmailVerification},
+    setups::{ServerSetup, Verification, VerificationCodeSender},
 };
 use axum::{
-    extract::{Json, State},
+    extract::{ws::Message, Json, Path, State},
     http::StatusCode,
+    response::Html,
 };
-use fission_core::common::{EmailVerifyRequest, SuccessResponse};
+use fission_core::common::{EmailVerifyRequest, SuccessResponse, VerificationMode};
 use validator::Validate;
 
+/// Shown in the browser after confirming a verification link
+const LINK_CONFIRMED_PAGE: &str = "<!DOCTYPE html>\
+    <title>Email verified</title>\
+    <p>Your email address is verified. You can close this tab and return to the app.</p>";
+
 /// POST handler for requesting a new token by email
 #[utoipa::path(
     post,
@@ -26,40 +33,201 @@ use validator::Validate;
         (status = 403, description = "Forbidden"),
         (status = 415, description = "Unsupported Media Type"),
         (status = 422, description = "Unprocessable Entity"),
+        (status = 429, description = "Too many requests for this email address or client"),
     )
 )]
 pub async fn request_token<S: ServerSetup>(
     State(state): State<AppState<S>>,
+    client_ip: ClientIp,
     Json(request): Json<EmailVerifyRequest>,
 ) -> AppResult<(StatusCode, Json<SuccessResponse>)> {
     request
         .validate()
         .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
 
-    let mut conn = db::connect(&state.db_pool).await?;
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    send_verification(&state, conn, &request, client_ip).await?;
+
+    Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
+}
+
+/// GET handler for opening a single-use email verification link.
+/// Only shows what the link is for, so prefetching it doesn't use it up.
+/// The page's confirm button posts to `confirm_link`.
+#[utoipa::path(
+    get,
+    path = "/api/v0/auth/email/link/{token}",
+    params(
+        ("token" = String, Path, description = "The token from the verification link"),
+    ),
+    responses(
+        (status = 200, description = "Confirmation page", content_type = "text/html"),
+        (status = 400, description = "Invalid verification link", body = AppError),
+        (status = 404, description = "Link expired or already used"),
+    )
+)]
+pub async fn open_link<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(token): Path<String>,
+) -> AppResult<(StatusCode, Html<String>)> {
+    let Some((id, _)) = parse_link_token(&token, &state.server_keypair) else {
+        return Err(invalid_link_error());
+    };
+
+    let conn = &mut db::connect(&state.db_pool).await?;
 
-    let verification = EmailVerification::new(&mut conn, &request).await?;
+    let verification = EmailVerification::find_unused_link(conn, id).await?;
+
+    let page = format!(
+        "<!DOCTYPE html>\
+        <title>Confirm email verification</title>\
+        <p>Someone asked to {}. \
+        Only confirm if you just did this yourself in the app.</p>\
+        <form method=\"post\"><button type=\"submit\">Confirm</button></form>",
+        describe_purpose(verification.link_purpose.as_deref())
+    );
+
+    Ok((StatusCode::OK, Html(page)))
+}
+
+/// POST handler for confirming a single-use email verification link.
+/// Sends a code to the client that's waiting on the link's relay topic.
+#[utoipa::path(
+    post,
+    path = "/api/v0/auth/email/link/{token}",
+    params(
+        ("token" = String, Path, description = "The token from the verification link"),
+    ),
+    responses(
+        (status = 200, description = "Verified the email address", content_type = "text/html"),
+        (status = 400, description = "Invalid verification link", body = AppError),
+        (status = 404, description = "Link expired or already used"),
+        (status = 409, description = "No client is waiting for the verification", body = AppError),
+    )
+)]
+pub async fn confirm_link<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Path(token): Path<String>,
+) -> AppResult<(StatusCode, Html<&'static str>)> {
+    let Some((id, relay_topic)) = parse_link_token(&token, &state.server_keypair) else {
+        return Err(invalid_link_error());
+    };
+
+    // Otherwise the code would get lost and the link used up
+    if !state.ws_peer_map.has_subscribers(&relay_topic) {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            Some(
+                "Nothing is waiting for this verification. \
+                Please open the link while the app that requested it is still open.",
+            ),
+        ));
+    }
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let (verification, code) = EmailVerification::confirm_link(conn, id).await?;
+
+    tracing::debug!(email = verification.email, "Opened verification link");
+
+    state
+        .ws_peer_map
+        .broadcast_on_topic(&relay_topic, Message::Text(code), None);
+
+    Ok((StatusCode::OK, Html(LINK_CONFIRMED_PAGE)))
+}
+
+fn invalid_link_error() -> AppError {
+    AppError::new(StatusCode::BAD_REQUEST, Some("Invalid verification link"))
+}
+
+/// Describe what a link code is for, so users can spot requests they didn't make
+fn describe_purpose(purpose: Option<&str>) -> &'static str {
+    match purpose {
+        Some("signup") => "create a Fission account with your email address",
+        Some("link-device") => "sign in to your Fission account on a new device",
+        Some("change-email") => "change your Fission account's email address to this one",
+        Some("add-email") => "add this email address to a Fission account",
+        Some("restore") => "restore your Fission account that's pending deletion",
+        _ => "verify your email address",
+    }
+}
+
+/// Store a new verification record and send its code, or a link if requested.
+///
+/// Every caller goes through the email verification rate limits.
+pub(crate) async fn send_verification<S: ServerSetup>(
+    state: &AppState<S>,
+    conn: &mut Conn<'_>,
+    request: &EmailVerifyRequest,
+    ClientIp(client_ip): ClientIp,
+) -> AppResult<()> {
+    let relay_topic = match request.mode {
+        VerificationMode::Code => None,
+        VerificationMode::Link => match request.relay_topic.as_deref() {
+            Some(topic) if is_valid_relay_topic(topic) => Some(topic),
+            _ => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    Some(
+                        "Verification links require a relayTopic of \
+                        16 to 128 letters, digits, '-' or '_'",
+                    ),
+                ))
+            }
+        },
+    };
+
+    // Link codes go to whoever requested the link, so they're bound to what they're for
+    if relay_topic.is_some() && (request.purpose.is_none() || request.did.is_none()) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some("Verification links require a purpose and the did that uses the code"),
+        ));
+    }
+
+    state
+        .rate_limiter
+        .check_email_verification(&request.email, client_ip)
+        .await?;
+
+    let (verification, code) = EmailVerification::new(conn, request).await?;
+
+    let payload = match relay_topic {
+        None => Verification::Code(code),
+        Some(topic) => Verification::Link(format!(
+            "{}/api/v0/auth/email/link/{}",
+            state.verification_links.base_url.trim_end_matches('/'),
+            verification.link_token(topic, &state.server_keypair)
+        )),
+    };
 
     state
         .verification_code_sender
-        .send_code(&verification.email, &verification.code)
+        .send_verification(&verification.email, &payload, request.locale.as_deref())
         .await?;
 
-    Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        db::schema::email_verifications, models::email_verification::EmailVerification,
-        routes::auth::SuccessResponse, test_utils::test_context::TestContext,
+        db::schema::email_verifications,
+        error::ErrorResponse,
+        models::email_verification::{hash_code, EmailVerification, MAX_FAILED_ATTEMPTS},
+        routes::auth::SuccessResponse,
+        settings::RateLimits,
+        test_utils::test_context::TestContext,
     };
     use anyhow::{anyhow, Result};
     use assert_matches::assert_matches;
+    use axum::extract::ws::Message;
     use chrono::{Duration, Local};
     use diesel_async::RunQueryDsl;
     use fission_core::dns;
-    use http::{Method, StatusCode};
+    use http::{header::RETRY_AFTER, Method, StatusCode};
     use serde_json::json;
     use testresult::TestResult;
 
@@ -94,6 +262,84 @@ mod tests {
         Ok(())
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_request_code_passes_locale() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": "oedipa@trystero.com", "locale": "de-AT" }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        request_code("mucho@trystero.com", ctx).await?;
+
+        assert_eq!(
+            ctx.verification_code_sender().get_locales(),
+            vec![Some("de-AT".to_string()), None]
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_code_email_rate_limited() -> TestResult {
+        let ctx = &TestContext::new_with_state(|builder| {
+            builder.with_rate_limits(RateLimits {
+                email_max_requests: 2,
+                ..Default::default()
+            })
+        })
+        .await?;
+
+        let email = "oedipa@trystero.com";
+
+        request_code(email, ctx).await?;
+        request_code(email, ctx).await?;
+
+        let response = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email }))?
+            .into_response()
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+        assert_eq!(ctx.verification_code_sender().get_emails().len(), 2);
+
+        // Other email addresses aren't affected
+        let (status, _, _) = request_code("mucho@trystero.com", ctx).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_code_ip_rate_limited() -> TestResult {
+        let ctx = &TestContext::new_with_state(|builder| {
+            builder.with_rate_limits(RateLimits {
+                ip_max_requests: 2,
+                ..Default::default()
+            })
+        })
+        .await?;
+
+        request_code("oedipa@trystero.com", ctx).await?;
+        request_code("mucho@trystero.com", ctx).await?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": "metzger@trystero.com" }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        Ok(())
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_email_verification_fetch_token() -> TestResult {
         let ctx = &TestContext::new().await?;
@@ -133,7 +379,11 @@ mod tests {
             inserted_at,
             updated_at: inserted_at,
             email: email.to_string(),
-            code: code.to_string(),
+            code_hash: hash_code(email, 123456),
+            failed_attempts: 0,
+            link_used: false,
+            link_purpose: None,
+            link_did: None,
         };
 
         diesel::insert_into(email_verifications::table)
@@ -170,6 +420,214 @@ mod tests {
         Ok(())
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_request_code_stored_hashed() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let email = "oedipa@trystero.com";
+
+        let (_, _, code) = request_code(email, ctx).await?;
+
+        let records = email_verifications::table
+            .get_results::<EmailVerification>(conn)
+            .await?;
+
+        assert_eq!(records.len(), 1);
+        assert_ne!(records[0].code_hash, code);
+        assert_eq!(records[0].code_hash, hash_code(email, code.parse()?));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_code_invalidated_after_failed_attempts() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let email = "oedipa@trystero.com";
+
+        let (_, _, code) = request_code(email, ctx).await?;
+        let wrong_code = format!("{:0>6}", (code.parse::<u32>()? + 1) % 1_000_000);
+
+        for _ in 0..MAX_FAILED_ATTEMPTS - 1 {
+            let result = EmailVerification::find_token(conn, email, &wrong_code).await;
+            assert_matches!(result, Err(_));
+        }
+
+        // Still usable before reaching the limit
+        EmailVerification::find_token(conn, email, &code).await?;
+
+        let result = EmailVerification::find_token(conn, email, &wrong_code).await;
+        assert_matches!(result, Err(_));
+
+        let result = EmailVerification::find_token(conn, email, &code).await;
+        assert_matches!(result, Err(_));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_failed_attempts_only_count_for_latest_code() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let email = "oedipa@trystero.com";
+
+        let (_, _, old_code) = request_code(email, ctx).await?;
+        let (_, _, new_code) = request_code(email, ctx).await?;
+        let new = new_code.parse::<u32>()?;
+        let wrong_code = (1..3)
+            .map(|i| format!("{:0>6}", (new + i) % 1_000_000))
+            .find(|code| code != &old_code)
+            .expect("No wrong code");
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            let result = EmailVerification::find_token(conn, email, &wrong_code).await;
+            assert_matches!(result, Err(_));
+        }
+
+        // Guessing the latest code doesn't use up the attempts of earlier ones
+        let result = EmailVerification::find_token(conn, email, &new_code).await;
+        assert_matches!(result, Err(_));
+        EmailVerification::find_token(conn, email, &old_code).await?;
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_link_notifies_relay_topic() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let email = "oedipa@trystero.com";
+        let relay_topic = "dc1b8f4e52a97d03";
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({
+                "email": email,
+                "mode": "link",
+                "relayTopic": relay_topic,
+                "purpose": "signup",
+                "did": "did:key:z6MkkT5xHf6ecp4A2sNBVPGXTFHbvjDhmrHKdyYHrfMakxtD",
+            }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(ctx.verification_code_sender().get_emails().is_empty());
+
+        let (_, link) = ctx
+            .verification_code_sender()
+            .get_links()
+            .into_iter()
+            .last()
+            .expect("No link sent");
+
+        let path = link
+            .strip_prefix("http://localhost:3000")
+            .expect("Link should point to the server");
+
+        // Confirming the link is refused while nobody waits for the code
+        let (status, _) = ctx
+            .request(Method::POST, path)
+            .into_json_response::<ErrorResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, mut messages) = ctx.app_state().ws_peer_map.subscribe(relay_topic);
+
+        // Only opening the link, e.g. by a mail scanner, doesn't use it up
+        for _ in 0..2 {
+            let (status, _) = ctx.request(Method::GET, path).into_raw_response().await?;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert!(messages.try_next().is_err());
+
+        let (status, _) = ctx.request(Method::POST, path).into_raw_response().await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let code = match messages.try_next()? {
+            Some(Message::Text(code)) => code,
+            other => panic!("Expected a code on the relay topic, got {other:?}"),
+        };
+
+        EmailVerification::find_token(conn, email, &code).await?;
+
+        // The link is single-use
+        for method in [Method::GET, Method::POST] {
+            let (status, _) = ctx
+                .request(method, path)
+                .into_json_response::<ErrorResponse>()
+                .await?;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_link_rejects_tampered_token() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": "oedipa@trystero.com", "mode": "link" }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        ctx.request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({
+                "email": "oedipa@trystero.com",
+                "mode": "link",
+                "relayTopic": "dc1b8f4e52a97d03",
+                "purpose": "signup",
+                "did": "did:key:z6MkkT5xHf6ecp4A2sNBVPGXTFHbvjDhmrHKdyYHrfMakxtD",
+            }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        let (_, link) = ctx.verification_code_sender().get_links().remove(0);
+        let tampered = link.replace(".dc1b8f4e52a97d03.", ".attacker-topic-0000.");
+
+        ctx.app_state().ws_peer_map.subscribe("attacker-topic-0000");
+
+        for method in [Method::GET, Method::POST] {
+            let (status, _) = ctx
+                .request(
+                    method,
+                    tampered.strip_prefix("http://localhost:3000").unwrap(),
+                )
+                .into_json_response::<ErrorResponse>()
+                .await?;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_link_requires_purpose_and_did() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({
+                "email": "oedipa@trystero.com",
+                "mode": "link",
+                "relayTopic": "dc1b8f4e52a97d03",
+            }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(ctx.verification_code_sender().get_links().is_empty());
+
+        Ok(())
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_get_server_did() -> TestResult {
         let ctx = &TestContext::new().await?;
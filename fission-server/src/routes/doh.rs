@@ -70,7 +70,12 @@ pub async fn post<S: ServerSetup>(
 
 #[cfg(test)]
 mod tests {
-    use crate::{db::schema::accounts, test_utils::test_context::TestContext};
+    use crate::{
+        db::schema::{accounts, apps, volumes},
+        settings::Dns,
+        test_utils::test_context::TestContext,
+    };
+    use anyhow::Result;
     use diesel::ExpressionMethods;
     use diesel_async::RunQueryDsl;
     use http::{Method, StatusCode};
@@ -235,4 +240,261 @@ mod tests {
 
         Ok(())
     }
+
+    async fn txt_answer(ctx: &TestContext, name: &str) -> Result<serde_json::Value> {
+        let (status, body) = ctx
+            .request(
+                Method::GET,
+                format!("/dns-query?name={}&type={}", name, "txt"),
+            )
+            .with_accept_mime(Mime::from_str("application/dns-json")?)
+            .into_json_response::<serde_json::Value>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(body["Answer"].clone())
+    }
+
+    async fn insert_account_and_app(
+        username: &str,
+        account_did: &str,
+        app_name: &str,
+        app_did: &str,
+        app_cid: &str,
+        ctx: &TestContext,
+    ) -> Result<()> {
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let owner_id: i32 = diesel::insert_into(accounts::table)
+            .values((
+                accounts::username.eq(username),
+                accounts::email.eq(format!("{username}@example.com")),
+                accounts::did.eq(account_did),
+            ))
+            .returning(accounts::id)
+            .get_result(conn)
+            .await?;
+
+        let volume_id: i32 = diesel::insert_into(volumes::table)
+            .values(volumes::cid.eq(app_cid))
+            .returning(volumes::id)
+            .get_result(conn)
+            .await?;
+
+        diesel::insert_into(apps::table)
+            .values((
+                apps::owner_id.eq(owner_id),
+                apps::volume_id.eq(volume_id),
+                apps::did.eq(app_did),
+                apps::name.eq(app_name),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_json_did_app_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+        insert_account_and_app(
+            "donnie",
+            "did:28:06:42:12",
+            "friendly-pink-dragon",
+            "did:12:42:06:28",
+            cid,
+            ctx,
+        )
+        .await?;
+
+        let answer = txt_answer(ctx, "_did.friendly-pink-dragon.apps.localhost").await?;
+
+        assert_eq!(
+            answer,
+            json!([
+                {
+                  "name": "_did.friendly-pink-dragon.apps.localhost.",
+                  "type": 16,
+                  "TTL": 1800,
+                  "data": "did:12:42:06:28"
+                }
+            ]),
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_json_did_same_name_under_both_origins() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+        insert_account_and_app(
+            "donnie",
+            "did:28:06:42:12",
+            "donnie",
+            "did:12:42:06:28",
+            cid,
+            ctx,
+        )
+        .await?;
+
+        let answer = txt_answer(ctx, "_did.donnie.localhost").await?;
+        assert_eq!(answer[0]["data"], json!("did:28:06:42:12"));
+
+        let answer = txt_answer(ctx, "_did.donnie.apps.localhost").await?;
+        assert_eq!(answer[0]["data"], json!("did:12:42:06:28"));
+
+        let answer = txt_answer(ctx, "_dnslink.donnie.apps.localhost").await?;
+        assert_eq!(answer[0]["data"], json!(format!("dnslink=/ipfs/{cid}")));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_json_shared_origin_prefers_username() -> TestResult {
+        let ctx = &TestContext::new_with_dns_settings(|dns| Dns {
+            apps_origin: dns.users_origin.clone(),
+            ..dns
+        })
+        .await?;
+        let cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+        insert_account_and_app(
+            "donnie",
+            "did:28:06:42:12",
+            "donnie",
+            "did:12:42:06:28",
+            cid,
+            ctx,
+        )
+        .await?;
+
+        let answer = txt_answer(ctx, "_did.donnie.localhost").await?;
+        assert_eq!(answer[0]["data"], json!("did:28:06:42:12"));
+
+        // The account doesn't have a volume, but it still shadows the app's
+        let answer = txt_answer(ctx, "_dnslink.donnie.localhost").await?;
+        assert_eq!(answer, json!(null));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_json_dnslink_app_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+        let owner_id: i32 = diesel::insert_into(accounts::table)
+            .values((
+                accounts::username.eq("donnie"),
+                accounts::email.eq("donnie@example.com"),
+                accounts::did.eq("did:28:06:42:12"),
+            ))
+            .returning(accounts::id)
+            .get_result(conn)
+            .await?;
+
+        let volume_id: i32 = diesel::insert_into(volumes::table)
+            .values(volumes::cid.eq(cid))
+            .returning(volumes::id)
+            .get_result(conn)
+            .await?;
+
+        diesel::insert_into(apps::table)
+            .values((
+                apps::owner_id.eq(owner_id),
+                apps::volume_id.eq(volume_id),
+                apps::did.eq("did:12:42:06:28"),
+                apps::name.eq("friendly-pink-dragon"),
+            ))
+            .execute(conn)
+            .await?;
+
+        let (status, body) = ctx
+            .request(
+                Method::GET,
+                format!(
+                    "/dns-query?name={}&type={}",
+                    "_dnslink.friendly-pink-dragon.apps.localhost", "txt"
+                ),
+            )
+            .with_accept_mime(Mime::from_str("application/dns-json")?)
+            .into_json_response::<serde_json::Value>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["Answer"],
+            json!([
+                {
+                  "name": "_dnslink.friendly-pink-dragon.apps.localhost.",
+                  "type": 16,
+                  "TTL": 10,
+                  "data": format!("dnslink=/ipfs/{cid}")
+                }
+            ]),
+        );
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dns_json_dnslink_private_app_not_published() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let conn = &mut ctx.get_db_conn().await?;
+
+        let owner_id: i32 = diesel::insert_into(accounts::table)
+            .values((
+                accounts::username.eq("donnie"),
+                accounts::email.eq("donnie@example.com"),
+                accounts::did.eq("did:28:06:42:12"),
+            ))
+            .returning(accounts::id)
+            .get_result(conn)
+            .await?;
+
+        let volume_id: i32 = diesel::insert_into(volumes::table)
+            .values((
+                volumes::cid.eq("bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"),
+                volumes::owner_did.eq("did:12:42:06:28"),
+                volumes::private.eq(true),
+            ))
+            .returning(volumes::id)
+            .get_result(conn)
+            .await?;
+
+        diesel::insert_into(apps::table)
+            .values((
+                apps::owner_id.eq(owner_id),
+                apps::volume_id.eq(volume_id),
+                apps::did.eq("did:12:42:06:28"),
+                apps::name.eq("friendly-pink-dragon"),
+                apps::volume_private.eq(true),
+            ))
+            .execute(conn)
+            .await?;
+
+        let (status, body) = ctx
+            .request(
+                Method::GET,
+                format!(
+                    "/dns-query?name={}&type={}",
+                    "_dnslink.friendly-pink-dragon.apps.localhost", "txt"
+                ),
+            )
+            .with_accept_mime(Mime::from_str("application/dns-json")?)
+            .into_json_response::<serde_json::Value>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["Answer"], json!(null));
+
+        Ok(())
+    }
 }
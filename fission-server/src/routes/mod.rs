@@ -1,6 +1,7 @@
 //! Routes for [axum::Router].
 
 pub mod account;
+pub mod app;
 pub mod auth;
 pub mod capability_indexing;
 pub mod doh;
@@ -10,4 +11,5 @@ pub mod ipfs;
 pub mod ping;
 pub mod revocations;
 pub mod volume;
+pub mod webauthn;
 pub mod ws;
@@ -30,7 +30,9 @@ pub async fn post_revocation<S: ServerSetup>(
     authority.validate_revocation(&revocation)?;
 
     let conn = &mut db::connect(&state.db_pool).await?;
-    NewRevocationRecord::new(revocation).insert(conn).await?;
+    NewRevocationRecord::new(revocation, &authority.ucan)
+        .insert(conn)
+        .await?;
 
     Ok((StatusCode::CREATED, Json(SuccessResponse { success: true })))
 }
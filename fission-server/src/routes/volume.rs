@@ -2,11 +2,16 @@
 
 use crate::{
     app_state::AppState,
-    authority::Authority,
-    db,
+    authority::{Authority, CaveatRequirement},
+    db::{self, Conn, Pool},
     error::{AppError, AppResult},
-    extract::dag_cbor::DagCbor,
-    models::account::AccountRecord,
+    extract::{dag_cbor::DagCbor, json::Json},
+    models::{
+        account::AccountRecord,
+        app::AppRecord,
+        volume::{block_links, raw_cid, Volume},
+    },
+    rate_limit::ExpiringCounters,
     setups::ServerSetup,
 };
 use axum::{
@@ -17,13 +22,65 @@ use axum::{
 use bytes::Bytes;
 use car_mirror::messages::{PullRequest, PushResponse};
 use cid::Cid;
+use dashmap::DashMap;
+use diesel::OptionalExtension;
 use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
-use fission_core::capabilities::{did::Did, fission::FissionAbility};
-use futures_util::{Stream, TryStreamExt};
+use fission_core::{
+    capabilities::{
+        did::Did,
+        fission::{AccountOperation, FissionAbility},
+        volume::VolumeAbility,
+    },
+    common::{SuccessResponse, VolumeVisibilityRequest},
+};
+use futures_util::{Stream, StreamExt, TryStreamExt};
 use headers::ContentLength;
 use http::StatusCode;
-use std::str::FromStr;
+use parking_lot::Mutex;
+use std::{
+    collections::BTreeSet,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio_util::io::StreamReader;
+use wnfs::common::{utils::CondSend, BlockStore, BlockStoreError};
+
+/// Pushes that didn't finish within this time start counting their size from zero again
+const PUSH_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Tracks how many bytes unfinished pushes received so far.
+///
+/// A push spans as many requests as it takes the client to upload all missing blocks,
+/// so `maxSize` caveats need to be enforced across all of them.
+#[derive(Debug, Default)]
+pub struct PushSizes {
+    received: ExpiringCounters<(String, Cid)>,
+}
+
+impl PushSizes {
+    /// How many bytes the push of `cid` to the volume of `did` received so far
+    pub fn received(&self, did: &str, cid: Cid) -> u64 {
+        self.received.get(&(did.to_string(), cid))
+    }
+
+    /// Count `bytes` more towards the push of `cid` to the volume of `did`.
+    /// Returns the total number of bytes the push received.
+    pub fn add(&self, did: &str, cid: Cid, bytes: u64) -> u64 {
+        let (received, _) = self
+            .received
+            .add((did.to_string(), cid), bytes, PUSH_EXPIRY);
+        received
+    }
+
+    /// Forget about a finished push
+    pub fn finish(&self, did: &str, cid: Cid) {
+        self.received.remove(&(did.to_string(), cid));
+    }
+}
 
 /// PUT uploading a new volume CID
 #[utoipa::path(
@@ -53,53 +110,224 @@ pub async fn push_volume_cid<S: ServerSetup>(
     tracing::info!(content_length, "Parsed content length hint");
 
     let Did(did) = authority
-        .get_capability(&state, FissionAbility::AccountManage)
+        .get_capability_for(
+            &state,
+            VolumeAbility::Update,
+            CaveatRequirement::VolumeUpdate,
+        )
         .await?;
+    let max_size = authority.volume_max_size(&did);
+
+    // Fail early for unknown accounts, before receiving anything
+    AccountRecord::find_by_did(&mut db::connect(&state.db_pool).await?, &did).await?;
+
+    let response = receive_push(&state, &did, cid, max_size, content_length, body).await?;
+
+    if !response.indicates_finished() {
+        return Ok((StatusCode::ACCEPTED, DagCbor(response)));
+    }
 
     let conn = &mut db::connect(&state.db_pool).await?;
     conn.transaction(|conn| {
         async move {
-            let account = AccountRecord::find_by_did(conn, did).await?;
-
-            let mut reader = StreamReader::new(
-                body.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
-            );
-
-            let response = car_mirror::push::response_streaming(
-                cid,
-                &mut reader,
-                &Default::default(),
-                &state.blocks.store,
-                &state.blocks.cache,
-            )
-            .await?;
-
-            if content_length.is_some() {
-                tracing::info!("Draining request body");
-                // If the client provided a `Content-Length` value, then
-                // we know the client didn't stream the request.
-                // In that case, it's common that the client doesn't support
-                // getting a response before it finished finished sending,
-                // because the socket closes early, before the client manages
-                // to read the response.
-                tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
-            }
-
-            if response.indicates_finished() {
-                account
-                    .set_volume_cid(conn, &cid_string, &state.blocks.ipfs_db())
-                    .await?;
+            AccountRecord::find_by_did(conn, &did)
+                .await?
+                .set_volume_cid(conn, &cid_string, state.blocks.ipfs_db())
+                .await?;
 
-                Ok((StatusCode::OK, DagCbor(response)))
-            } else {
-                Ok((StatusCode::ACCEPTED, DagCbor(response)))
-            }
+            Ok((StatusCode::OK, DagCbor(response)))
         }
         .scope_boxed()
     })
     .await
 }
 
+/// Make sure none of given CIDs are part of a private volume of another account or app.
+///
+/// The server may already have all blocks of such a CID, so a push linking to it would
+/// succeed without uploading them, and afterwards pulling it would be authorized.
+async fn ensure_not_private_elsewhere(
+    conn: &mut Conn<'_>,
+    cids: &[Cid],
+    did: &str,
+) -> AppResult<()> {
+    let owners = Volume::find_private_owners(conn, cids).await?;
+
+    if owners.values().any(|owners| !owners.contains(did)) {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            Some("This push links to blocks of another private volume"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// A blockstore that remembers which blocks a push stored and which blocks they link to
+#[derive(Debug, Clone)]
+struct RecordingBlockStore<B> {
+    inner: B,
+    received: Arc<Mutex<ReceivedBlocks>>,
+}
+
+#[derive(Debug, Default)]
+struct ReceivedBlocks {
+    cids: BTreeSet<Cid>,
+    links: BTreeSet<Cid>,
+}
+
+impl<B> RecordingBlockStore<B> {
+    fn new(inner: B) -> Self {
+        Self {
+            inner,
+            received: Default::default(),
+        }
+    }
+
+    fn record(&self, cid: Cid, block: &[u8]) -> Result<(), BlockStoreError> {
+        let links = block_links(&cid, block)?;
+
+        let mut received = self.received.lock();
+        received.cids.insert(cid);
+        received.links.extend(links);
+
+        Ok(())
+    }
+
+    /// The blocks that received blocks link to, but that weren't received themselves
+    fn linked_blocks(&self) -> Vec<Cid> {
+        let received = self.received.lock();
+        received.links.difference(&received.cids).copied().collect()
+    }
+}
+
+impl<B: BlockStore> BlockStore for RecordingBlockStore<B> {
+    async fn get_block(&self, cid: &Cid) -> Result<Bytes, BlockStoreError> {
+        self.inner.get_block(cid).await
+    }
+
+    async fn put_block(
+        &self,
+        bytes: impl Into<Bytes> + CondSend,
+        codec: u64,
+    ) -> Result<Cid, BlockStoreError> {
+        let bytes = bytes.into();
+        let cid = self.inner.put_block(bytes.clone(), codec).await?;
+        self.record(cid, &bytes)?;
+        Ok(cid)
+    }
+
+    async fn put_block_keyed(
+        &self,
+        cid: Cid,
+        bytes: impl Into<Bytes> + CondSend,
+    ) -> Result<(), BlockStoreError> {
+        let bytes = bytes.into();
+        self.inner.put_block_keyed(cid, bytes.clone()).await?;
+        self.record(cid, &bytes)
+    }
+
+    async fn has_block(&self, cid: &Cid) -> Result<bool, BlockStoreError> {
+        self.inner.has_block(cid).await
+    }
+}
+
+/// Stream a car-mirror push request body into the server's blockstore.
+///
+/// If a `max_size` is given, the push to the volume of `did` is rejected as soon as
+/// all of its requests together uploaded more than that many bytes.
+///
+/// Pushes can't link to blocks of private volumes of other accounts or apps.
+pub(crate) async fn receive_push<S: ServerSetup>(
+    state: &AppState<S>,
+    did: &str,
+    cid: Cid,
+    max_size: Option<u64>,
+    content_length: Option<u64>,
+    body: BodyStream,
+) -> AppResult<PushResponse> {
+    let push_sizes = &state.push_sizes;
+
+    ensure_not_private_elsewhere(&mut db::connect(&state.db_pool).await?, &[cid], did).await?;
+
+    if let (Some(max_size), Some(content_length)) = (max_size, content_length) {
+        if push_sizes.received(did, cid).saturating_add(content_length) > max_size {
+            return Err(push_too_large(max_size));
+        }
+    }
+
+    let exceeded = AtomicBool::new(false);
+    let body = body
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        .map(|chunk| {
+            let chunk = chunk?;
+            if let Some(max_size) = max_size {
+                if push_sizes.add(did, cid, chunk.len() as u64) > max_size {
+                    exceeded.store(true, Ordering::Relaxed);
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "Push exceeds the maximum size",
+                    ));
+                }
+            }
+            Ok(chunk)
+        });
+    let mut reader = StreamReader::new(body);
+    let store = RecordingBlockStore::new(state.blocks.store.clone());
+
+    let result = async {
+        let response = car_mirror::push::response_streaming(
+            cid,
+            &mut reader,
+            &Default::default(),
+            &store,
+            &state.blocks.cache,
+        )
+        .await?;
+
+        if content_length.is_some() {
+            tracing::info!("Draining request body");
+            // If the client provided a `Content-Length` value, then
+            // we know the client didn't stream the request.
+            // In that case, it's common that the client doesn't support
+            // getting a response before it finished finished sending,
+            // because the socket closes early, before the client manages
+            // to read the response.
+            tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+        }
+
+        AppResult::Ok(response)
+    }
+    .await;
+
+    let response = match (result, max_size) {
+        (Err(_), Some(max_size)) if exceeded.load(Ordering::Relaxed) => {
+            return Err(push_too_large(max_size));
+        }
+        (result, _) => result?,
+    };
+
+    // The server may already have some of the blocks that received blocks link to,
+    // in which case the push doesn't need to upload them.
+    let conn = &mut db::connect(&state.db_pool).await?;
+    ensure_not_private_elsewhere(conn, &store.linked_blocks(), did).await?;
+
+    if response.indicates_finished() {
+        push_sizes.finish(did, cid);
+    }
+
+    Ok(response)
+}
+
+fn push_too_large(max_size: u64) -> AppError {
+    AppError::new(
+        StatusCode::FORBIDDEN,
+        Some(format!(
+            "Capability allows uploading at most {max_size} bytes per push"
+        )),
+    )
+}
+
 /// GET some data via car-mirror
 #[utoipa::path(
     get,
@@ -107,10 +335,13 @@ pub async fn push_volume_cid<S: ServerSetup>(
     responses(
         (status = 200, description = "Ok"),
         (status = 400, description = "Bad Request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Forbidden"),
     )
 )]
 pub async fn pull_volume_cid<S: ServerSetup>(
     State(state): State<AppState<S>>,
+    authority: Result<Authority, AppError>,
     Path(cid_string): Path<String>,
     request: Option<DagCbor<PullRequest>>,
 ) -> AppResult<(StatusCode, StreamBody<impl Stream<Item = AppResult<Bytes>>>)> {
@@ -124,16 +355,228 @@ pub async fn pull_volume_cid<S: ServerSetup>(
         })
     });
 
-    let car_stream = car_mirror::pull::response_streaming(
-        cid,
-        request,
-        state.blocks.store.clone(),
-        state.blocks.cache.clone(),
-    )
-    .await?;
+    let conn = &mut db::connect(&state.db_pool).await?;
+    let requested = std::iter::once(cid)
+        .chain(request.resources.iter().copied())
+        .collect::<Vec<_>>();
+    let reader = authorize_pull(&state, conn, authority, &requested).await?;
+
+    // The requested DAG may still link into private volumes, if there are any
+    let lookup = match Volume::any_private(conn).await? {
+        true => Some(Arc::new(PrivateBlockLookup {
+            db_pool: state.db_pool.clone(),
+            reader,
+            readable: Default::default(),
+        })),
+        false => None,
+    };
+    let store = PrivateBlocksFilter {
+        inner: state.blocks.store.clone(),
+        lookup,
+    };
+
+    let car_stream =
+        car_mirror::pull::response_streaming(cid, request, store, state.blocks.cache.clone())
+            .await?;
 
     Ok((
         StatusCode::OK,
         StreamBody::new(car_stream.map_err(AppError::from)),
     ))
 }
+
+/// Private volumes and blocks within them can only be pulled
+/// with a `volume/read` capability from their owner.
+///
+/// Returns the owner's DID, if pulling any of the CIDs needed such a capability.
+async fn authorize_pull<S: ServerSetup>(
+    state: &AppState<S>,
+    conn: &mut Conn<'_>,
+    authority: Result<Authority, AppError>,
+    cids: &[Cid],
+) -> AppResult<Option<String>> {
+    let private_owners = Volume::find_private_owners(conn, cids).await?;
+
+    if private_owners.is_empty() {
+        return Ok(None);
+    }
+
+    let Did(did) = authority?
+        .get_capability(state, VolumeAbility::Read)
+        .await?;
+
+    if !private_owners.values().all(|owners| owners.contains(&did)) {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            Some("Not allowed to pull this private volume"),
+        ));
+    }
+
+    Ok(Some(did))
+}
+
+/// A blockstore that only serves blocks of private volumes to their owners.
+///
+/// Pulls walk the DAG below the requested CIDs, which may link into private volumes
+/// that the requested CIDs themselves aren't part of.
+#[derive(Debug, Clone)]
+struct PrivateBlocksFilter<B> {
+    inner: B,
+    /// Only set if there are any private volumes
+    lookup: Option<Arc<PrivateBlockLookup>>,
+}
+
+#[derive(Debug)]
+struct PrivateBlockLookup {
+    db_pool: Pool,
+    /// The DID that authorized the pull, if any
+    reader: Option<String>,
+    /// Whether blocks are readable by their raw CID, so each block is looked up once per pull
+    readable: DashMap<String, bool>,
+}
+
+impl<B> PrivateBlocksFilter<B> {
+    /// Pretend not to have blocks of private volumes that the reader doesn't own
+    async fn ensure_readable(&self, cid: &Cid) -> Result<(), BlockStoreError> {
+        let Some(lookup) = &self.lookup else {
+            return Ok(());
+        };
+
+        let key = raw_cid(cid);
+        let cached = lookup.readable.get(&key).map(|readable| *readable);
+        let readable = match cached {
+            Some(readable) => readable,
+            None => {
+                let conn = &mut db::connect(&lookup.db_pool).await?;
+                let owners = Volume::find_private_block_owners(conn, &[*cid])
+                    .await?
+                    .remove(cid)
+                    .unwrap_or_default();
+
+                let readable = match &lookup.reader {
+                    _ if owners.is_empty() => true,
+                    Some(reader) => owners.contains(reader),
+                    None => false,
+                };
+                lookup.readable.insert(key, readable);
+                readable
+            }
+        };
+
+        if !readable {
+            return Err(BlockStoreError::CIDNotFound(*cid));
+        }
+
+        Ok(())
+    }
+}
+
+impl<B: BlockStore> BlockStore for PrivateBlocksFilter<B> {
+    async fn get_block(&self, cid: &Cid) -> Result<Bytes, BlockStoreError> {
+        self.ensure_readable(cid).await?;
+        self.inner.get_block(cid).await
+    }
+
+    async fn put_block(
+        &self,
+        bytes: impl Into<Bytes> + CondSend,
+        codec: u64,
+    ) -> Result<Cid, BlockStoreError> {
+        self.inner.put_block(bytes, codec).await
+    }
+
+    async fn put_block_keyed(
+        &self,
+        cid: Cid,
+        bytes: impl Into<Bytes> + CondSend,
+    ) -> Result<(), BlockStoreError> {
+        self.inner.put_block_keyed(cid, bytes).await
+    }
+
+    async fn has_block(&self, cid: &Cid) -> Result<bool, BlockStoreError> {
+        match self.ensure_readable(cid).await {
+            Ok(()) => self.inner.has_block(cid).await,
+            Err(BlockStoreError::CIDNotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// PATCH whether pulling an account's or app's volume requires a `volume/read` capability
+#[utoipa::path(
+    patch,
+    path = "/api/v0/volume/visibility",
+    request_body = VolumeVisibilityRequest,
+    security(
+        ("ucan_bearer" = []),
+    ),
+    responses(
+        (status = 200, description = "Changed volume visibility", body = SuccessResponse),
+        (status = 400, description = "Bad Request"),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn patch_volume_visibility<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    authority: Authority,
+    Json(request): Json<VolumeVisibilityRequest>,
+) -> AppResult<(StatusCode, Json<SuccessResponse>)> {
+    let Did(did) = authority
+        .get_capability_for(
+            &state,
+            FissionAbility::AccountManage,
+            AccountOperation::VolumeSettings,
+        )
+        .await?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+    conn.transaction(|conn| {
+        async move {
+            // The DID is either an account's or one of its apps'
+            match AccountRecord::find_by_did(conn, &did).await.optional()? {
+                Some(account) => {
+                    account
+                        .set_volume_private(conn, request.private, state.blocks.ipfs_db())
+                        .await?
+                }
+                None => {
+                    AppRecord::find_by_did(conn, &did)
+                        .await?
+                        .set_volume_private(conn, request.private, state.blocks.ipfs_db())
+                        .await?
+                }
+            }
+
+            Ok((StatusCode::OK, Json(SuccessResponse { success: true })))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testresult::TestResult;
+
+    #[test]
+    fn test_push_sizes() -> TestResult {
+        let sizes = PushSizes::default();
+        let did = "did:key:z6MkiTBz1ymuepAQ4HEHYSF1H8quG5GLVVQR3djdX3mDooWp";
+        let cid = Cid::from_str("bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e")?;
+        let other_cid =
+            Cid::from_str("bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku")?;
+
+        assert_eq!(sizes.received(did, cid), 0);
+        assert_eq!(sizes.add(did, cid, 40), 40);
+        assert_eq!(sizes.add(did, cid, 40), 80);
+        assert_eq!(sizes.received(did, cid), 80);
+        assert_eq!(sizes.received(did, other_cid), 0);
+
+        sizes.finish(did, cid);
+        assert_eq!(sizes.received(did, cid), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+//! Routes for passkey registration and login via WebAuthn
+
+use crate::{
+    app_state::AppState,
+    db::{self, schema::accounts},
+    error::{AppError, AppResult},
+    extract::json::Json,
+    models::{
+        account::{AccountAndAuth, AccountRecord},
+        passkey::{authenticated_credential_id, PasskeyRecord},
+        webauthn_challenge::WebauthnChallengeRecord,
+    },
+    passkeys::passkey_did,
+    setups::ServerSetup,
+};
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use fission_core::common::{
+    PasskeyChallengeResponse, PasskeyFinishRequest, PasskeyRegistrationResponse,
+    PasskeyStartRequest,
+};
+use serde::de::DeserializeOwned;
+use webauthn_rs::prelude::{
+    PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential, RegisterPublicKeyCredential,
+    Uuid,
+};
+
+/// POST handler for starting a passkey registration
+#[utoipa::path(
+    post,
+    path = "/api/v0/auth/webauthn/register/start",
+    request_body = PasskeyStartRequest,
+    responses(
+        (status = 200, description = "Started passkey registration", body = PasskeyChallengeResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+    )
+)]
+pub async fn start_registration<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Json(request): Json<PasskeyStartRequest>,
+) -> AppResult<(StatusCode, Json<PasskeyChallengeResponse>)> {
+    // The user handle is only used by authenticators to tell apart accounts, so it's random
+    let user_id = Uuid::from_bytes(rand::random());
+
+    let (options, registration) = state
+        .webauthn
+        .start_passkey_registration(
+            user_id,
+            request.username.as_str(),
+            &request.username.to_unicode(),
+            None,
+        )
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let challenge_id = WebauthnChallengeRecord::new(conn, &registration, None).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(PasskeyChallengeResponse {
+            challenge_id,
+            options: serde_json::to_value(options).map_err(anyhow::Error::from)?,
+        }),
+    ))
+}
+
+/// POST handler for finishing a passkey registration.
+/// The passkey can then be associated with an account on account creation.
+#[utoipa::path(
+    post,
+    path = "/api/v0/auth/webauthn/register/finish",
+    request_body = PasskeyFinishRequest,
+    responses(
+        (status = 200, description = "Registered passkey", body = PasskeyRegistrationResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 404, description = "Registration not found or expired"),
+        (status = 422, description = "Unsupported passkey algorithm", body = AppError),
+    )
+)]
+pub async fn finish_registration<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Json(request): Json<PasskeyFinishRequest>,
+) -> AppResult<(StatusCode, Json<PasskeyRegistrationResponse>)> {
+    let credential: RegisterPublicKeyCredential = parse_credential(request.credential)?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let (registration, _) =
+        WebauthnChallengeRecord::take::<PasskeyRegistration>(conn, &request.challenge_id).await?;
+
+    let passkey = state
+        .webauthn
+        .finish_passkey_registration(&credential, &registration)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
+
+    let did = passkey_did(passkey.get_public_key())
+        .map_err(|e| AppError::new(StatusCode::UNPROCESSABLE_ENTITY, Some(e)))?;
+
+    let record = PasskeyRecord::new(conn, &passkey, did).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(PasskeyRegistrationResponse {
+            credential_id: record.credential_id,
+            did: record.did,
+        }),
+    ))
+}
+
+/// POST handler for starting a login via passkey
+#[utoipa::path(
+    post,
+    path = "/api/v0/auth/webauthn/login/start",
+    request_body = PasskeyStartRequest,
+    responses(
+        (status = 200, description = "Started passkey login", body = PasskeyChallengeResponse),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 404, description = "Account not found"),
+        (status = 422, description = "No passkeys registered for the account", body = AppError),
+    )
+)]
+pub async fn start_login<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Json(request): Json<PasskeyStartRequest>,
+) -> AppResult<(StatusCode, Json<PasskeyChallengeResponse>)> {
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    let account = AccountRecord::find_by_username(conn, request.username.as_str()).await?;
+
+    let passkeys = PasskeyRecord::find_by_account(conn, account.id)
+        .await?
+        .iter()
+        .map(PasskeyRecord::passkey)
+        .collect::<Result<Vec<_>>>()?;
+
+    if passkeys.is_empty() {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Some("No passkeys registered for this account"),
+        ));
+    }
+
+    let (options, authentication) = state
+        .webauthn
+        .start_passkey_authentication(&passkeys)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(e)))?;
+
+    let challenge_id =
+        WebauthnChallengeRecord::new(conn, &authentication, Some(account.id)).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(PasskeyChallengeResponse {
+            challenge_id,
+            options: serde_json::to_value(options).map_err(anyhow::Error::from)?,
+        }),
+    ))
+}
+
+/// POST handler for finishing a login via passkey.
+/// This delegates account access to the DID derived from the passkey that signed the challenge.
+/// No other key proved anything during the ceremony, so no other DID gets access.
+#[utoipa::path(
+    post,
+    path = "/api/v0/auth/webauthn/login/finish",
+    request_body = PasskeyFinishRequest,
+    responses(
+        (status = 200, description = "Successfully linked account", body = AccountAndAuth),
+        (status = 400, description = "Invalid request", body = AppError),
+        (status = 403, description = "Forbidden"),
+        (status = 404, description = "Login not found or expired"),
+    )
+)]
+pub async fn finish_login<S: ServerSetup>(
+    State(state): State<AppState<S>>,
+    Json(request): Json<PasskeyFinishRequest>,
+) -> AppResult<(StatusCode, Json<AccountAndAuth>)> {
+    let credential: PublicKeyCredential = parse_credential(request.credential)?;
+
+    let conn = &mut db::connect(&state.db_pool).await?;
+
+    // Taken outside the transaction below, so failed attempts can't be retried
+    let (authentication, account_id) =
+        WebauthnChallengeRecord::take::<PasskeyAuthentication>(conn, &request.challenge_id).await?;
+
+    let account_id = account_id.ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some("Challenge is not a passkey login challenge"),
+        )
+    })?;
+
+    let result = state
+        .webauthn
+        .finish_passkey_authentication(&credential, &authentication)
+        .map_err(|e| AppError::new(StatusCode::FORBIDDEN, Some(e)))?;
+
+    conn.transaction(|conn| {
+        async move {
+            PasskeyRecord::update_credential(conn, account_id, &result).await?;
+
+            let passkey = PasskeyRecord::find_by_credential_id(
+                conn,
+                account_id,
+                &authenticated_credential_id(&result),
+            )
+            .await?;
+
+            let account: AccountRecord = accounts::table
+                .filter(accounts::id.eq(account_id))
+                .first(conn)
+                .await?;
+
+            let account = AccountAndAuth::link_agent(
+                account,
+                &passkey.did,
+                &state.server_keypair,
+                &state.dns_settings,
+                conn,
+            )
+            .await?;
+
+            Ok((StatusCode::OK, Json(account)))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+fn parse_credential<T: DeserializeOwned>(credential: serde_json::Value) -> AppResult<T> {
+    serde_json::from_value(credential).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some(format!("Invalid WebAuthn credential: {e}")),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::schema::{passkeys, webauthn_challenges},
+        error::ErrorResponse,
+        models::{
+            account::AccountAndAuth, passkey::PasskeyRecord,
+            webauthn_challenge::WebauthnChallengeRecord,
+        },
+        test_utils::test_context::TestContext,
+    };
+    use anyhow::Result;
+    use diesel::{
+        dsl::{now, IntervalDsl},
+        ExpressionMethods,
+    };
+    use diesel_async::RunQueryDsl;
+    use fission_core::{
+        capabilities::{did::Did, fission::FissionAbility},
+        common::{PasskeyChallengeResponse, PasskeyRegistrationResponse, SuccessResponse},
+        ed_did_key::EdDidKey,
+    };
+    use http::{Method, StatusCode};
+    use rs_ucan::{
+        builder::UcanBuilder, capability::Capability, semantics::caveat::EmptyCaveat, ucan::Ucan,
+    };
+    use serde_json::json;
+    use testresult::TestResult;
+    use url::Url;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+
+    const RP_ORIGIN: &str = "http://localhost:3000";
+
+    async fn register_passkey(
+        username: &str,
+        authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+        ctx: &TestContext,
+    ) -> Result<PasskeyRegistrationResponse> {
+        let (_, challenge) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/register/start")
+            .with_json_body(json!({ "username": username }))?
+            .into_json_response::<PasskeyChallengeResponse>()
+            .await?;
+
+        let credential = authenticator
+            .do_registration(
+                Url::parse(RP_ORIGIN)?,
+                serde_json::from_value(challenge.options)?,
+            )
+            .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+
+        let (status, registration) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/register/finish")
+            .with_json_body(json!({
+                "challengeId": challenge.challenge_id,
+                "credential": credential,
+            }))?
+            .into_json_response::<PasskeyRegistrationResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        Ok(registration)
+    }
+
+    async fn create_account_with_passkey(
+        username: &str,
+        email: &str,
+        credential_id: &str,
+        ctx: &TestContext,
+    ) -> Result<AccountAndAuth> {
+        ctx.request(Method::POST, "/api/v0/auth/email/verify")
+            .with_json_body(json!({ "email": email }))?
+            .into_json_response::<SuccessResponse>()
+            .await?;
+
+        let (_, code) = ctx
+            .verification_code_sender()
+            .get_emails()
+            .into_iter()
+            .last()
+            .expect("No email Sent");
+
+        let issuer = &EdDidKey::generate();
+        let ucan: Ucan = UcanBuilder::default()
+            .for_audience(ctx.server_did())
+            .claiming_capability(Capability::new(
+                Did(issuer.did()),
+                FissionAbility::AccountCreate,
+                EmptyCaveat,
+            ))
+            .sign(issuer)?;
+
+        let (status, auth) = ctx
+            .request(Method::POST, "/api/v0/account")
+            .with_ucan(ucan)
+            .with_json_body(json!({
+                "username": username,
+                "email": email,
+                "code": code,
+                "credentialID": credential_id,
+            }))?
+            .into_json_response::<AccountAndAuth>()
+            .await?;
+
+        assert_eq!(status, StatusCode::CREATED);
+
+        Ok(auth)
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_passkey_registration_and_login_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let authenticator = &mut WebauthnAuthenticator::new(SoftPasskey::new());
+
+        let registration = register_passkey("oedipa", authenticator, ctx).await?;
+        assert!(registration.did.starts_with("did:key:zDn"));
+
+        create_account_with_passkey(
+            "oedipa",
+            "oedipa@trystero.com",
+            &registration.credential_id,
+            ctx,
+        )
+        .await?;
+
+        let (status, challenge) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/login/start")
+            .with_json_body(json!({ "username": "oedipa" }))?
+            .into_json_response::<PasskeyChallengeResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+
+        let credential = authenticator
+            .do_authentication(
+                Url::parse(RP_ORIGIN)?,
+                serde_json::from_value(challenge.options)?,
+            )
+            .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+
+        let (status, auth) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/login/finish")
+            .with_json_body(json!({
+                "challengeId": challenge.challenge_id,
+                "credential": credential,
+            }))?
+            .into_json_response::<AccountAndAuth>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(auth.account.username.as_deref(), Some("oedipa"));
+
+        // Access is only delegated to the passkey that signed the challenge
+        assert!(auth
+            .ucans
+            .iter()
+            .any(|ucan| ucan.audience() == registration.did));
+        assert!(auth
+            .ucans
+            .iter()
+            .filter(|ucan| ucan.issuer() == ctx.server_did().did_as_str())
+            .all(|ucan| ucan.audience() == registration.did));
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_delete_expired_registrations() -> TestResult {
+        let ctx = &TestContext::new().await?;
+        let authenticator = &mut WebauthnAuthenticator::new(SoftPasskey::new());
+
+        register_passkey("oedipa", authenticator, ctx).await?;
+
+        let (_, challenge) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/register/start")
+            .with_json_body(json!({ "username": "mucho" }))?
+            .into_json_response::<PasskeyChallengeResponse>()
+            .await?;
+
+        let conn = &mut ctx.get_db_conn().await?;
+
+        assert_eq!(WebauthnChallengeRecord::delete_expired(conn).await?, 0);
+        assert_eq!(PasskeyRecord::delete_unclaimed(conn).await?, 0);
+
+        diesel::update(webauthn_challenges::table)
+            .filter(webauthn_challenges::challenge_id.eq(&challenge.challenge_id))
+            .set(webauthn_challenges::inserted_at.eq(now - 10.minutes()))
+            .execute(conn)
+            .await?;
+
+        diesel::update(passkeys::table)
+            .set(passkeys::inserted_at.eq(now - 25.hours()))
+            .execute(conn)
+            .await?;
+
+        assert_eq!(WebauthnChallengeRecord::delete_expired(conn).await?, 1);
+        assert_eq!(PasskeyRecord::delete_unclaimed(conn).await?, 1);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_start_registration_ok() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, response) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/register/start")
+            .with_json_body(json!({ "username": "oedipa" }))?
+            .into_json_response::<PasskeyChallengeResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.options["publicKey"]["user"]["name"], "oedipa");
+        assert_eq!(response.options["publicKey"]["rp"]["id"], "localhost");
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_finish_registration_invalid_credential_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (_, response) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/register/start")
+            .with_json_body(json!({ "username": "oedipa" }))?
+            .into_json_response::<PasskeyChallengeResponse>()
+            .await?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/register/finish")
+            .with_json_body(json!({
+                "challengeId": response.challenge_id,
+                "credential": { "id": "not a credential" },
+            }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_start_login_without_passkeys_err() -> TestResult {
+        let ctx = &TestContext::new().await?;
+
+        let (status, _) = ctx
+            .request(Method::POST, "/api/v0/auth/webauthn/login/start")
+            .with_json_body(json!({ "username": "oedipa" }))?
+            .into_json_response::<ErrorResponse>()
+            .await?;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
@@ -11,7 +11,7 @@ use axum::{
 };
 use dashmap::{DashMap, DashSet};
 use futures::{
-    channel::mpsc::{self, Sender},
+    channel::mpsc::{self, Receiver, Sender},
     future, pin_mut, StreamExt, TryStreamExt,
 };
 use std::sync::{
@@ -61,6 +61,20 @@ impl WsPeerMap {
             .remove_if(topic, |_, peer_set| peer_set.is_empty());
     }
 
+    /// Add a peer that's subscribed to `topic`.
+    /// Returns the peer's ID and the receiving end of its channel.
+    pub fn subscribe(&self, topic: &str) -> (usize, Receiver<Message>) {
+        let (tx, rx) = mpsc::channel(64);
+        let peer_id = self.add_peer(WsPeer { channel: tx });
+        self.topic_subscribe(peer_id, topic);
+        (peer_id, rx)
+    }
+
+    /// Whether any peer is subscribed to `topic`
+    pub fn has_subscribers(&self, topic: &str) -> bool {
+        self.topics.contains_key(topic)
+    }
+
     fn remove_peer(&self, peer_id: usize) {
         self.peers.remove(&peer_id);
     }
@@ -115,13 +129,11 @@ pub async fn handler<S: ServerSetup>(
 }
 
 async fn handle_socket(topic: String, socket: WebSocket, map: Arc<WsPeerMap>) {
-    let (tx, rx) = mpsc::channel(64);
     let (outgoing, incoming) = socket.split();
 
     tracing::debug!(topic, "websocket peer connected");
 
-    let peer_id = map.add_peer(WsPeer { channel: tx });
-    map.topic_subscribe(peer_id, &topic);
+    let (peer_id, rx) = map.subscribe(&topic);
 
     let broadcast = incoming.try_for_each(|msg| async {
         match msg {
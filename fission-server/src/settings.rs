@@ -4,7 +4,7 @@ use config::{Config, ConfigError, Environment, File};
 use http::Uri;
 use serde::Deserialize;
 use serde_with::serde_as;
-use std::{path::PathBuf, time::Duration};
+use std::{collections::BTreeMap, net::IpAddr, path::PathBuf, time::Duration};
 
 /// Names of environments for fission-server.
 /// Overrides serialization to force lower case in settings and
@@ -60,6 +60,216 @@ pub struct Dns {
     pub origin: String,
     /// Domain used for serving the `_did.<username>.users_origin>` DNS TXT entry
     pub users_origin: String,
+    /// Domain used for serving the `_dnslink.<app name>.<apps_origin>` DNS TXT entry
+    pub apps_origin: String,
+}
+
+/// Username registration settings
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Usernames {
+    /// Usernames that can't be registered, in addition to the built-in reserved usernames
+    #[serde(default)]
+    pub denylist: Vec<String>,
+    /// Regular expressions matched against the whole (punycode) username.
+    /// Usernames matching any of these can't be registered.
+    #[serde(default)]
+    pub denylist_patterns: Vec<String>,
+}
+
+/// Account lifecycle settings
+#[derive(Clone, Debug, Deserialize)]
+pub struct Accounts {
+    /// How long deleted accounts can still be restored, in seconds, before they're purged
+    pub deletion_grace_period_secs: u64,
+    /// How often to purge deleted accounts, in seconds
+    pub purge_interval_secs: u64,
+}
+
+impl Default for Accounts {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period_secs: 30 * 24 * 60 * 60,
+            purge_interval_secs: 60 * 60,
+        }
+    }
+}
+
+impl Accounts {
+    /// Convert `deletion_grace_period_secs` to [Duration].
+    pub fn deletion_grace_period(&self) -> Duration {
+        Duration::from_secs(self.deletion_grace_period_secs)
+    }
+
+    /// Convert `purge_interval_secs` to [Duration].
+    pub fn purge_interval(&self) -> Duration {
+        Duration::from_secs(self.purge_interval_secs)
+    }
+
+    /// Fails on settings the purge task can't run with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.purge_interval_secs == 0 {
+            return Err(ConfigError::Message(
+                "accounts.purge_interval_secs must be greater than zero".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for re-verifying custom handles
+#[derive(Clone, Debug, Deserialize)]
+pub struct Handles {
+    /// How often to look for handles that need re-verification, in seconds
+    pub reverify_interval_secs: u64,
+    /// How long a successful verification is trusted, in seconds, before checking again
+    pub verification_ttl_secs: u64,
+    /// How long a handle can keep failing re-verification, in seconds, before it's cleared
+    pub unverified_grace_period_secs: u64,
+}
+
+impl Default for Handles {
+    fn default() -> Self {
+        Self {
+            reverify_interval_secs: 60 * 60,
+            verification_ttl_secs: 24 * 60 * 60,
+            unverified_grace_period_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Handles {
+    /// Convert `reverify_interval_secs` to [Duration].
+    pub fn reverify_interval(&self) -> Duration {
+        Duration::from_secs(self.reverify_interval_secs)
+    }
+
+    /// Convert `verification_ttl_secs` to [Duration].
+    pub fn verification_ttl(&self) -> Duration {
+        Duration::from_secs(self.verification_ttl_secs)
+    }
+
+    /// Convert `unverified_grace_period_secs` to [Duration].
+    pub fn unverified_grace_period(&self) -> Duration {
+        Duration::from_secs(self.unverified_grace_period_secs)
+    }
+
+    /// Fails on settings the re-verification task can't run with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.reverify_interval_secs == 0 {
+            return Err(ConfigError::Message(
+                "handles.reverify_interval_secs must be greater than zero".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Rate limits for requesting email verification codes
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimits {
+    /// How many codes can be requested for the same email address per window
+    pub email_max_requests: u32,
+    /// Length of the per-email window in seconds
+    pub email_window_secs: u64,
+    /// How many codes can be requested from the same client IP per window
+    pub ip_max_requests: u32,
+    /// Length of the per-IP window in seconds
+    pub ip_window_secs: u64,
+    /// How many verification codes the same client IP can try per window
+    pub ip_max_code_attempts: u32,
+    /// Addresses of reverse proxies in front of this server.
+    /// Only requests from these peers have their `X-Forwarded-For` header trusted.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            email_max_requests: 5,
+            email_window_secs: 60 * 60,
+            ip_max_requests: 30,
+            ip_window_secs: 60 * 60,
+            ip_max_code_attempts: 30,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl RateLimits {
+    /// Convert `email_window_secs` to [Duration].
+    pub fn email_window(&self) -> Duration {
+        Duration::from_secs(self.email_window_secs)
+    }
+
+    /// Convert `ip_window_secs` to [Duration].
+    pub fn ip_window(&self) -> Duration {
+        Duration::from_secs(self.ip_window_secs)
+    }
+}
+
+/// Settings for single-use email verification links
+#[derive(Clone, Debug, Deserialize)]
+pub struct VerificationLinks {
+    /// The public URL of this server that verification links point to
+    pub base_url: String,
+}
+
+impl Default for VerificationLinks {
+    fn default() -> Self {
+        Self {
+            base_url: "http://localhost:3000".to_string(),
+        }
+    }
+}
+
+/// WebAuthn relying party settings, used for passkey registration and login
+#[derive(Clone, Debug, Deserialize)]
+pub struct Webauthn {
+    /// The relying party ID, i.e. the effective domain that passkeys are scoped to
+    pub rp_id: String,
+    /// The web origin that WebAuthn ceremonies are performed from
+    pub rp_origin: String,
+    /// Human-readable relying party name that authenticators may show
+    pub rp_name: String,
+    /// How often to delete expired ceremonies and unclaimed passkeys, in seconds
+    #[serde(default = "default_webauthn_cleanup_interval_secs")]
+    pub cleanup_interval_secs: u64,
+}
+
+fn default_webauthn_cleanup_interval_secs() -> u64 {
+    10 * 60
+}
+
+impl Default for Webauthn {
+    fn default() -> Self {
+        Self {
+            rp_id: "localhost".to_string(),
+            rp_origin: "http://localhost:3000".to_string(),
+            rp_name: "Fission".to_string(),
+            cleanup_interval_secs: default_webauthn_cleanup_interval_secs(),
+        }
+    }
+}
+
+impl Webauthn {
+    /// Convert `cleanup_interval_secs` to [Duration].
+    pub fn cleanup_interval(&self) -> Duration {
+        Duration::from_secs(self.cleanup_interval_secs)
+    }
+
+    /// Fails on settings the cleanup task can't run with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.cleanup_interval_secs == 0 {
+            return Err(ConfigError::Message(
+                "webauthn.cleanup_interval_secs must be greater than zero".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// Server settings.
@@ -119,6 +329,134 @@ pub struct Mailgun {
     pub from_name: String,
     /// Mailgun Template
     pub template: String,
+    /// The region of the Mailgun account
+    #[serde(default)]
+    pub region: MailgunRegion,
+    /// Translations, keyed by language tag, e.g. `[mailgun.locales.de]`
+    #[serde(default)]
+    pub locales: BTreeMap<String, MailgunLocale>,
+}
+
+/// Translated Mailgun settings for emails in a locale
+#[derive(Clone, Debug, Deserialize)]
+pub struct MailgunLocale {
+    /// Mailgun Subject
+    pub subject: String,
+    /// Mailgun Template
+    pub template: String,
+}
+
+/// Mailgun serves accounts in different regions from different API hosts
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailgunRegion {
+    /// api.mailgun.net
+    #[default]
+    Us,
+    /// api.eu.mailgun.net
+    Eu,
+}
+
+/// Which service sends emails
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    /// The Mailgun API, see [Mailgun]
+    #[default]
+    Mailgun,
+    /// Any SMTP server, see [Smtp]
+    Smtp,
+    /// Write emails into a local maildir instead of sending them, see [Maildir]
+    Maildir,
+    /// POST emails as JSON to a webhook, see [EmailWebhook]
+    Webhook,
+}
+
+/// Email delivery settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Email {
+    /// Which service sends emails. Its section needs to be configured, too.
+    pub provider: EmailProvider,
+    /// From address, unless the provider configures its own
+    pub from_address: String,
+    /// From name, unless the provider configures its own
+    pub from_name: String,
+    /// Subject of verification emails, unless the provider configures its own
+    pub subject: String,
+    /// Directory with custom `code.txt`, `code.html`, `link.txt` and `link.html` templates,
+    /// relative to the settings file. Missing templates fall back to the built-in ones.
+    /// Mailgun uses its own templates instead.
+    pub templates_dir: Option<String>,
+    /// Translations, keyed by language tag, e.g. `[email.locales.de]`.
+    /// Templates for a locale are read from a subdirectory of `templates_dir` with its tag,
+    /// falling back to the default templates.
+    pub locales: BTreeMap<String, EmailLocale>,
+}
+
+/// Translated settings for emails in a locale
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmailLocale {
+    /// Subject of verification emails
+    pub subject: String,
+}
+
+impl Default for Email {
+    fn default() -> Self {
+        Self {
+            provider: EmailProvider::default(),
+            from_address: "noreply@localhost".to_string(),
+            from_name: "Fission".to_string(),
+            subject: "Your Fission Verification Code".to_string(),
+            templates_dir: None,
+            locales: BTreeMap::new(),
+        }
+    }
+}
+
+/// How connections to the SMTP server are secured
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Unencrypted. Only use this for servers on the same host or private network.
+    None,
+    /// Upgrade a plain connection via STARTTLS (port 587 by default)
+    #[default]
+    Starttls,
+    /// Implicit TLS (port 465 by default)
+    Tls,
+}
+
+/// SMTP settings.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Smtp {
+    /// Hostname of the SMTP server
+    pub host: String,
+    /// Port of the SMTP server. Defaults to the standard port for `tls`.
+    pub port: Option<u16>,
+    /// How to secure the connection
+    #[serde(default)]
+    pub tls: SmtpTls,
+    /// Username to authenticate with, if the server requires authentication
+    pub username: Option<String>,
+    /// Password to authenticate with
+    pub password: Option<String>,
+}
+
+/// Settings for writing emails into a maildir, e.g. for staging environments
+#[derive(Clone, Debug, Deserialize)]
+pub struct Maildir {
+    /// The maildir directory, relative to the settings file. Created if it doesn't exist.
+    pub path: String,
+}
+
+/// Settings for handing emails off to a webhook
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmailWebhook {
+    /// The URL that email events are POSTed to
+    pub url: String,
+    /// Sent as a bearer token in the `Authorization` header, if set
+    pub secret: Option<String>,
 }
 
 /// Background healthcheck settings
@@ -146,12 +484,39 @@ pub struct Settings {
     pub server: Server,
     /// Open telemetry settings
     pub otel: Otel,
+    /// Email delivery settings
+    #[serde(default)]
+    pub email: Email,
     /// Mailgun settings
-    pub mailgun: Mailgun,
+    pub mailgun: Option<Mailgun>,
+    /// SMTP settings
+    pub smtp: Option<Smtp>,
+    /// Maildir settings
+    pub maildir: Option<Maildir>,
+    /// Email webhook settings
+    pub email_webhook: Option<EmailWebhook>,
     /// Healthcheck settings
     pub healthcheck: Healthcheck,
     /// Local authoritative DNS server settings
     pub dns: Dns,
+    /// Username registration settings
+    #[serde(default)]
+    pub usernames: Usernames,
+    /// Account lifecycle settings
+    #[serde(default)]
+    pub accounts: Accounts,
+    /// Custom handle re-verification settings
+    #[serde(default)]
+    pub handles: Handles,
+    /// WebAuthn settings for passkeys
+    #[serde(default)]
+    pub webauthn: Webauthn,
+    /// Rate limits for unauthenticated requests
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// Email verification link settings
+    #[serde(default)]
+    pub verification_links: VerificationLinks,
     /// The path where the settings file resides.
     /// This can't actually be configured in the settings file itself, for obvious reasons.
     #[serde(skip)]
@@ -174,22 +539,38 @@ impl Settings {
                     .separator("__")
                     .try_parsing(true)
                     .list_separator(",")
-                    .with_list_parse_key("ipfs.peers"),
+                    .with_list_parse_key("ipfs.peers")
+                    .with_list_parse_key("usernames.denylist")
+                    .with_list_parse_key("usernames.denylist_patterns"),
             )
             .build()?;
         let mut settings: Self = s.try_deserialize()?;
+        settings.validate()?;
         settings.path = Some(path);
         Ok(settings)
     }
 
+    /// Check settings that can be deserialized, but not used
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.accounts.validate()?;
+        self.handles.validate()?;
+        self.webauthn.validate()?;
+        Ok(())
+    }
+
     /// Return the keypair path relative to the current working directory
     /// (as opposed to `self.server.keypair_path`, which is relative to the
     /// settings file)
     pub fn relative_keypair_path(&self) -> PathBuf {
+        self.relative_path(&self.server.keypair_path)
+    }
+
+    /// Resolve a path that's configured relative to the settings file
+    pub fn relative_path(&self, path: &str) -> PathBuf {
         if let Some(settings_dir) = self.path.as_ref().and_then(|p| p.parent()) {
-            settings_dir.join(&self.server.keypair_path)
+            settings_dir.join(path)
         } else {
-            PathBuf::from(&self.server.keypair_path)
+            PathBuf::from(path)
         }
     }
 }
@@ -299,6 +680,28 @@ mod tests {
         assert_eq!(settings.http_client.timeout_ms, 100);
     }
 
+    #[test]
+    fn test_accounts_zero_purge_interval_err() {
+        let accounts = Accounts {
+            purge_interval_secs: 0,
+            ..Default::default()
+        };
+
+        assert!(accounts.validate().is_err());
+        assert!(Accounts::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_handles_zero_reverify_interval_err() {
+        let handles = Handles {
+            reverify_interval_secs: 0,
+            ..Default::default()
+        };
+
+        assert!(handles.validate().is_err());
+        assert!(Handles::default().validate().is_ok());
+    }
+
     #[test]
     fn test_http_client_partial_overrides() {
         let settings = Client {
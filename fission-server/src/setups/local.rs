@@ -4,8 +4,11 @@ use anyhow::Result;
 use async_trait::async_trait;
 use axum::extract::ws::Message;
 
-use super::{prod::IpfsHttpApiDatabase, ServerSetup, VerificationCodeSender};
-use crate::routes::ws::WsPeerMap;
+use super::{
+    prod::{IpfsHttpApiDatabase, ReqwestHttpClient},
+    ServerSetup, Verification, VerificationCodeSender,
+};
+use crate::{rate_limit::InMemoryRateLimitStore, routes::ws::WsPeerMap};
 use std::sync::Arc;
 
 /// Implementation of `ServerSetup` for local environments.
@@ -16,10 +19,12 @@ pub struct LocalSetup;
 impl ServerSetup for LocalSetup {
     type IpfsDatabase = IpfsHttpApiDatabase;
     type VerificationCodeSender = WebsocketCodeSender;
+    type RateLimitStore = InMemoryRateLimitStore;
+    type HttpClient = ReqwestHttpClient;
 }
 
 /// A `VerificationCodeSender` that doesn't actually send emails,
-/// but instead logs them via tracing & sends a ws message on the
+/// but instead logs them via tracing & sends codes as a ws message on the
 /// channel with the email as the topic.
 #[derive(Debug, Clone)]
 pub struct WebsocketCodeSender {
@@ -35,10 +40,32 @@ impl WebsocketCodeSender {
 
 #[async_trait]
 impl VerificationCodeSender for WebsocketCodeSender {
-    async fn send_code(&self, email: &str, code: &str) -> Result<()> {
-        tracing::info!(email, ?code, "verification code (also sent via websockets)");
-        self.ws_peer_map
-            .broadcast_on_topic(email, Message::Text(code.to_string()), None);
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        match verification {
+            Verification::Code(code) => {
+                tracing::info!(
+                    email,
+                    ?code,
+                    ?locale,
+                    "verification code (also sent via websockets)"
+                );
+                self.ws_peer_map
+                    .broadcast_on_topic(email, Message::Text(code.to_string()), None);
+            }
+            Verification::Link(url) => {
+                tracing::info!(email, url, ?locale, "verification link");
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        tracing::info!(old_email, new_email, "email address changed notice");
         Ok(())
     }
 }
@@ -0,0 +1,113 @@
+//! Writing verification emails into a local maildir instead of sending them
+
+use crate::{
+    email_templates::EmailComposer,
+    setups::{Verification, VerificationCodeSender},
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use hex::ToHex;
+use lettre::Message;
+use rand::Rng;
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A `VerificationCodeSender` that stores emails in a maildir, so they can be
+/// inspected with any mail client. Useful for staging environments.
+#[derive(Debug, Clone)]
+pub struct MaildirVerificationCodeSender {
+    path: PathBuf,
+    composer: EmailComposer,
+}
+
+impl MaildirVerificationCodeSender {
+    /// Create a new MaildirVerificationCodeSender, creating the maildir if needed
+    pub fn new(path: PathBuf, composer: EmailComposer) -> Result<Self> {
+        for subdir in ["tmp", "new", "cur"] {
+            std::fs::create_dir_all(path.join(subdir))
+                .with_context(|| format!("Creating maildir at {}", path.display()))?;
+        }
+
+        Ok(Self { path, composer })
+    }
+
+    /// Deliver a message the way maildir expects it:
+    /// Written to `tmp` first, then moved to `new`, so readers never see partial files.
+    async fn deliver(&self, message: Message) -> Result<PathBuf> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let unique = rand::thread_rng().gen::<[u8; 8]>().encode_hex::<String>();
+        let file_name = format!("{timestamp}.{unique}.fission-server");
+
+        let tmp_path = self.path.join("tmp").join(&file_name);
+        let new_path = self.path.join("new").join(&file_name);
+
+        tokio::fs::write(&tmp_path, message.formatted()).await?;
+        tokio::fs::rename(&tmp_path, &new_path).await?;
+
+        Ok(new_path)
+    }
+}
+
+#[async_trait]
+impl VerificationCodeSender for MaildirVerificationCodeSender {
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        let path = self
+            .deliver(self.composer.verification(email, verification, locale)?)
+            .await?;
+        tracing::info!(email, path = %path.display(), "Wrote verification email to maildir");
+        Ok(())
+    }
+
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        let path = self
+            .deliver(self.composer.email_changed_notice(old_email, new_email)?)
+            .await?;
+        tracing::info!(old_email, path = %path.display(), "Wrote email changed notice to maildir");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_templates::EmailTemplates;
+    use testresult::TestResult;
+
+    #[test_log::test(tokio::test)]
+    async fn test_writes_verification_email_to_maildir() -> TestResult {
+        let unique = rand::thread_rng().gen::<[u8; 8]>().encode_hex::<String>();
+        let path = std::env::temp_dir().join(format!("fission-maildir-{unique}"));
+
+        let sender = MaildirVerificationCodeSender::new(
+            path.clone(),
+            EmailComposer::new(&Default::default(), EmailTemplates::default())?,
+        )?;
+
+        sender
+            .send_verification(
+                "oedipa@trystero.com",
+                &Verification::Code("012345".to_string()),
+                None,
+            )
+            .await?;
+
+        let mut entries = std::fs::read_dir(path.join("new"))?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(std::fs::read_dir(path.join("tmp"))?.count(), 0);
+
+        let mail = std::fs::read_to_string(entries.remove(0).path())?;
+        assert!(mail.contains("To: oedipa@trystero.com"));
+        assert!(mail.contains("012345"));
+
+        std::fs::remove_dir_all(path)?;
+
+        Ok(())
+    }
+}
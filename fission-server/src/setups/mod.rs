@@ -7,12 +7,16 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use cid::{multihash::Code, Cid};
 use futures_util::Future;
+use std::time::Duration;
 use wnfs::common::{utils::CondSend, BlockStore, BlockStoreError};
 
 pub mod local;
+pub mod maildir;
 pub mod prod;
+pub mod smtp;
 #[cfg(any(feature = "test_utils", test))]
 pub mod test;
+pub mod webhook;
 
 /// This trait groups type parameters to the server's `AppState` struct.
 ///
@@ -23,6 +27,10 @@ pub trait ServerSetup: Clone + Send + Sync + 'static {
     type IpfsDatabase: IpfsDatabase;
     /// Which implementation to use to send verification codes
     type VerificationCodeSender: VerificationCodeSender;
+    /// Where to keep track of rate limited requests
+    type RateLimitStore: RateLimitStore;
+    /// Which implementation to use for outgoing HTTP requests, e.g. for verifying handles
+    type HttpClient: HttpClient;
 }
 
 /// Provides functionality for storing IPFS data.
@@ -55,11 +63,56 @@ pub trait IpfsDatabase: Clone + Send + Sync {
     fn block_get(&self, cid: &str) -> impl Future<Output = Result<Option<Bytes>>> + Send;
 }
 
+/// What's sent to an email address to verify it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// A 6-digit code that the user enters in the client
+    Code(String),
+    /// The URL of a single-use link that completes the verification when opened
+    Link(String),
+}
+
 /// The service that sends account verification codes
 #[async_trait]
 pub trait VerificationCodeSender: Clone + Send + Sync {
-    /// Send the code associated with the email
-    async fn send_code(&self, email: &str, code: &str) -> Result<()>;
+    /// Send the code or link that verifies the email.
+    ///
+    /// `locale` is the language tag the client requested, if any.
+    /// Senders fall back to their default locale if they don't have a translation for it.
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()>;
+
+    /// Notify an account's previous email address that it was changed to a new one.
+    ///
+    /// Senders that can't deliver notifications may skip this.
+    async fn send_email_changed_notice(&self, _old_email: &str, _new_email: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps track of requests for rate limiting.
+/// Abstracted away, so the counters can live in memory or be shared between server instances.
+#[async_trait]
+pub trait RateLimitStore: Clone + Send + Sync + Default {
+    /// Count a hit for given key in a fixed window of given length.
+    ///
+    /// Returns `None` if the hit is within `max_hits` for the current window.
+    /// Otherwise the hit isn't counted and the time until the window resets is returned.
+    async fn hit(&self, key: &str, max_hits: u32, window: Duration) -> Result<Option<Duration>>;
+}
+
+/// Fetches resources from other hosts over HTTP(S).
+/// Abstracted away, so tests don't need to reach out to the network.
+#[async_trait]
+pub trait HttpClient: Clone + Send + Sync + Default {
+    /// GET given URL and return its body as text.
+    ///
+    /// Returns `None` if the server responded with a non-success status.
+    async fn get_text(&self, url: &str) -> Result<Option<String>>;
 }
 
 impl<T: IpfsDatabase> IpfsDatabase for &T {
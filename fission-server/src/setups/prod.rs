@@ -1,24 +1,39 @@
 //! Production server setup code
 
 use crate::{
+    email_templates::{
+        email_changed_text, find_locale, normalize_locale, EmailComposer, EmailTemplates,
+        EMAIL_CHANGED_SUBJECT,
+    },
     // middleware::{client::metrics::Metrics, logging::Logger},
-    settings,
-    setups::{IpfsDatabase, ServerSetup, VerificationCodeSender},
+    rate_limit::InMemoryRateLimitStore,
+    settings::{self, EmailProvider, Settings},
+    setups::{
+        maildir::MaildirVerificationCodeSender, smtp::SmtpVerificationCodeSender,
+        webhook::WebhookVerificationCodeSender, HttpClient, IpfsDatabase, ServerSetup,
+        Verification, VerificationCodeSender,
+    },
 };
 use anyhow::{anyhow, bail, Context as _, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use cid::Cid;
 use mailgun_rs::{EmailAddress, Mailgun, MailgunRegion, Message};
-use reqwest::multipart::{Form, Part};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    multipart::{Form, Part},
+};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
     str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 use tracing::log;
-use url::Url;
+use url::{Host, Url};
 
 /// Production implementatoin of `ServerSetup`.
 /// Actually calls out to other HTTP services configured in `settings.toml`.
@@ -27,7 +42,140 @@ pub struct ProdSetup;
 
 impl ServerSetup for ProdSetup {
     type IpfsDatabase = IpfsHttpApiDatabase;
-    type VerificationCodeSender = EmailVerificationCodeSender;
+    type VerificationCodeSender = ProdVerificationCodeSender;
+    type RateLimitStore = InMemoryRateLimitStore;
+    type HttpClient = ReqwestHttpClient;
+}
+
+/// Responses from other hosts that are larger than this are rejected
+const MAX_RESPONSE_BYTES: usize = 16 * 1024;
+
+/// An `HttpClient` that reaches out to other hosts via reqwest.
+/// It doesn't follow redirects and gives up on slow hosts after a few seconds.
+///
+/// URLs come from users, so it refuses to connect to loopback, private and other
+/// non-public addresses, to keep them from probing the network the server runs in.
+#[derive(Clone, Debug)]
+pub struct ReqwestHttpClient {
+    client: reqwest::Client,
+}
+
+impl Default for ReqwestHttpClient {
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            // A proxy would connect to the address instead, skipping the checks below
+            .no_proxy()
+            .timeout(Duration::from_secs(5))
+            .dns_resolver(Arc::new(PublicAddressResolver))
+            .build()
+            .expect("Couldn't initialize the TLS backend for the HTTP client");
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl HttpClient for ReqwestHttpClient {
+    async fn get_text(&self, url: &str) -> Result<Option<String>> {
+        // IP addresses in URLs don't go through the resolver
+        let ip = match Url::parse(url)?.host() {
+            Some(Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+            _ => None,
+        };
+        if let Some(ip) = ip.filter(|ip| !is_public_ip(*ip)) {
+            bail!("Refusing to connect to non-public address {ip}");
+        }
+
+        let mut response = self.client.get(url).send().await?;
+
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_RESPONSE_BYTES {
+                bail!("Response from {url} is larger than {MAX_RESPONSE_BYTES} bytes");
+            }
+        }
+
+        Ok(Some(String::from_utf8(body)?))
+    }
+}
+
+/// Resolves host names via the system resolver, but only to publicly routable addresses.
+/// Checking the resolved addresses instead of the host name also covers DNS rebinding.
+#[derive(Debug, Clone, Copy)]
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_public(name.as_str().to_string()))
+    }
+}
+
+async fn resolve_public(host: String) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+        .await?
+        .filter(|addr| is_public_ip(addr.ip()))
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(anyhow!("{host} doesn't resolve to a public address").into());
+    }
+
+    Ok(Box::new(addrs.into_iter()))
+}
+
+/// Whether an address is publicly routable,
+/// i.e. not loopback, private, link-local, shared, multicast or otherwise reserved.
+///
+/// IPv6 addresses that embed an IPv4 address may be translated to any IPv4 address,
+/// so they're not considered public either.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Shared address space for carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (b & 0b1100_0000) == 64)
+                // IETF protocol assignments, 192.0.0.0/24
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (b & 0b1111_1110) == 18)
+                // Reserved for future use, 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(mapped));
+            }
+            let segments = ip.segments();
+            let first = segments[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // IPv4-compatible addresses, ::a.b.c.d
+                || segments[..6] == [0; 6]
+                // NAT64, 64:ff9b::/96 and 64:ff9b:1::/48
+                || (first == 0x64 && segments[1] == 0xff9b)
+                // 6to4, 2002::/16
+                || first == 0x2002
+                // Unique local addresses, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link-local addresses, fe80::/10
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
 }
 
 /// An implementation of `IpfsDatabase` which connects to a locally-running
@@ -198,6 +346,113 @@ impl IpfsDatabase for IpfsHttpApiDatabase {
     }
 }
 
+/// Sends verification emails with the provider that's selected in the settings,
+/// so switching providers doesn't require recompiling.
+#[derive(Debug, Clone)]
+pub enum ProdVerificationCodeSender {
+    /// Send emails via the Mailgun API
+    Mailgun(EmailVerificationCodeSender),
+    /// Send emails via an SMTP server
+    Smtp(SmtpVerificationCodeSender),
+    /// Write emails into a local maildir
+    Maildir(MaildirVerificationCodeSender),
+    /// POST emails to a webhook
+    Webhook(WebhookVerificationCodeSender),
+}
+
+impl ProdVerificationCodeSender {
+    /// Create the sender for the configured `email.provider`
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        Ok(match settings.email.provider {
+            EmailProvider::Mailgun => {
+                let mailgun = settings
+                    .mailgun
+                    .clone()
+                    .ok_or_else(|| anyhow!("Missing [mailgun] settings"))?;
+                Self::Mailgun(EmailVerificationCodeSender::new(mailgun))
+            }
+            EmailProvider::Smtp => {
+                let smtp = settings
+                    .smtp
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Missing [smtp] settings"))?;
+                Self::Smtp(SmtpVerificationCodeSender::new(
+                    smtp,
+                    email_composer(settings)?,
+                )?)
+            }
+            EmailProvider::Maildir => {
+                let maildir = settings
+                    .maildir
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Missing [maildir] settings"))?;
+                Self::Maildir(MaildirVerificationCodeSender::new(
+                    settings.relative_path(&maildir.path),
+                    email_composer(settings)?,
+                )?)
+            }
+            EmailProvider::Webhook => {
+                let webhook = settings
+                    .email_webhook
+                    .clone()
+                    .ok_or_else(|| anyhow!("Missing [email_webhook] settings"))?;
+                Self::Webhook(WebhookVerificationCodeSender::new(webhook))
+            }
+        })
+    }
+}
+
+fn email_composer(settings: &Settings) -> Result<EmailComposer> {
+    let templates_dir = settings
+        .email
+        .templates_dir
+        .as_ref()
+        .map(|dir| settings.relative_path(dir));
+
+    let templates = match &templates_dir {
+        Some(dir) => EmailTemplates::load(dir)?,
+        None => EmailTemplates::default(),
+    };
+
+    let mut composer = EmailComposer::new(&settings.email, templates.clone())?;
+
+    for (locale, localized) in &settings.email.locales {
+        let templates = match &templates_dir {
+            Some(dir) => templates.clone().overridden_from(&dir.join(locale))?,
+            None => templates.clone(),
+        };
+        composer = composer.with_locale(locale, localized.subject.clone(), templates);
+    }
+
+    Ok(composer)
+}
+
+#[async_trait]
+impl VerificationCodeSender for ProdVerificationCodeSender {
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        match self {
+            Self::Mailgun(sender) => sender.send_verification(email, verification, locale).await,
+            Self::Smtp(sender) => sender.send_verification(email, verification, locale).await,
+            Self::Maildir(sender) => sender.send_verification(email, verification, locale).await,
+            Self::Webhook(sender) => sender.send_verification(email, verification, locale).await,
+        }
+    }
+
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        match self {
+            Self::Mailgun(sender) => sender.send_email_changed_notice(old_email, new_email).await,
+            Self::Smtp(sender) => sender.send_email_changed_notice(old_email, new_email).await,
+            Self::Maildir(sender) => sender.send_email_changed_notice(old_email, new_email).await,
+            Self::Webhook(sender) => sender.send_email_changed_notice(old_email, new_email).await,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Sends verification codes over email
 pub struct EmailVerificationCodeSender {
@@ -206,7 +461,12 @@ pub struct EmailVerificationCodeSender {
 
 impl EmailVerificationCodeSender {
     /// Create a new EmailVerificationCodeSender
-    pub fn new(settings: settings::Mailgun) -> Self {
+    pub fn new(mut settings: settings::Mailgun) -> Self {
+        settings.locales = settings
+            .locales
+            .into_iter()
+            .map(|(locale, localized)| (normalize_locale(&locale), localized))
+            .collect();
         Self { settings }
     }
 
@@ -214,12 +474,12 @@ impl EmailVerificationCodeSender {
         EmailAddress::name_address(&self.settings.from_name, &self.settings.from_address)
     }
 
-    fn subject(&self) -> &str {
-        self.settings.subject.as_str()
-    }
-
-    fn template(&self) -> &str {
-        self.settings.template.as_str()
+    /// The subject & template for given locale, falling back to the default ones
+    fn subject_and_template(&self, locale: Option<&str>) -> (&str, &str) {
+        match find_locale(&self.settings.locales, locale) {
+            Some(localized) => (&localized.subject, &localized.template),
+            None => (&self.settings.subject, &self.settings.template),
+        }
     }
 
     fn api_key(&self) -> &str {
@@ -230,14 +490,26 @@ impl EmailVerificationCodeSender {
         self.settings.domain.as_str()
     }
 
-    fn message(&self, email: &str, code: &str) -> Message {
+    fn region(&self) -> MailgunRegion {
+        match self.settings.region {
+            settings::MailgunRegion::Us => MailgunRegion::US,
+            settings::MailgunRegion::Eu => MailgunRegion::EU,
+        }
+    }
+
+    fn message(&self, email: &str, verification: &Verification, locale: Option<&str>) -> Message {
         let delivery_address = EmailAddress::address(email);
-        let template_vars = HashMap::from_iter([("code".to_string(), code.to_string())]);
+        let template_var = match verification {
+            Verification::Code(code) => ("code".to_string(), code.to_string()),
+            Verification::Link(url) => ("link".to_string(), url.to_string()),
+        };
+        let template_vars = HashMap::from_iter([template_var]);
+        let (subject, template) = self.subject_and_template(locale);
 
         Message {
             to: vec![delivery_address],
-            subject: self.subject().to_string(),
-            template: self.template().to_string(),
+            subject: subject.to_string(),
+            template: template.to_string(),
             template_vars,
             ..Default::default()
         }
@@ -246,9 +518,15 @@ impl EmailVerificationCodeSender {
 
 #[async_trait]
 impl VerificationCodeSender for EmailVerificationCodeSender {
-    /// Sends the code to the user
-    async fn send_code(&self, email: &str, code: &str) -> Result<()> {
-        let message = self.message(email, code);
+    /// Sends the code or link to the user.
+    /// The template gets either a `code` or a `link` variable.
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        let message = self.message(email, verification, locale);
 
         log::debug!(
             "Sending verification email:\nTo: {}\nSubject: {}\nTemplate: {}\nTemplate Vars: {:?}",
@@ -264,7 +542,89 @@ impl VerificationCodeSender for EmailVerificationCodeSender {
             domain: self.domain().to_string(),
         };
 
-        client.async_send(MailgunRegion::US, &self.sender()).await?;
+        client.async_send(self.region(), &self.sender()).await?;
+
+        Ok(())
+    }
+
+    /// Lets the user know on their old address that their email address changed
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        let message = Message {
+            to: vec![EmailAddress::address(old_email)],
+            subject: EMAIL_CHANGED_SUBJECT.to_string(),
+            text: email_changed_text(new_email),
+            ..Default::default()
+        };
+
+        log::debug!(
+            "Sending email changed notice:\nTo: {}\nSubject: {}",
+            old_email,
+            message.subject
+        );
+
+        let client = Mailgun {
+            message,
+            api_key: self.api_key().to_string(),
+            domain: self.domain().to_string(),
+        };
+
+        client.async_send(self.region(), &self.sender()).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testresult::TestResult;
+
+    #[test]
+    fn test_is_public_ip() -> TestResult {
+        for ip in ["1.1.1.1", "203.0.114.1", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse()?), "{ip} should be public");
+        }
+
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "192.0.0.170",
+            "198.18.0.1",
+            "198.19.255.255",
+            "::127.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b:1::a00:1",
+            "2002:7f00:1::1",
+        ] {
+            assert!(!is_public_ip(ip.parse()?), "{ip} shouldn't be public");
+        }
+
+        Ok(())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_http_client_refuses_private_addresses() -> TestResult {
+        let client = ReqwestHttpClient::default();
+
+        for url in [
+            "http://127.0.0.1:3000/.well-known/did.txt",
+            "http://[::1]/.well-known/did.txt",
+            "http://localhost/.well-known/did.txt",
+        ] {
+            assert!(
+                client.get_text(url).await.is_err(),
+                "{url} should be refused"
+            );
+        }
 
         Ok(())
     }
@@ -0,0 +1,167 @@
+//! Sending verification emails via SMTP, for setups that don't use Mailgun
+
+use crate::{
+    email_templates::EmailComposer,
+    settings::{self, SmtpTls},
+    setups::{Verification, VerificationCodeSender},
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+
+/// Sends verification codes over email via an SMTP server
+#[derive(Debug, Clone)]
+pub struct SmtpVerificationCodeSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    composer: EmailComposer,
+}
+
+impl SmtpVerificationCodeSender {
+    /// Create a new SmtpVerificationCodeSender.
+    /// Doesn't connect to the server until the first email is sent.
+    pub fn new(settings: &settings::Smtp, composer: EmailComposer) -> Result<Self> {
+        let mut builder = match settings.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)
+            }
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        };
+
+        if let Some(port) = settings.port {
+            builder = builder.port(port);
+        }
+
+        match (&settings.username, &settings.password) {
+            (Some(username), Some(password)) => {
+                let credentials = Credentials::new(username.clone(), password.clone());
+                builder = builder.credentials(credentials);
+            }
+            (None, None) => {}
+            _ => bail!("SMTP username and password need to be configured together"),
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            composer,
+        })
+    }
+}
+
+#[async_trait]
+impl VerificationCodeSender for SmtpVerificationCodeSender {
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        let message = self.composer.verification(email, verification, locale)?;
+
+        tracing::debug!(email, "Sending verification email via SMTP");
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        let message = self.composer.email_changed_notice(old_email, new_email)?;
+
+        tracing::debug!(old_email, "Sending email changed notice via SMTP");
+
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email_templates::EmailTemplates;
+    use testresult::TestResult;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::oneshot,
+    };
+
+    /// Accepts a single SMTP connection and returns the data of the first mail sent over it
+    async fn smtp_sink() -> Result<(u16, oneshot::Receiver<String>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let (tx, rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut data = None::<String>;
+
+            writer.write_all(b"220 localhost ESMTP sink\r\n").await?;
+
+            while let Some(line) = lines.next_line().await? {
+                if let Some(data) = data.as_mut() {
+                    if line == "." {
+                        writer.write_all(b"250 OK\r\n").await?;
+                        let _ = tx.send(std::mem::take(data));
+                        break;
+                    }
+                    data.push_str(&line);
+                    data.push('\n');
+                } else if line.starts_with("DATA") {
+                    data = Some(String::new());
+                    writer
+                        .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                        .await?;
+                } else {
+                    writer.write_all(b"250 OK\r\n").await?;
+                }
+            }
+
+            anyhow::Ok(())
+        });
+
+        Ok((port, rx))
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_send_code_via_smtp() -> TestResult {
+        let (port, mail) = smtp_sink().await?;
+
+        let sender = SmtpVerificationCodeSender::new(
+            &settings::Smtp {
+                host: "127.0.0.1".to_string(),
+                port: Some(port),
+                tls: SmtpTls::None,
+                username: None,
+                password: None,
+            },
+            EmailComposer::new(&Default::default(), EmailTemplates::default())?,
+        )?;
+
+        sender
+            .send_verification(
+                "oedipa@trystero.com",
+                &Verification::Code("012345".to_string()),
+                None,
+            )
+            .await?;
+
+        let mail = mail.await?;
+
+        assert!(mail.contains("To: oedipa@trystero.com"));
+        assert!(mail.contains("Subject: Your Fission Verification Code"));
+        assert!(mail.contains("text/plain"));
+        assert!(mail.contains("text/html"));
+        assert!(mail.contains("012345"));
+
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
 //! Test server setup code
 
-use crate::setups::{IpfsDatabase, ServerSetup, VerificationCodeSender};
+use crate::{
+    rate_limit::InMemoryRateLimitStore,
+    setups::{HttpClient, IpfsDatabase, ServerSetup, Verification, VerificationCodeSender},
+};
 use anyhow::{bail, Context as _, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -21,6 +24,8 @@ pub struct TestSetup;
 impl ServerSetup for TestSetup {
     type IpfsDatabase = TestIpfsDatabase;
     type VerificationCodeSender = TestVerificationCodeSender;
+    type RateLimitStore = InMemoryRateLimitStore;
+    type HttpClient = TestHttpClient;
 }
 
 #[derive(Debug, Default, Clone)]
@@ -81,21 +86,80 @@ impl IpfsDatabase for TestIpfsDatabase {
 #[derive(Debug, Clone, Default)]
 pub struct TestVerificationCodeSender {
     emails: Arc<Mutex<Vec<(String, String)>>>,
+    links: Arc<Mutex<Vec<(String, String)>>>,
+    locales: Arc<Mutex<Vec<Option<String>>>>,
+    email_changed_notices: Arc<Mutex<Vec<(String, String)>>>,
 }
 
 impl TestVerificationCodeSender {
     pub fn get_emails(&self) -> Vec<(String, String)> {
         self.emails.lock().unwrap().clone()
     }
+
+    pub fn get_links(&self) -> Vec<(String, String)> {
+        self.links.lock().unwrap().clone()
+    }
+
+    pub fn get_locales(&self) -> Vec<Option<String>> {
+        self.locales.lock().unwrap().clone()
+    }
+
+    pub fn get_email_changed_notices(&self) -> Vec<(String, String)> {
+        self.email_changed_notices.lock().unwrap().clone()
+    }
 }
 
 #[async_trait]
 impl VerificationCodeSender for TestVerificationCodeSender {
-    async fn send_code(&self, email: &str, code: &str) -> Result<()> {
-        self.emails
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        let (sent, payload) = match verification {
+            Verification::Code(code) => (&self.emails, code),
+            Verification::Link(url) => (&self.links, url),
+        };
+        sent.lock()
+            .unwrap()
+            .push((email.to_string(), payload.to_string()));
+        self.locales
+            .lock()
+            .unwrap()
+            .push(locale.map(ToString::to_string));
+        Ok(())
+    }
+
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        self.email_changed_notices
             .lock()
             .unwrap()
-            .push((email.to_string(), code.to_string()));
+            .push((old_email.to_string(), new_email.to_string()));
         Ok(())
     }
 }
+
+/// Serves canned responses instead of reaching out to the network.
+/// URLs without a response behave like a 404.
+#[derive(Debug, Clone, Default)]
+pub struct TestHttpClient {
+    responses: Arc<DashMap<String, String>>,
+}
+
+impl TestHttpClient {
+    pub fn set_response(&self, url: impl Into<String>, body: impl Into<String>) {
+        self.responses.insert(url.into(), body.into());
+    }
+
+    pub fn remove_response(&self, url: &str) {
+        self.responses.remove(url);
+    }
+}
+
+#[async_trait]
+impl HttpClient for TestHttpClient {
+    async fn get_text(&self, url: &str) -> Result<Option<String>> {
+        Ok(self.responses.get(url).map(|body| body.clone()))
+    }
+}
@@ -0,0 +1,184 @@
+//! Handing verification emails off to a webhook, e.g. for custom email infrastructure
+
+use crate::{
+    settings,
+    setups::{Verification, VerificationCodeSender},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Give up on webhooks that don't respond within this time
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `VerificationCodeSender` that POSTs a JSON event per email to a configured URL.
+/// The webhook is responsible for rendering & delivering the email.
+#[derive(Debug, Clone)]
+pub struct WebhookVerificationCodeSender {
+    client: Client,
+    settings: settings::EmailWebhook,
+}
+
+/// The JSON body POSTed to the webhook
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WebhookEvent<'a> {
+    /// Send a verification code to `email`
+    VerificationCode {
+        /// The recipient
+        email: &'a str,
+        /// The 6-digit code
+        code: &'a str,
+        /// The language the email was requested in, if any
+        #[serde(skip_serializing_if = "Option::is_none")]
+        locale: Option<&'a str>,
+    },
+    /// Send a single-use verification link to `email`
+    VerificationLink {
+        /// The recipient
+        email: &'a str,
+        /// The link's URL
+        link: &'a str,
+        /// The language the email was requested in, if any
+        #[serde(skip_serializing_if = "Option::is_none")]
+        locale: Option<&'a str>,
+    },
+    /// Notify `oldEmail` that the account's email address changed to `newEmail`
+    #[serde(rename_all = "camelCase")]
+    EmailChanged {
+        /// The recipient, the account's previous address
+        old_email: &'a str,
+        /// The account's new address
+        new_email: &'a str,
+    },
+}
+
+impl WebhookVerificationCodeSender {
+    /// Create a new WebhookVerificationCodeSender
+    pub fn new(settings: settings::EmailWebhook) -> Self {
+        let client = Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .expect("Couldn't initialize the TLS backend for the webhook client");
+        Self { client, settings }
+    }
+
+    async fn post(&self, event: &WebhookEvent<'_>) -> Result<()> {
+        let mut request = self.client.post(&self.settings.url).json(event);
+
+        if let Some(secret) = &self.settings.secret {
+            request = request.bearer_auth(secret);
+        }
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VerificationCodeSender for WebhookVerificationCodeSender {
+    async fn send_verification(
+        &self,
+        email: &str,
+        verification: &Verification,
+        locale: Option<&str>,
+    ) -> Result<()> {
+        let event = match verification {
+            Verification::Code(code) => WebhookEvent::VerificationCode {
+                email,
+                code,
+                locale,
+            },
+            Verification::Link(link) => WebhookEvent::VerificationLink {
+                email,
+                link,
+                locale,
+            },
+        };
+
+        tracing::debug!(
+            email,
+            url = self.settings.url,
+            "Posting verification email to webhook"
+        );
+
+        self.post(&event).await
+    }
+
+    async fn send_email_changed_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        tracing::debug!(
+            old_email,
+            url = self.settings.url,
+            "Posting email changed notice to webhook"
+        );
+
+        self.post(&WebhookEvent::EmailChanged {
+            old_email,
+            new_email,
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::HeaderMap, routing::post, Json, Router};
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+    use testresult::TestResult;
+    use tokio::sync::mpsc;
+
+    #[test_log::test(tokio::test)]
+    async fn test_posts_verification_code_to_webhook() -> TestResult {
+        type Calls = mpsc::UnboundedSender<(HeaderMap, Value)>;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let app = Router::new()
+            .route(
+                "/emails",
+                post(
+                    |State(tx): State<Calls>,
+                     headers: HeaderMap,
+                     Json(body): Json<Value>| async move {
+                        let _ = tx.send((headers, body));
+                    },
+                ),
+            )
+            .with_state(tx);
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(axum::Server::from_tcp(listener)?.serve(app.into_make_service()));
+
+        let sender = WebhookVerificationCodeSender::new(settings::EmailWebhook {
+            url: format!("http://{addr}/emails"),
+            secret: Some("hunter2".to_string()),
+        });
+
+        sender
+            .send_verification(
+                "oedipa@trystero.com",
+                &Verification::Code("012345".to_string()),
+                None,
+            )
+            .await?;
+
+        let (headers, body) = rx.recv().await.ok_or("Webhook wasn't called")?;
+
+        assert_eq!(headers["authorization"], "Bearer hunter2");
+        assert_eq!(
+            body,
+            json!({
+                "type": "verificationCode",
+                "email": "oedipa@trystero.com",
+                "code": "012345",
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@
 use anyhow::{anyhow, Result};
 use axum::Router;
 use bytes::Bytes;
-use http::{Method, Request, StatusCode, Uri};
+use http::{Method, Request, Response, StatusCode, Uri};
 use hyper::Body;
 use mime::{Mime, APPLICATION_JSON};
 use rs_ucan::{ucan::Ucan, DefaultFact};
@@ -70,6 +70,16 @@ impl<F: Clone + DeserializeOwned> RouteBuilder<F> {
         Ok(self)
     }
 
+    pub fn with_body(mut self, mime: Mime, body: impl Into<Body>) -> Self {
+        self.body = Some((mime, body.into()));
+        self
+    }
+
+    pub async fn into_response(mut self) -> Result<Response<Body>> {
+        let request = self.build_request()?;
+        Ok(self.app.oneshot(request).await?)
+    }
+
     pub async fn into_raw_response(mut self) -> Result<(StatusCode, Bytes)> {
         let request = self.build_request()?;
         let response = self.app.oneshot(request).await?;
@@ -0,0 +1,117 @@
+//! Checking usernames against the configured denylist
+
+use crate::settings;
+use anyhow::{Context, Result};
+use fission_core::username::Username;
+use regex::RegexSet;
+
+/// Usernames that can't be registered.
+///
+/// This includes the built-in reserved usernames from `fission_core`
+/// as well as the names and patterns configured in `settings.toml`.
+#[derive(Debug, Clone)]
+pub struct UsernameDenylist {
+    names: Vec<String>,
+    patterns: RegexSet,
+}
+
+impl Default for UsernameDenylist {
+    fn default() -> Self {
+        Self {
+            names: Vec::new(),
+            patterns: RegexSet::empty(),
+        }
+    }
+}
+
+impl UsernameDenylist {
+    /// Compile the denylist from settings.
+    ///
+    /// Names are normalized the same way as usernames, i.e. lowercased and turned into punycode.
+    /// Fails if any of the names aren't valid usernames or any of the patterns aren't valid
+    /// regular expressions.
+    pub fn new(settings: &settings::Usernames) -> Result<Self> {
+        let names = settings
+            .denylist
+            .iter()
+            .map(|name| {
+                Username::from_unicode(name)
+                    .map(|username| username.as_str().to_string())
+                    .with_context(|| format!("Invalid username on the denylist: {name:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Anchor the patterns, so they always need to match the whole username
+        let patterns = RegexSet::new(
+            settings
+                .denylist_patterns
+                .iter()
+                .map(|pattern| format!("^(?:{pattern})$")),
+        )
+        .context("Invalid username denylist pattern")?;
+
+        Ok(Self { names, patterns })
+    }
+
+    /// Whether given username is reserved or denied by configuration
+    pub fn is_denied(&self, username: &Username) -> bool {
+        username.is_reserved()
+            || self.names.iter().any(|name| name == username.as_str())
+            || self.patterns.is_match(username.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testresult::TestResult;
+
+    #[test]
+    fn test_denylist_names_and_patterns() -> TestResult {
+        let denylist = UsernameDenylist::new(&settings::Usernames {
+            denylist: vec!["oedipa".to_string()],
+            denylist_patterns: vec!["dmarc.*".to_string()],
+        })?;
+
+        assert!(denylist.is_denied(&"oedipa".parse()?));
+        assert!(denylist.is_denied(&"dmarc-reports".parse()?));
+        assert!(denylist.is_denied(&"admin".parse()?));
+        assert!(!denylist.is_denied(&"mucho-dmarc".parse()?));
+        assert!(!denylist.is_denied(&"oedipa2".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_denylist_names_normalized() -> TestResult {
+        let denylist = UsernameDenylist::new(&settings::Usernames {
+            denylist: vec!["Oedipa".to_string(), "tristerö".to_string()],
+            denylist_patterns: Vec::new(),
+        })?;
+
+        assert!(denylist.is_denied(&"oedipa".parse()?));
+        assert!(denylist.is_denied(&Username::from_unicode("tristerö")?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_denylist_invalid_name_err() {
+        let result = UsernameDenylist::new(&settings::Usernames {
+            denylist: vec!["not a username".to_string()],
+            denylist_patterns: Vec::new(),
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_denylist_invalid_pattern_err() {
+        let result = UsernameDenylist::new(&settings::Usernames {
+            denylist: Vec::new(),
+            denylist_patterns: vec!["(unclosed".to_string()],
+        });
+
+        assert!(result.is_err());
+    }
+}